@@ -0,0 +1,149 @@
+use std::rc::Rc;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::RgbaImage;
+
+use engine::graphics::{gpu::SpriteInstance, texture::CpuTexture};
+use engine::logic::{
+    collision::{self, Contacts, RectContact},
+    geom::{Plane, Pos3, Sphere, Vec3},
+    state::{EntityType, GameState},
+    tiles::{Tile, Tilemap, Tileset, World},
+    types::{Marble, Rect, Vec2i, Wall},
+};
+
+// Cheap, repeatable scatter so every run benchmarks the same layout
+fn scatter(i: usize, range: i32) -> i32 {
+    ((i * 7919 + 104_729) % range as usize) as i32
+}
+
+fn texture(w: u32, h: u32) -> Rc<CpuTexture> {
+    Rc::new(CpuTexture::new(RgbaImage::new(w, h)))
+}
+
+// A square map `side` tiles across with every other tile solid
+fn tilemap(side: usize) -> Tilemap {
+    let tileset = Rc::new(Tileset::new(
+        vec![Tile::EMPTY, Tile::SOLID],
+        texture(32, 16),
+    ));
+    let map = (0..side * side).map(|i| (i + i / side) % 2).collect();
+    Tilemap::new(Vec2i(0, 0), (side, side), &tileset, map)
+}
+
+fn player_contacts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gather_player_contacts");
+    for &n in [10, 100, 1000].iter() {
+        let tex = texture(16, 16);
+        let mut state = GameState::new(0, Rc::clone(&tex));
+        for i in 0..n {
+            let pos = Vec2i(scatter(i, 320), scatter(i * 3, 240));
+            let anim = state.entity_anims.standing.start();
+            state.spawn(EntityType::Enemy, pos, (16, 16), Rc::clone(&tex), anim);
+        }
+        let mut contacts: Vec<RectContact> = vec![];
+        group.bench_with_input(BenchmarkId::from_parameter(n), &state, |b, state| {
+            b.iter(|| collision::gather_player_contacts(black_box(state), &mut contacts))
+        });
+    }
+    group.finish();
+}
+
+fn world_solids(c: &mut Criterion) {
+    let mut group = c.benchmark_group("world_solids");
+    for &side in [16, 64, 256].iter() {
+        // One chunk per 16x16 tiles, all in a row
+        let chunks = (side / 16) * (side / 16);
+        let world = World::new(
+            (0..chunks)
+                .map(|i| {
+                    let mut map = tilemap(16);
+                    map.position = Vec2i(i as i32 * 256, 0);
+                    map
+                })
+                .collect(),
+        );
+        let player = Rect {
+            x: 100,
+            y: 100,
+            w: 16,
+            h: 24,
+        };
+        let mut solids = vec![];
+        group.bench_with_input(BenchmarkId::from_parameter(side * side), &world, |b, world| {
+            b.iter(|| {
+                solids.clear();
+                collision::world_solids(black_box(world), player, &mut solids)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn marbles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("marble_update");
+    for &n in [10, 100, 500].iter() {
+        let marbles: Vec<Marble> = (0..n)
+            .map(|i| Marble {
+                body: Sphere {
+                    c: Pos3::new(
+                        scatter(i, 100) as f32 / 10.0 - 5.0,
+                        scatter(i * 3, 50) as f32 / 10.0,
+                        scatter(i * 7, 100) as f32 / 10.0 - 5.0,
+                    ),
+                    r: 0.3,
+                },
+                velocity: Vec3::new(0.0, -1.0, 0.0),
+            })
+            .collect();
+        let walls = vec![Wall {
+            body: Plane {
+                n: Vec3::new(0.0, 1.0, 0.0),
+                d: 0.0,
+            },
+            destructible: false,
+            destroyed: false,
+        }];
+        let mut contacts = Contacts::new();
+        group.bench_with_input(BenchmarkId::from_parameter(n), &marbles, |b, marbles| {
+            b.iter(|| {
+                let mut walls = walls.clone();
+                let mut marbles = marbles.clone();
+                collision::update(&mut walls, &mut marbles, &mut contacts)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn tilemap_instances(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_tilemap_instances");
+    for &side in [16, 64, 256].iter() {
+        let mut map = tilemap(side);
+        let mut instances: Vec<SpriteInstance> = vec![];
+        // About a screen's worth of the map
+        let view = Rect {
+            x: 0,
+            y: 0,
+            w: 320,
+            h: 240,
+        };
+        // A tile changes every frame, so everything in view is rebuilt
+        group.bench_function(BenchmarkId::new("changing", side * side), |b| {
+            let mut id = 0;
+            b.iter(|| {
+                id = 1 - id;
+                map.set_tile_id(Vec2i(0, 0), id);
+                map.update_tilemap_instances(view, &mut instances)
+            })
+        });
+        // Nothing changes, so the cached instances stand
+        group.bench_function(BenchmarkId::new("static", side * side), |b| {
+            b.iter(|| map.update_tilemap_instances(view, &mut instances))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, player_contacts, world_solids, marbles, tilemap_instances);
+criterion_main!(benches);
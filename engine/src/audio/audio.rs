@@ -1,19 +1,251 @@
-use rodio::{buffer::SamplesBuffer, OutputStream, OutputStreamHandle};
+use rodio::{buffer::SamplesBuffer, Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    time::{Duration, Instant},
+};
 use synthrs::{music, synthesizer::make_samples};
 
+use crate::graphics::resources::AssetError;
+
+// Default minimum gap between two plays of the same throttled sound
+const SOUND_COOLDOWN: Duration = Duration::from_millis(50);
+// How much one press of the volume keys changes the volume
+pub const VOLUME_STEP: f32 = 0.1;
+
 pub struct SoundChannels {
-    pub stream: OutputStream,
-    pub stream_handle: OutputStreamHandle,
+    // None if there's no audio device, in which case everything is silent
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    // How soon the same throttled sound may play again
+    pub cooldown: Duration,
+    last_played: HashMap<&'static str, Instant>,
+    // Master volume in 0..=1, applied to everything played from now on
+    volume: f32,
+    muted: bool,
+    // While the game is paused in the background: music holds its place
+    // and new sounds are dropped
+    suspended: bool,
+    // Looping background music, kept so volume changes reach it right away
+    music: Option<Sink>,
 }
 
 impl SoundChannels {
+    /// Open the default audio device.  Without one (headless machines, CI)
+    /// this logs a warning and returns channels that silently drop every
+    /// sound instead of failing.
     pub fn new() -> SoundChannels {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                log::warn!("No audio device, sound is disabled: {}", e);
+                None
+            }
+        };
         SoundChannels {
-            stream,
-            stream_handle,
+            output,
+            cooldown: SOUND_COOLDOWN,
+            last_played: HashMap::new(),
+            volume: 1.0,
+            muted: false,
+            suspended: false,
+            music: None,
+        }
+    }
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.max(0.0).min(1.0);
+        self.update_music_volume();
+    }
+    pub fn volume_up(&mut self) {
+        self.set_volume(self.volume + VOLUME_STEP);
+    }
+    pub fn volume_down(&mut self) {
+        self.set_volume(self.volume - VOLUME_STEP);
+    }
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+    /// Muting silences the music at once and drops new sounds until unmuted.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update_music_volume();
+    }
+    pub fn toggle_mute(&mut self) {
+        self.set_muted(!self.muted);
+    }
+    pub fn suspended(&self) -> bool {
+        self.suspended
+    }
+    /// Suspending pauses the music where it is and drops new sounds until
+    /// resumed, e.g. while the window is in the background.  Unlike muting
+    /// it isn't a player setting.
+    pub fn set_suspended(&mut self, suspended: bool) {
+        self.suspended = suspended;
+        if let Some(music) = &self.music {
+            if suspended {
+                music.pause();
+            } else {
+                music.play();
+            }
         }
     }
+    fn effective_volume(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.volume
+        }
+    }
+    fn update_music_volume(&mut self) {
+        let volume = self.effective_volume();
+        if let Some(music) = &self.music {
+            music.set_volume(volume);
+        }
+    }
+    /// Loop `sound` in the background, replacing any music already playing.
+    pub fn play_music(&mut self, sound: &SoundBuffer) {
+        self.stop_music();
+        if let Some((_stream, handle)) = &self.output {
+            match Sink::try_new(handle) {
+                Ok(sink) => {
+                    sink.set_volume(self.effective_volume());
+                    sink.append(sound.source().repeat_infinite());
+                    if self.suspended {
+                        sink.pause();
+                    }
+                    self.music = Some(sink);
+                }
+                Err(e) => log::warn!("Couldn't play music: {}", e),
+            }
+        }
+    }
+    pub fn stop_music(&mut self) {
+        if let Some(music) = self.music.take() {
+            music.stop();
+        }
+    }
+    pub fn available(&self) -> bool {
+        self.output.is_some()
+    }
+    pub fn play_raw<S>(&self, source: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        if self.muted || self.suspended {
+            return;
+        }
+        if let Some((_stream, handle)) = &self.output {
+            // A sound that fails to play isn't worth crashing over
+            if let Err(e) = handle.play_raw(source.amplify(self.volume)) {
+                log::warn!("Couldn't play sound: {}", e);
+            }
+        }
+    }
+    /// Is sound `key` off cooldown?  If so, starts a new cooldown for it.
+    fn ready(&mut self, key: &'static str) -> bool {
+        let now = Instant::now();
+        match self.last_played.get(key) {
+            Some(last) if now.duration_since(*last) < self.cooldown => false,
+            _ => {
+                self.last_played.insert(key, now);
+                true
+            }
+        }
+    }
+    /// Play `source` unless something else played as `key` within the
+    /// last `cooldown`.  Use this for effects that many entities can set
+    /// off on the same frame (landings, jumps) so they don't pile up.
+    pub fn play_raw_throttled<S>(&mut self, key: &'static str, source: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        if self.ready(key) {
+            self.play_raw(source);
+        }
+    }
+    pub fn play_throttled(&mut self, key: &'static str, sound: &SoundBuffer) {
+        if self.ready(key) {
+            self.play(sound);
+        }
+    }
+    pub fn play(&self, sound: &SoundBuffer) {
+        self.play_raw(sound.source());
+    }
+    /// Play `sound` balanced between the speakers: `pan` runs from -1.0
+    /// (all left) through 0.0 (centered) to 1.0 (all right).  For an entity
+    /// on screen, `(entity.x - camera.x) / game_size.width * 2.0 - 1.0` works.
+    pub fn play_panned(&self, sound: &SoundBuffer, pan: f32) {
+        self.play_raw(sound.panned(pan));
+    }
+}
+
+impl Drop for SoundChannels {
+    // Fields drop in order, which would close the output stream out from
+    // under the music; stop the music first so it ends cleanly
+    fn drop(&mut self) {
+        self.stop_music();
+    }
+}
+
+/// A decoded sound file, kept in memory so it can be played any number of times.
+pub struct SoundBuffer {
+    channels: u16,
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl SoundBuffer {
+    /// Decode a sound file.  Anything rodio can decode works, including WAV
+    /// and OGG Vorbis.
+    pub fn with_file(path: &Path) -> Result<Self, AssetError> {
+        let file = File::open(path).map_err(|source| AssetError::Missing {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let decoder = Decoder::new(BufReader::new(file)).map_err(|e| AssetError::Bad {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        Ok(Self {
+            channels,
+            sample_rate,
+            samples: decoder.convert_samples().collect(),
+        })
+    }
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    /// A fresh source to hand to rodio.
+    pub fn source(&self) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(self.channels, self.sample_rate, self.samples.clone())
+    }
+    /// A stereo copy of this sound panned by `pan` (see
+    /// `SoundChannels::play_panned`).  Sounds with more than two channels
+    /// aren't panned.
+    pub fn panned(&self, pan: f32) -> SamplesBuffer<f32> {
+        // Equal-power panning keeps the overall loudness steady across the sweep
+        let angle = (pan.max(-1.0).min(1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+        let (left, right) = (angle.cos(), angle.sin());
+        let samples = match self.channels {
+            1 => self.samples.iter().flat_map(|s| vec![s * left, s * right]).collect(),
+            2 => self
+                .samples
+                .chunks_exact(2)
+                .flat_map(|lr| vec![lr[0] * left, lr[1] * right])
+                .collect(),
+            _ => return self.source(),
+        };
+        SamplesBuffer::new(2, self.sample_rate, samples)
+    }
 }
 
 pub struct Note {
@@ -35,33 +267,194 @@ impl Note {
     }
 }
 
+/// Notes that sound at the same time.
+pub struct Chord {
+    notes: Vec<Note>,
+}
+
+impl Chord {
+    pub fn new(notes: Vec<Note>) -> Self {
+        Chord { notes }
+    }
+    pub fn beats(&self) -> u8 {
+        self.notes.iter().map(|n| n.beats).max().unwrap_or(0)
+    }
+}
+
+/// Attack/decay/sustain/release volume envelope applied to each note.
+/// Times are in seconds, `sustain` is a level in 0..=1.  The release happens
+/// at the end of the note's own duration rather than after it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Envelope {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Envelope {
+    /// A short fade in and out that takes the click off of beeps without
+    /// audibly changing them.
+    pub const SOFT: Envelope = Envelope {
+        attack: 0.005,
+        decay: 0.05,
+        sustain: 0.8,
+        release: 0.02,
+    };
+
+    /// Volume (0..=1) at `t` seconds into a note lasting `len` seconds.
+    pub fn level(&self, t: f64, len: f64) -> f64 {
+        let held = if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        };
+        let until_end = len - t;
+        if until_end < self.release {
+            held * (until_end / self.release).max(0.0)
+        } else {
+            held
+        }
+    }
+
+    fn apply(&self, samples: &mut [f64], sample_rate: u32) {
+        let len = samples.len() as f64 / sample_rate as f64;
+        for (i, s) in samples.iter_mut().enumerate() {
+            *s *= self.level(i as f64 / sample_rate as f64, len);
+        }
+    }
+}
+
+impl Default for Envelope {
+    /// Full volume from the first sample to the last, i.e. no shaping at all.
+    fn default() -> Self {
+        Self {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.0,
+        }
+    }
+}
+
+pub const SAMPLE_RATE: u32 = 44_100;
+// Synthesized waves peak at 1.0, which is painfully loud
+pub const DEFAULT_GAIN: f32 = 0.1;
+
+/// Mono, 44.1kHz samples for `notes`; see `generate_samples_with`.
 pub fn generate_samples<F, G>(notes: Vec<Note>, bpm: f32, waveform: F) -> SamplesBuffer<f32>
+where
+    F: Fn(f64) -> G,
+    G: Fn(f64) -> f64,
+{
+    generate_samples_with(
+        notes,
+        bpm,
+        waveform,
+        1,
+        SAMPLE_RATE,
+        DEFAULT_GAIN,
+        Envelope::default(),
+    )
+}
+
+/// Synthesize `notes` one after another at `bpm`.  With more than one
+/// channel, every channel gets the same signal; each note is shaped by
+/// `envelope` and each sample is scaled by `gain`.
+pub fn generate_samples_with<F, G>(
+    notes: Vec<Note>,
+    bpm: f32,
+    waveform: F,
+    channels: u16,
+    sample_rate: u32,
+    gain: f32,
+    envelope: Envelope,
+) -> SamplesBuffer<f32>
+where
+    F: Fn(f64) -> G,
+    G: Fn(f64) -> f64,
+{
+    generate_chord_samples_with(
+        notes.into_iter().map(|n| Chord::new(vec![n])).collect(),
+        bpm,
+        waveform,
+        channels,
+        sample_rate,
+        gain,
+        envelope,
+    )
+}
+
+/// Mono, 44.1kHz samples for `chords`; see `generate_chord_samples_with`.
+pub fn generate_chord_samples<F, G>(chords: Vec<Chord>, bpm: f32, waveform: F) -> SamplesBuffer<f32>
+where
+    F: Fn(f64) -> G,
+    G: Fn(f64) -> f64,
+{
+    generate_chord_samples_with(
+        chords,
+        bpm,
+        waveform,
+        1,
+        SAMPLE_RATE,
+        DEFAULT_GAIN,
+        Envelope::default(),
+    )
+}
+
+/// Like `generate_samples_with`, but each step is a chord whose notes are
+/// mixed together.  A chord lasts as long as its longest note.
+pub fn generate_chord_samples_with<F, G>(
+    chords: Vec<Chord>,
+    bpm: f32,
+    waveform: F,
+    channels: u16,
+    sample_rate: u32,
+    gain: f32,
+    envelope: Envelope,
+) -> SamplesBuffer<f32>
 where
     F: Fn(f64) -> G,
     G: Fn(f64) -> f64,
 {
     let multiplier = 60.0 / bpm;
-    let num_samples = (notes
+    let num_samples = (chords
         .iter()
-        .map(|x| x.beats as usize)
+        .map(|x| x.beats() as usize)
         .reduce(|x, y| x + y)
         .unwrap_or(0) as f32
-        * multiplier)
+        * multiplier
+        * sample_rate as f32)
         .floor() as usize;
     let mut samples: Vec<f64> = Vec::with_capacity(num_samples);
-    for note in notes {
-        samples.append(&mut make_samples(
-            (note.beats as f32 * multiplier) as f64,
-            44_100,
-            waveform(note.pitch()),
-        ));
+    for chord in chords {
+        let mut chord_samples: Vec<f64> = vec![];
+        for note in chord.notes.iter() {
+            let mut note_samples = make_samples(
+                (note.beats as f32 * multiplier) as f64,
+                sample_rate as usize,
+                waveform(note.pitch()),
+            );
+            envelope.apply(&mut note_samples, sample_rate);
+            if chord_samples.len() < note_samples.len() {
+                chord_samples.resize(note_samples.len(), 0.0);
+            }
+            for (c, n) in chord_samples.iter_mut().zip(note_samples.iter()) {
+                *c += n;
+            }
+        }
+        // Scale the sum back down so big chords don't clip
+        let voices = chord.notes.len().max(1) as f64;
+        samples.extend(chord_samples.iter().map(|s| s / voices));
     }
     SamplesBuffer::new(
-        1,
-        44_100,
+        channels,
+        sample_rate,
         samples
             .iter()
-            .map(|x| (*x as f32) / 10.0)
+            .flat_map(|x| std::iter::repeat((*x as f32) * gain).take(channels as usize))
             .collect::<Vec<f32>>(),
     )
 }
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use winit::dpi::LogicalSize;
+use winit::window::{Fullscreen, WindowBuilder};
+
+use crate::graphics::graphics::GraphicsMethod;
+
+pub const SETTINGS_FILE: &str = "settings.cfg";
+/// Environment variable that moves every game's saves under one directory
+/// (each game still gets its own folder in it).
+pub const SAVE_DIR_VAR: &str = "CS181_SAVE_DIR";
+
+/// Where the game called `title` keeps its saves: the `--save-dir` option
+/// if given, else `$CS181_SAVE_DIR/<title>`, else the platform's per-user
+/// data directory for `title` (e.g. `~/.local/share/<title>` on Linux).
+/// Games sharing a working directory no longer overwrite each other.
+pub fn save_dir(title: &str, matches: &clap::ArgMatches) -> PathBuf {
+    if let Some(dir) = matches.value_of("save-dir") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os(SAVE_DIR_VAR) {
+        return PathBuf::from(dir).join(title);
+    }
+    match ProjectDirs::from("", "", title) {
+        Some(dirs) => dirs.data_dir().to_path_buf(),
+        // No home directory to speak of; fall back to the working directory
+        None => PathBuf::from("saves").join(title),
+    }
+}
+
+/// Environment variable naming the directory assets are loaded from,
+/// overriding the search `content_root` does.
+pub const CONTENT_DIR_VAR: &str = "CS181_CONTENT_DIR";
+
+/// Where to load assets from, so the game runs from any working directory:
+/// `$CS181_CONTENT_DIR` if set, else the nearest directory called `name`
+/// next to the executable or in one of its parent directories (which finds
+/// it from `target/debug` too), else `name` in the working directory.
+/// Paths found this way are absolute, so errors about missing files say
+/// exactly where they looked.
+pub fn content_root(name: &str) -> PathBuf {
+    if let Some(dir) = env::var_os(CONTENT_DIR_VAR) {
+        return absolute(PathBuf::from(dir));
+    }
+    if let Ok(exe) = env::current_exe() {
+        if let Some(dir) = exe.ancestors().skip(1).map(|d| d.join(name)).find(|d| d.is_dir()) {
+            return dir;
+        }
+    }
+    absolute(PathBuf::from(name))
+}
+
+fn absolute(path: PathBuf) -> PathBuf {
+    if path.is_absolute() {
+        return path;
+    }
+    match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path,
+    }
+}
+
+/// Player settings that should survive between runs.  Stored as plain
+/// `key = value` lines; bindings are `bind.<action> = <key name>`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub backend: GraphicsMethod,
+    // Master volume in 0..=1
+    pub volume: f32,
+    pub muted: bool,
+    // Window size as a multiple of the game's resolution
+    pub scale: f32,
+    pub fullscreen: bool,
+    // Seconds between autosaves; 0 turns autosave off
+    pub autosave: f32,
+    // Stop the simulation and sound while the window is in the background
+    pub pause_unfocused: bool,
+    // Most frames drawn a second; 0 leaves it up to vsync
+    pub max_fps: f32,
+    // Action name (e.g. "jump") to key name (e.g. "Space")
+    pub bindings: BTreeMap<String, String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let bindings = [
+            ("left", "Left"),
+            ("right", "Right"),
+            ("up", "Up"),
+            ("down", "Down"),
+            ("jump", "Space"),
+            ("dash", "LShift"),
+            ("pause", "Escape"),
+        ]
+        .iter()
+        .map(|(action, key)| (action.to_string(), key.to_string()))
+        .collect();
+        Self {
+            backend: GraphicsMethod::WGPUDefault,
+            volume: 1.0,
+            muted: false,
+            scale: 1.0,
+            fullscreen: false,
+            autosave: 60.0,
+            pause_unfocused: true,
+            max_fps: 0.0,
+            bindings,
+        }
+    }
+}
+
+impl Settings {
+    /// The settings the game called `title` starts with: its settings file
+    /// in `save_dir`, with command line options (see `args`) over the top.
+    /// Returns the file's path too, for saving changes to.
+    pub fn startup(title: &str, matches: &clap::ArgMatches) -> (Self, PathBuf) {
+        let path = save_dir(title, matches).join(SETTINGS_FILE);
+        let mut settings = Self::load(&path);
+        settings.apply_args(matches);
+        (settings, path)
+    }
+
+    /// Read settings from `path`.  A missing file gives the defaults, and
+    /// any line that can't be understood is skipped (keeping its default)
+    /// so a partly corrupt file doesn't lose everything else.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let mut settings = Self::default();
+        let text = match fs::read_to_string(path.as_ref()) {
+            Ok(text) => text,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Couldn't read {}: {}", path.as_ref().display(), e);
+                }
+                return settings;
+            }
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut kv = line.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => {
+                    log::warn!("Ignoring malformed setting {:?}", line);
+                    continue;
+                }
+            };
+            if !settings.set(key, value) {
+                log::warn!("Ignoring bad setting {} = {:?}", key, value);
+            }
+        }
+        settings
+    }
+
+    /// Change one setting from its textual form.  Returns false (changing
+    /// nothing) if `key` is unknown or `value` doesn't parse.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "backend" => match value.parse() {
+                Ok(backend) => self.backend = backend,
+                Err(_) => return false,
+            },
+            "volume" => match value.parse::<f32>() {
+                Ok(volume) if (0.0..=1.0).contains(&volume) => self.volume = volume,
+                _ => return false,
+            },
+            "muted" => match value.parse() {
+                Ok(muted) => self.muted = muted,
+                Err(_) => return false,
+            },
+            "scale" => match value.parse::<f32>() {
+                Ok(scale) if scale > 0.0 => self.scale = scale,
+                _ => return false,
+            },
+            "fullscreen" => match value.parse() {
+                Ok(fullscreen) => self.fullscreen = fullscreen,
+                Err(_) => return false,
+            },
+            "autosave" => match value.parse::<f32>() {
+                Ok(autosave) if autosave >= 0.0 => self.autosave = autosave,
+                _ => return false,
+            },
+            "pause_unfocused" => match value.parse() {
+                Ok(pause) => self.pause_unfocused = pause,
+                Err(_) => return false,
+            },
+            "max_fps" => match value.parse::<f32>() {
+                Ok(max_fps) if max_fps >= 0.0 => self.max_fps = max_fps,
+                _ => return false,
+            },
+            _ if key.starts_with("bind.") && !value.is_empty() => {
+                self.bindings
+                    .insert(key["bind.".len()..].to_string(), value.to_string());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Save to `path`, making its directory if need be.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        text.push_str(&format!("backend = {}\n", self.backend.name()));
+        text.push_str(&format!("volume = {}\n", self.volume));
+        text.push_str(&format!("muted = {}\n", self.muted));
+        text.push_str(&format!("scale = {}\n", self.scale));
+        text.push_str(&format!("fullscreen = {}\n", self.fullscreen));
+        text.push_str(&format!("autosave = {}\n", self.autosave));
+        text.push_str(&format!("pause_unfocused = {}\n", self.pause_unfocused));
+        text.push_str(&format!("max_fps = {}\n", self.max_fps));
+        for (action, key) in self.bindings.iter() {
+            text.push_str(&format!("bind.{} = {}\n", action, key));
+        }
+        fs::write(path, text)
+    }
+
+    /// `max_fps` as `run` wants it: `None` for no cap.
+    pub fn fps_cap(&self) -> Option<f64> {
+        if self.max_fps > 0.0 {
+            Some(self.max_fps as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn binding(&self, action: &str) -> Option<&str> {
+        self.bindings.get(action).map(|k| k.as_str())
+    }
+
+    /// A window for a `size` game, `scale` times as big or fullscreen.
+    pub fn window(&self, title: &str, size: (usize, usize)) -> WindowBuilder {
+        let (w, h) = (size.0 as f32 * self.scale, size.1 as f32 * self.scale);
+        let window = WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(w, h));
+        if self.fullscreen {
+            window.with_fullscreen(Some(Fullscreen::Borderless(None)))
+        } else {
+            window
+        }
+    }
+
+    /// Command line options that override the settings file; add these to
+    /// the game's `clap::App` and pass the matches to `apply_args` (and
+    /// `save_dir`).
+    pub fn args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+        vec![
+            clap::Arg::with_name("backend")
+                .long("backend")
+                .takes_value(true)
+                .validator(GraphicsMethod::validate)
+                .help("Graphics backend. Options are CPU, OpenGL, Vulkan, or default"),
+            clap::Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .help("Master volume from 0 to 1"),
+            clap::Arg::with_name("scale")
+                .long("scale")
+                .takes_value(true)
+                .help("Window scale factor"),
+            clap::Arg::with_name("fullscreen")
+                .long("fullscreen")
+                .help("Start in fullscreen"),
+            clap::Arg::with_name("autosave")
+                .long("autosave")
+                .takes_value(true)
+                .help("Seconds between autosaves, or 0 for none"),
+            clap::Arg::with_name("max-fps")
+                .long("max-fps")
+                .takes_value(true)
+                .help("Most frames to draw per second, or 0 for no cap"),
+            clap::Arg::with_name("keep-running")
+                .long("keep-running")
+                .help("Keep the game running while the window is in the background"),
+            clap::Arg::with_name("save-dir")
+                .long("save-dir")
+                .takes_value(true)
+                .help("Directory to keep save files in"),
+        ]
+    }
+
+    /// Let command line options win over whatever was loaded from the file.
+    /// These overrides aren't saved unless the caller saves afterwards.
+    pub fn apply_args(&mut self, matches: &clap::ArgMatches) {
+        for key in ["backend", "volume", "scale", "autosave"].iter() {
+            if let Some(value) = matches.value_of(key) {
+                if !self.set(key, value) {
+                    log::warn!("Ignoring bad --{} {:?}", key, value);
+                }
+            }
+        }
+        if let Some(value) = matches.value_of("max-fps") {
+            if !self.set("max_fps", value) {
+                log::warn!("Ignoring bad --max-fps {:?}", value);
+            }
+        }
+        if matches.is_present("fullscreen") {
+            self.fullscreen = true;
+        }
+        if matches.is_present("keep-running") {
+            self.pause_unfocused = false;
+        }
+    }
+}
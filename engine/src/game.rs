@@ -0,0 +1,788 @@
+//! The glue between `run` and the game's screens: which `StateType` the
+//! game is on, what a frame of input does there, and how it's drawn.  Give
+//! `run` a `Game` as its rules and `update` and `draw` as its callbacks.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use wgpu::SwapChainError;
+
+use crate::config::Settings;
+use crate::graphics::{
+    end_screen::{draw_game_over, draw_timer, draw_victory},
+    gpu::SpriteInstance,
+    graphics::GraphicalDisplay,
+    loading::{draw_loading, Preloader},
+    resources::Resources,
+    screen::{Framebuffer, Screen},
+    sprites::SpriteBatch,
+    texture::CpuTexture,
+};
+use crate::input::{InputSource, KeyBindings};
+use crate::logic::{
+    camera::Camera,
+    menu::{
+        update_confirm_quit, update_end_screen, update_menu, EndAction, MenuAction, QuitAction,
+    },
+    platformer::PlatformerConfig,
+    save::{Autosave, AUTOSAVE_FILE},
+    sim::Sim,
+    state::{EntityType, GameState, Inputs, Level, LevelProgress, StateType},
+    timer::TimeUp,
+    types::{Rect, Rgba, Vec2i},
+};
+use crate::net::Lockstep;
+use crate::schedule::{StepMode, Time};
+
+const MENU_COLOR: Rgba = Rgba(32, 32, 64, 255);
+const MENU_HIGHLIGHT: Rgba = Rgba(240, 200, 64, 255);
+const HUD_COLOR: Rgba = Rgba(255, 255, 255, 255);
+
+// What `Game::preload` expects each sheet to be queued as
+pub const PLAYER_SHEET: &str = "player";
+pub const ENEMY_SHEET: &str = "enemy";
+pub const PICKUP_SHEET: &str = "pickup";
+
+/// A sprite sheet for each type of entity.
+#[derive(Clone)]
+pub struct EntitySheets {
+    pub player: Rc<CpuTexture>,
+    pub enemy: Rc<CpuTexture>,
+    pub pickup: Rc<CpuTexture>,
+}
+
+impl EntitySheets {
+    /// `Resources`' placeholder for everything, to stand in while the real
+    /// sheets preload.
+    pub fn placeholder(rsrc: &Resources) -> Self {
+        let tex = rsrc.placeholder();
+        Self {
+            player: tex.clone(),
+            enemy: tex.clone(),
+            pickup: tex.clone(),
+        }
+    }
+    pub fn get(&self, etype: EntityType) -> Rc<CpuTexture> {
+        match etype {
+            EntityType::Player => self.player.clone(),
+            EntityType::Enemy => self.enemy.clone(),
+            EntityType::Pickup => self.pickup.clone(),
+        }
+    }
+}
+
+/// Everything the game keeps between frames besides its `GameState`.
+pub struct Game {
+    pub levels: Vec<Level>,
+    pub config: PlatformerConfig,
+    // The current level's collision; rebuilt whenever a level loads
+    pub sim: Sim,
+    pub camera: Camera,
+    pub sheets: EntitySheets,
+    // Behind everything while playing
+    pub bg: Rgba,
+    // The menu changes volume and mute here and saves them to the path
+    pub settings: Settings,
+    pub settings_path: PathBuf,
+    // Sheets still loading, to swap into `sheets` once they're done
+    loading: Option<Preloader>,
+    // Off if `settings.autosave` is 0
+    pub autosave: Option<Autosave>,
+    // Set to play online; `start` adds the other side's player
+    pub lockstep: Option<Lockstep>,
+    // Sprite instances for each of the world's chunks, for the GPU; only
+    // rebuilt when the camera sees different tiles
+    tiles: Vec<Vec<SpriteInstance>>,
+    // Each player's inputs this frame, in the order of `players`
+    inputs: Vec<Inputs>,
+    // `draw` only gets `&Game`, so its scratch space lives in here
+    scratch: RefCell<DrawScratch>,
+}
+
+struct DrawScratch {
+    order: Vec<usize>,
+    instances: Vec<SpriteInstance>,
+    // The HUD and menus on the GPU backend
+    ui: Framebuffer,
+}
+
+impl Game {
+    /// A game on the title screen, drawing at `view` game pixels, with
+    /// `settings` and their path from `Settings::startup`.
+    pub fn new(
+        levels: Vec<Level>,
+        config: PlatformerConfig,
+        view: (u16, u16),
+        sheets: EntitySheets,
+        settings: Settings,
+        settings_path: PathBuf,
+    ) -> Self {
+        assert!(!levels.is_empty(), "A game needs at least one level");
+        let sim = Sim::with_world(config, &levels[0].world());
+        let autosave = if settings.autosave > 0.0 {
+            let path = settings_path.with_file_name(AUTOSAVE_FILE);
+            Some(Autosave::new(path, settings.autosave as f64))
+        } else {
+            None
+        };
+        Self {
+            levels,
+            config,
+            sim,
+            camera: Camera::new(view),
+            sheets,
+            bg: Rgba(80, 120, 160, 255),
+            settings,
+            settings_path,
+            loading: None,
+            autosave,
+            lockstep: None,
+            tiles: vec![],
+            inputs: vec![],
+            scratch: RefCell::new(DrawScratch {
+                order: vec![],
+                instances: vec![],
+                ui: Framebuffer::new(view.0 as usize, view.1 as usize),
+            }),
+        }
+    }
+
+    /// Start on the loading screen instead of the title screen, until the
+    /// sheets queued in `preloader` as `PLAYER_SHEET`, `ENEMY_SHEET` and
+    /// `PICKUP_SHEET` have loaded.
+    pub fn preload(&mut self, preloader: Preloader) {
+        self.loading = Some(preloader);
+    }
+
+    /// Check on the preloader; once it's done, swap in the sheets it loaded
+    /// and say it's time for the menu.
+    fn load(&mut self, state: &mut GameState) -> Next {
+        let preloader = match &mut self.loading {
+            Some(preloader) => preloader,
+            None => return Next::Menu,
+        };
+        if !preloader.poll() {
+            return Next::Stay;
+        }
+        self.sheets = EntitySheets {
+            player: preloader.take(PLAYER_SHEET),
+            enemy: preloader.take(ENEMY_SHEET),
+            pickup: preloader.take(PICKUP_SHEET),
+        };
+        self.loading = None;
+        for p in state.players.iter() {
+            state.textures[p.entity] = self.sheets.player.clone();
+        }
+        Next::Menu
+    }
+
+    /// Start over from the first level.
+    fn restart(&mut self, state: &mut GameState) {
+        let sheets = &self.sheets;
+        state.restart(&self.levels, &mut self.camera, |t| sheets.get(t));
+        self.level_started(state);
+    }
+
+    // Collision for the level `state` just loaded, and a fresh autosave
+    fn level_started(&mut self, state: &mut GameState) {
+        self.sim = Sim::with_world(self.config, &state.world);
+        self.update_tiles(state);
+        if let Some(autosave) = &mut self.autosave {
+            if let Err(e) = autosave.save_now(state) {
+                log::warn!("Couldn't autosave to {}: {}", autosave.path.display(), e);
+            }
+        }
+    }
+
+    // Bring `tiles` up to date with the world and the camera
+    fn update_tiles(&mut self, state: &mut GameState) {
+        let view = self.camera.view_rect();
+        self.tiles.resize_with(state.world.tilemaps.len(), Vec::new);
+        for (map, tiles) in state.world.tilemaps.iter_mut().zip(self.tiles.iter_mut()) {
+            map.update_tilemap_instances(view, tiles);
+        }
+    }
+
+    /// Run a frame of gameplay and say where to go next.
+    fn play(&mut self, state: &mut GameState, input: &dyn InputSource, time: Time) -> Next {
+        state.read_player_inputs(input);
+        if input.close_requested() || state.players.iter().any(|p| p.inputs.esc) {
+            return Next::ConfirmQuit;
+        }
+        // Players stand still while a camera path shows them something
+        if self.camera.input_locked() {
+            for p in state.players.iter_mut() {
+                p.inputs = Inputs::new();
+            }
+        }
+        self.inputs.clear();
+        match &mut self.lockstep {
+            // Our keys drive our player, whichever one that is, and the
+            // other side's come over the network
+            Some(lockstep) => {
+                let local = state.players[0].inputs;
+                if let Err(e) = lockstep.send(local).and_then(|_| lockstep.poll()) {
+                    log::warn!("Lost touch with the other player: {}", e);
+                }
+                match lockstep.next_frame() {
+                    Some(both) => self.inputs.extend(both.iter().copied()),
+                    // Wait for them to catch up
+                    None => return Next::Stay,
+                }
+            }
+            None => self.inputs.extend(state.players.iter().map(|p| p.inputs)),
+        }
+        let events = self.sim.step(state, &self.inputs, time);
+        self.camera.follow(state.players_center());
+        state.camera = self.camera.position();
+        self.update_tiles(state);
+        if let Some(autosave) = &mut self.autosave {
+            if let Err(e) = autosave.tick(state, time.dt) {
+                log::warn!("Couldn't autosave to {}: {}", autosave.path.display(), e);
+            }
+        }
+
+        if events.game_over || events.time_up == Some(TimeUp::GameOver) {
+            return Next::GameOver;
+        }
+        if events.time_up == Some(TimeUp::Victory) {
+            return Next::Victory;
+        }
+        if events.level_complete {
+            let sheets = &self.sheets;
+            match state.next_level(&self.levels, &mut self.camera, |t| sheets.get(t)) {
+                LevelProgress::Started(_) => self.level_started(state),
+                LevelProgress::Finished => return Next::Victory,
+            }
+        }
+        Next::Stay
+    }
+}
+
+// Where `update` goes after a frame
+enum Next {
+    Stay,
+    Play,
+    GameOver,
+    Victory,
+    Menu,
+    ConfirmQuit,
+    Back,
+    Quit,
+}
+
+/// Open a window for `game`, as its settings say, and play from the title
+/// screen until the player quits.  Load the settings with
+/// `Settings::startup` so the player's file and command line both count.
+pub fn start(title: &str, rsrc: Resources, game: Game) {
+    // Online, both sides need the same seed and have to step in time
+    let (seed, step_mode) = match &game.lockstep {
+        Some(lockstep) => (lockstep.seed(), StepMode::Fixed(1)),
+        None => (rand::random(), StepMode::CatchUp),
+    };
+    let mut state = GameState::new(seed, game.sheets.player.clone());
+    state.players[0].bindings = KeyBindings::from_settings(&game.settings);
+    if game.lockstep.is_some() {
+        state.add_player(Vec2i(0, 0), game.sheets.player.clone(), KeyBindings::WASD);
+    }
+    let settings = game.settings.clone();
+    let (w, h) = (game.camera.view.0 as usize, game.camera.view.1 as usize);
+    let sound = state.sound_channels.clone();
+    let game_loading = game.loading.is_some();
+    crate::run(
+        w,
+        h,
+        settings.window(title, (w, h)),
+        rsrc,
+        game,
+        if game_loading {
+            StateType::Loading(state)
+        } else {
+            StateType::Menu(state)
+        },
+        settings.backend,
+        step_mode,
+        &settings,
+        Some(sound),
+        |_, _, _, _| Ok(()),
+        draw,
+        update,
+    );
+}
+
+/// Run a frame of whichever screen the game is on and move between them:
+/// the menu starts a game, dying or winning ends it, and asking to quit
+/// (from the menu, pausing, or closing the window) goes through
+/// `StateType::ConfirmQuit`.  Returns true once the player really quits.
+pub fn update(game: &mut Game, state: &mut StateType, input: &dyn InputSource, time: Time) -> bool {
+    let next = match state {
+        // Nothing to lose yet, so closing the window just quits
+        StateType::Loading(_) if input.close_requested() => Next::Quit,
+        StateType::Loading(s) => game.load(s),
+        StateType::Menu(s) => {
+            s.read_menu_inputs(input);
+            if input.close_requested() {
+                Next::ConfirmQuit
+            } else {
+                match update_menu(s, &mut game.settings, &game.settings_path) {
+                    MenuAction::Stay => Next::Stay,
+                    MenuAction::Start => {
+                        game.restart(s);
+                        Next::Play
+                    }
+                    MenuAction::Quit => Next::ConfirmQuit,
+                }
+            }
+        }
+        StateType::Playing(s) => game.play(s, input, time),
+        StateType::GameOver(s) | StateType::Victory(s) => {
+            s.read_menu_inputs(input);
+            if input.close_requested() {
+                Next::ConfirmQuit
+            } else {
+                match update_end_screen(s) {
+                    EndAction::Stay => Next::Stay,
+                    EndAction::Restart => {
+                        game.restart(s);
+                        Next::Play
+                    }
+                    EndAction::Menu => Next::Menu,
+                }
+            }
+        }
+        StateType::ConfirmQuit(from) => {
+            let s = from.game_state_mut();
+            s.read_menu_inputs(input);
+            // Closing the window again means it
+            if input.close_requested() {
+                Next::Quit
+            } else {
+                match update_confirm_quit(s) {
+                    QuitAction::Stay => Next::Stay,
+                    QuitAction::Quit => Next::Quit,
+                    QuitAction::Back => Next::Back,
+                }
+            }
+        }
+    };
+    // Screens change a few times a game, so it's fine to copy the game
+    // across rather than juggle ownership of it
+    *state = match next {
+        Next::Stay => return false,
+        Next::Quit => return true,
+        Next::Play => StateType::Playing(state.game_state().clone()),
+        Next::GameOver => StateType::GameOver(state.game_state().clone()),
+        Next::Victory => StateType::Victory(state.game_state().clone()),
+        Next::Menu => StateType::Menu(state.game_state().clone()),
+        Next::ConfirmQuit => StateType::ConfirmQuit(Box::new(state.clone())),
+        Next::Back => match state {
+            StateType::ConfirmQuit(from) => (**from).clone(),
+            _ => return false,
+        },
+    };
+    false
+}
+
+/// Draw whichever screen the game is on.  The CPU backend draws it all
+/// into its framebuffer; the GPU backend draws the level's sprites on the
+/// GPU, with the HUD and menus drawn on the CPU over them.
+pub fn draw(
+    _rsrc: &Resources,
+    game: &Game,
+    state: &StateType,
+    display: &mut GraphicalDisplay,
+    _frame: usize,
+) -> Result<(), SwapChainError> {
+    let scratch = &mut *game.scratch.borrow_mut();
+    match display {
+        GraphicalDisplay::Cpu(gpu, fb) => {
+            let s = state.game_state();
+            if shows_level(state) {
+                s.draw_frame(&mut fb.screen(s.camera), game.bg);
+            }
+            draw_ui(game, state, &mut fb.screen(Vec2i(0, 0)));
+            gpu.render_sprites(&[], Some(fb))
+        }
+        GraphicalDisplay::Gpu(gpu) => {
+            let s = state.game_state();
+            gpu.clear_color = wgpu::Color {
+                r: game.bg.0 as f64 / 255.0,
+                g: game.bg.1 as f64 / 255.0,
+                b: game.bg.2 as f64 / 255.0,
+                a: 1.0,
+            };
+            gpu.sprites.camera = (s.camera.0 as f32, s.camera.1 as f32);
+            gpu.sprites.zoom = game.camera.zoom;
+            // The world's tiles, a batch per chunk, then the entities in
+            // draw order, a batch for each run that shares a texture
+            let mut textures = vec![];
+            let mut runs: Vec<&[SpriteInstance]> = vec![];
+            if shows_level(state) {
+                for (map, tiles) in s.world.tilemaps.iter().zip(game.tiles.iter()) {
+                    if !tiles.is_empty() {
+                        textures.push(map.tileset().texture().clone());
+                        runs.push(tiles);
+                    }
+                }
+                s.sprite_instances(&mut scratch.order, &mut scratch.instances);
+                let order = &scratch.order;
+                let mut start = 0;
+                for i in 1..=order.len() {
+                    if i == order.len()
+                        || !Rc::ptr_eq(&s.textures[order[i]], &s.textures[order[start]])
+                    {
+                        textures.push(s.textures[order[start]].clone());
+                        runs.push(&scratch.instances[start..i]);
+                        start = i;
+                    }
+                }
+            }
+            let mut bind_groups = Vec::with_capacity(textures.len());
+            for texture in textures.iter() {
+                match gpu.sprite_texture(texture) {
+                    Ok(bind_group) => bind_groups.push(bind_group),
+                    Err(e) => {
+                        log::error!("{}", e);
+                        return Ok(());
+                    }
+                }
+            }
+            let batches: Vec<SpriteBatch> = bind_groups
+                .iter()
+                .zip(runs.iter())
+                .map(|(texture, instances)| SpriteBatch { texture, instances })
+                .collect();
+            let ui = &mut scratch.ui;
+            {
+                let mut screen = ui.screen(Vec2i(0, 0));
+                screen.clear(Rgba(0, 0, 0, 0));
+                draw_ui(game, state, &mut screen);
+            }
+            gpu.render_sprites(&batches, Some(ui))
+        }
+    }
+}
+
+// Whether this screen shows the level, rather than covering it up
+fn shows_level(state: &StateType) -> bool {
+    match state {
+        StateType::Playing(_) => true,
+        StateType::ConfirmQuit(from) => shows_level(from),
+        _ => false,
+    }
+}
+
+// Everything drawn on the CPU over the level, in screen space
+fn draw_ui(game: &Game, state: &StateType, screen: &mut Screen) {
+    match state {
+        StateType::Loading(_) => draw_loading(
+            screen,
+            game.loading.as_ref().map_or(1.0, Preloader::progress),
+        ),
+        StateType::Menu(s) => {
+            screen.clear(MENU_COLOR);
+            let (w, h) = screen.size();
+            let size = ((w / 2) as u16, (h / 10) as u16);
+            let top_left = Vec2i((w as i32 - size.0 as i32) / 2, h as i32 / 4);
+            s.menu.draw(
+                screen,
+                top_left,
+                size,
+                size.1 as i32 / 2,
+                MENU_COLOR,
+                MENU_HIGHLIGHT,
+            );
+        }
+        StateType::Playing(s) => {
+            draw_timer(screen, &s.timer, Vec2i(4, 4), HUD_COLOR);
+            // Camera paths can ask for cinematic bars
+            screen.letterbox(game.camera.letterbox(), Rgba(0, 0, 0, 255));
+        }
+        StateType::GameOver(s) => draw_game_over(screen, s),
+        StateType::Victory(s) => draw_victory(screen, s),
+        StateType::ConfirmQuit(from) => {
+            // Whatever was on screen, with the question over it
+            draw_ui(game, from, screen);
+            let (w, h) = screen.size();
+            let scale = (h / 80).max(1) as u16;
+            let r = Rect {
+                x: w as i32 / 8,
+                y: h as i32 * 3 / 8,
+                w: (w * 3 / 4) as u16,
+                h: (h / 4) as u16,
+            };
+            screen.rect(r, MENU_COLOR);
+            let pad = scale as i32 * 2;
+            screen.text(
+                "Quit",
+                Vec2i(r.x + pad, r.y + pad),
+                scale * 2,
+                MENU_HIGHLIGHT,
+            );
+            screen.text(
+                "Space yes  Esc no",
+                Vec2i(r.x + pad, r.y + r.h as i32 - pad - 5 * scale as i32),
+                scale,
+                HUD_COLOR,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{RecordedFrame, RecordedInput};
+    use crate::logic::menu::{MenuEntry, MENU_ENTRIES};
+    use crate::logic::state::tests::{game_state, texture};
+    use crate::logic::tiles::{Tile, Tilemap, Tileset};
+    use winit::event::VirtualKeyCode;
+
+    fn game() -> Game {
+        let tileset = Rc::new(Tileset::new(vec![Tile::SOLID], texture(16, 16)));
+        let level = Level {
+            tilemaps: vec![Tilemap::new(Vec2i(0, 32), (8, 1), &tileset, vec![0; 8])],
+            spawns: vec![(EntityType::Player, 16, 0)],
+            map_pool: vec![],
+            empty_map: None,
+            start_map: None,
+        };
+        let sheets = EntitySheets {
+            player: texture(4, 4),
+            enemy: texture(4, 4),
+            pickup: texture(4, 4),
+        };
+        let path = std::env::temp_dir().join(format!("game-settings-{}.cfg", std::process::id()));
+        let mut settings = Settings::default();
+        settings.autosave = 0.0;
+        Game::new(
+            vec![level],
+            PlatformerConfig::default(),
+            (64, 48),
+            sheets,
+            settings,
+            path,
+        )
+    }
+
+    // Run a frame with `keys` going down
+    fn press(game: &mut Game, state: &mut StateType, keys: &[VirtualKeyCode]) -> bool {
+        let input = RecordedInput::new(vec![RecordedFrame {
+            pressed: keys.to_vec(),
+            ..RecordedFrame::default()
+        }]);
+        update(game, state, &input, Time::new(0, 1.0 / 60.0))
+    }
+
+    #[test]
+    fn quitting_from_the_menu_asks_first() {
+        let mut game = game();
+        let mut s = game_state();
+        s.menu.selected = MENU_ENTRIES
+            .iter()
+            .position(|&e| e == MenuEntry::Quit)
+            .unwrap();
+        let mut state = StateType::Menu(s);
+
+        assert!(!press(&mut game, &mut state, &[VirtualKeyCode::Space]));
+        assert!(
+            matches!(&state, StateType::ConfirmQuit(from) if matches!(**from, StateType::Menu(_)))
+        );
+        // Escape backs out to the menu, as it was
+        assert!(!press(&mut game, &mut state, &[VirtualKeyCode::Escape]));
+        assert!(matches!(&state, StateType::Menu(s) if s.menu.selected() == MenuEntry::Quit));
+        // Asked again, space really quits
+        press(&mut game, &mut state, &[VirtualKeyCode::Space]);
+        assert!(press(&mut game, &mut state, &[VirtualKeyCode::Space]));
+    }
+
+    #[test]
+    fn pausing_asks_to_quit_and_backing_out_resumes() {
+        let mut game = game();
+        let mut s = game_state();
+        s.menu.selected = MENU_ENTRIES
+            .iter()
+            .position(|&e| e == MenuEntry::Start)
+            .unwrap();
+        let mut state = StateType::Menu(s);
+        press(&mut game, &mut state, &[VirtualKeyCode::Space]);
+        assert!(matches!(state, StateType::Playing(_)));
+        // Started on the level's spawn
+        assert_eq!(state.game_state().positions[0], Vec2i(16, 0));
+
+        press(&mut game, &mut state, &[VirtualKeyCode::Escape]);
+        assert!(
+            matches!(&state, StateType::ConfirmQuit(from) if matches!(**from, StateType::Playing(_)))
+        );
+        press(&mut game, &mut state, &[VirtualKeyCode::Escape]);
+        assert!(matches!(state, StateType::Playing(_)));
+    }
+
+    #[test]
+    fn loading_screen_swaps_in_the_sheets_then_goes_to_the_menu() {
+        let dir = std::env::temp_dir().join(format!("game-preload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        image::RgbaImage::new(6, 2)
+            .save(dir.join("player.png"))
+            .unwrap();
+        let rsrc = Resources::with_root(&dir);
+        let mut preloader = Preloader::new();
+        preloader.texture(&rsrc, PLAYER_SHEET, "player.png");
+        // Missing sheets load as the placeholder rather than holding up
+        // the game
+        preloader.texture(&rsrc, ENEMY_SHEET, "enemy.png");
+        preloader.texture(&rsrc, PICKUP_SHEET, "pickup.png");
+        let mut game = game();
+        game.preload(preloader);
+        let mut state = StateType::Loading(game_state());
+
+        for _ in 0..500 {
+            if !matches!(state, StateType::Loading(_)) {
+                break;
+            }
+            press(&mut game, &mut state, &[]);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(matches!(state, StateType::Menu(_)));
+        assert_eq!(game.sheets.player.size(), (6, 2));
+        assert!(Rc::ptr_eq(
+            &state.game_state().textures[0],
+            &game.sheets.player
+        ));
+        assert!(Rc::ptr_eq(&game.sheets.enemy, rsrc.placeholder()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn autosaves_on_starting_a_level_and_every_interval() {
+        let dir = std::env::temp_dir().join(format!("game-autosave-{}", std::process::id()));
+        let path = dir.join(AUTOSAVE_FILE);
+        let mut game = game();
+        game.autosave = Some(Autosave::new(&path, 0.05));
+        let mut s = game_state();
+        s.menu.selected = MENU_ENTRIES
+            .iter()
+            .position(|&e| e == MenuEntry::Start)
+            .unwrap();
+        let mut state = StateType::Menu(s);
+        press(&mut game, &mut state, &[VirtualKeyCode::Space]);
+        assert!(path.exists());
+
+        std::fs::remove_file(&path).unwrap();
+        press(&mut game, &mut state, &[]);
+        press(&mut game, &mut state, &[]);
+        assert!(!path.exists());
+        press(&mut game, &mut state, &[]);
+        assert!(path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn online_games_step_together() {
+        let port = || {
+            std::net::UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        };
+        let (host_addr, guest_addr) = (port(), port());
+        let side = |local, remote, host| {
+            let mut game = game();
+            game.lockstep = Some(Lockstep::connect(local, remote, 9, host).unwrap());
+            let mut s = game_state();
+            s.add_player(Vec2i(0, 0), texture(4, 4), KeyBindings::WASD);
+            game.restart(&mut s);
+            (game, StateType::Playing(s))
+        };
+        let (mut host, mut host_state) = side(host_addr, guest_addr, true);
+        let (mut guest, mut guest_state) = side(guest_addr, host_addr, false);
+        let frame = |game: &Game| game.lockstep.as_ref().unwrap().frame();
+
+        // Each side holds right, which moves their own player
+        for _ in 0..500 {
+            if frame(&host) >= 30 && frame(&guest) >= 30 {
+                break;
+            }
+            press(&mut host, &mut host_state, &[VirtualKeyCode::Right]);
+            press(&mut guest, &mut guest_state, &[VirtualKeyCode::Right]);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        // Run whoever's ahead back to the same frame, then compare
+        while frame(&host) < frame(&guest) {
+            press(&mut host, &mut host_state, &[VirtualKeyCode::Right]);
+        }
+        while frame(&guest) < frame(&host) {
+            press(&mut guest, &mut guest_state, &[VirtualKeyCode::Right]);
+        }
+        let (h, g) = (host_state.game_state(), guest_state.game_state());
+        assert_eq!(h.positions, g.positions);
+        assert!(h.positions[0].0 > 16);
+        assert!(h.positions[1].0 > 16 + 25);
+    }
+
+    #[test]
+    fn camera_paths_lock_input_and_letterbox_the_view() {
+        use crate::logic::camera::{CameraPath, Easing, Keyframe};
+        // The same game twice, one with right held and one with nothing
+        let side = || {
+            let mut game = game();
+            let mut s = game_state();
+            game.restart(&mut s);
+            let mut path = CameraPath::new(vec![Keyframe {
+                center: Vec2i(100, 0),
+                zoom: None,
+                frames: 60,
+                easing: Easing::Linear,
+            }]);
+            path.letterbox = 6;
+            game.camera.play(path);
+            (game, StateType::Playing(s))
+        };
+        let (mut game, mut state) = side();
+        let (mut idle, mut idle_state) = side();
+        for _ in 0..10 {
+            press(&mut game, &mut state, &[VirtualKeyCode::Right]);
+            press(&mut idle, &mut idle_state, &[]);
+        }
+        assert_eq!(
+            state.game_state().positions,
+            idle_state.game_state().positions
+        );
+
+        let mut fb = Framebuffer::new(64, 48);
+        draw_ui(&game, &state, &mut fb.screen(Vec2i(0, 0)));
+        let pixel = |x: usize, y: usize| fb.buffer()[(y * 64 + x) * 4..][..4].to_vec();
+        assert_eq!(pixel(32, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(32, 47), [0, 0, 0, 255]);
+        assert_eq!(pixel(32, 24), [0, 0, 0, 0]);
+
+        // Once it's over the player can move again
+        game.camera.stop();
+        idle.camera.stop();
+        for _ in 0..10 {
+            press(&mut game, &mut state, &[VirtualKeyCode::Right]);
+            press(&mut idle, &mut idle_state, &[]);
+        }
+        assert!(state.game_state().positions[0].0 > idle_state.game_state().positions[0].0);
+    }
+
+    #[test]
+    fn tile_instances_follow_the_camera() {
+        let mut game = game();
+        let mut s = game_state();
+        game.restart(&mut s);
+        assert_eq!(game.tiles.len(), 1);
+
+        game.camera.snap_to(Vec2i(32, 32));
+        game.update_tiles(&mut s);
+        let visible = s.world.tilemaps[0].visible_tiles(game.camera.view_rect());
+        assert!(!game.tiles[0].is_empty());
+        assert_eq!(game.tiles[0].len(), visible.0.len() * visible.1.len());
+
+        // Nothing to draw with the chunk out of view
+        game.camera.snap_to(Vec2i(1000, 1000));
+        game.update_tiles(&mut s);
+        assert!(game.tiles[0].is_empty());
+    }
+}
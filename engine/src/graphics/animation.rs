@@ -1,6 +1,81 @@
+use crate::graphics::texture::CpuTexture;
 use crate::logic::types::Rect;
+use std::ops::Range;
 use std::rc::Rc;
 
+/// A sprite sheet laid out as a grid of equally sized cells, numbered
+/// left to right, then top to bottom.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpriteSheet {
+    cols: u16,
+    rows: u16,
+    cell_w: u16,
+    cell_h: u16,
+}
+
+impl SpriteSheet {
+    pub fn new(cols: u16, rows: u16, cell_w: u16, cell_h: u16) -> Self {
+        Self {
+            cols,
+            rows,
+            cell_w,
+            cell_h,
+        }
+    }
+    /// Like `new`, but checks that the grid actually fits on `tex`.
+    pub fn grid(tex: &CpuTexture, cols: u16, rows: u16, cell_w: u16, cell_h: u16) -> Self {
+        let sheet = Self::new(cols, rows, cell_w, cell_h);
+        assert!(
+            tex.valid_frame(Rect {
+                x: 0,
+                y: 0,
+                w: cols * cell_w,
+                h: rows * cell_h,
+            }),
+            "{}x{} grid of {}x{} cells doesn't fit on a {:?} texture",
+            cols,
+            rows,
+            cell_w,
+            cell_h,
+            tex.size()
+        );
+        sheet
+    }
+    pub fn len(&self) -> usize {
+        self.cols as usize * self.rows as usize
+    }
+    /// The rect of cell number `idx`.
+    pub fn frame(&self, idx: usize) -> Rect {
+        assert!(idx < self.len(), "Frame {} out of range for {:?}", idx, self);
+        let col = (idx % self.cols as usize) as i32;
+        let row = (idx / self.cols as usize) as i32;
+        Rect {
+            x: col * self.cell_w as i32,
+            y: row * self.cell_h as i32,
+            w: self.cell_w,
+            h: self.cell_h,
+        }
+    }
+    /// An animation through cells `frames`, holding each for `len` ticks.
+    pub fn animation(&self, frames: Range<usize>, len: usize, looping: bool) -> Animation {
+        Animation::new(frames.map(|idx| (self.frame(idx), len)), looping)
+    }
+    pub fn animation_with(&self, frames: Range<usize>, len: usize, playback: Playback) -> Animation {
+        Animation::with_playback(frames.map(|idx| (self.frame(idx), len)), playback)
+    }
+}
+
+/// What an animation does once it reaches its last frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Playback {
+    // Stop on the last frame
+    Once,
+    // Jump back to the first frame
+    Loop,
+    // Play backwards to the first frame, then forwards again, and so on
+    PingPong,
+}
+
 #[derive(Debug)]
 pub struct Animation {
     // Do this for the exercise today!
@@ -9,16 +84,38 @@ pub struct Animation {
     // An Animation/AnimationState split could be fine, if AnimationState holds the start time and the present frame (or just the start time) and possibly a reference to the Animation
     // but there are lots of designs that will work!
     frames: Vec<(Rect, usize)>,
-    looping: bool,
+    playback: Playback,
+    // An optional tag for each frame, reported by `AnimationState::tick`
+    // when that frame comes up
+    events: Vec<Option<&'static str>>,
 }
 
 impl Animation {
+    /// Shorthand for `with_playback` with either `Loop` or `Once`.
     pub fn new(frames: impl IntoIterator<Item = (Rect, usize)>, looping: bool) -> Self {
+        Self::with_playback(
+            frames,
+            if looping {
+                Playback::Loop
+            } else {
+                Playback::Once
+            },
+        )
+    }
+    pub fn with_playback(frames: impl IntoIterator<Item = (Rect, usize)>, playback: Playback) -> Self {
+        let frames: Vec<(Rect, usize)> = frames.into_iter().collect();
         Self {
-            frames: frames.into_iter().collect(),
-            looping,
+            events: vec![None; frames.len()],
+            frames,
+            playback,
         }
     }
+    /// Tag frame `frame` with `tag`, e.g. "footstep" on the frames where a
+    /// foot hits the ground.
+    pub fn with_event(mut self, frame: usize, tag: &'static str) -> Self {
+        self.events[frame] = Some(tag);
+        self
+    }
     pub fn freeze(r: Rect) -> Self {
         Self::new(vec![(r, 0)], false)
     }
@@ -32,10 +129,23 @@ impl Animation {
         AnimationState {
             animation: Rc::clone(self),
             time: 0,
+            fade_from: None,
+            fade_left: 0,
         }
     }
+    /// The frames in the order they're shown over one cycle.  For ping-pong
+    /// that's 0, 1, ..., n-1, n-2, ..., 1; the ends aren't doubled up.
+    fn sequence(&self) -> impl Iterator<Item = usize> {
+        let n = self.frames.len();
+        let back = match self.playback {
+            Playback::PingPong if n > 2 => 1..(n - 1),
+            _ => 0..0,
+        };
+        (0..n).chain(back.rev())
+    }
+    /// Length of one cycle; a ping-pong cycle includes the trip back.
     pub fn duration(&self) -> usize {
-        self.frames.iter().map(|(_, t)| t).sum()
+        self.sequence().map(|fi| self.frames[fi].1).sum()
     }
 }
 
@@ -43,35 +153,121 @@ impl Animation {
 pub struct AnimationState {
     animation: Rc<Animation>,
     time: usize,
+    // Last frame of the animation we're crossfading out of, if any
+    fade_from: Option<Rect>,
+    // Ticks of crossfade left
+    fade_left: usize,
 }
 impl AnimationState {
     pub fn frame(&self) -> Rect {
+        match self.fade_from {
+            // Sprites can't really blend, so a crossfade alternates
+            // between the old frame and the new animation each tick
+            Some(old) if self.fade_left % 2 == 1 => old,
+            _ => self.animation.frames[self.frame_index()].0,
+        }
+    }
+    /// Which of the animation's frames is showing.
+    pub fn frame_index(&self) -> usize {
         let mut t = 0;
-        for (cr, ct) in self.animation.frames.iter() {
-            t += ct;
-            if t >= self.time {
-                return *cr;
+        for fi in self.animation.sequence() {
+            t += self.animation.frames[fi].1;
+            if t > self.time {
+                return fi;
             }
         }
-        panic!(
-            "Animation frame not found for t={}, anim={:?}",
-            self.time, self.animation
-        );
+        // Only a finished (or zero-length) animation gets here; it stays on
+        // its last frame
+        self.animation.sequence().last().unwrap_or_else(|| {
+            panic!(
+                "Animation frame not found for t={}, anim={:?}",
+                self.time, self.animation
+            )
+        })
     }
     pub fn done(&self) -> bool {
         self.time >= self.animation.duration()
     }
-    pub fn tick(&mut self) {
+    /// Ticks into the animation.
+    pub fn time(&self) -> usize {
+        self.time
+    }
+    /// Jump to `time` ticks in, e.g. to pick up where a saved game left
+    /// off.  Clamped to the end of the animation.
+    pub fn seek(&mut self, time: usize) {
+        self.time = time.min(self.animation.duration());
+    }
+    /// Advance a tick.  If that moved onto a new frame with an event tag,
+    /// returns the tag.
+    pub fn tick(&mut self) -> Option<&'static str> {
+        self.fade_left = self.fade_left.saturating_sub(1);
+        if self.fade_left == 0 {
+            self.fade_from = None;
+        }
+        let before = self.frame_index();
         let dur = self.animation.duration();
-        self.time = if self.animation.looping {
-            (self.time + 1) % dur
-        } else {
-            (self.time + 1).min(dur)
+        self.time = match self.animation.playback {
+            Playback::Once => (self.time + 1).min(dur),
+            Playback::Loop | Playback::PingPong => (self.time + 1) % dur,
         };
+        let after = self.frame_index();
+        if after != before {
+            self.animation.events[after]
+        } else {
+            None
+        }
     }
     pub fn play(&mut self, anim: &Rc<Animation>, force: bool) {
         if self.done() || force {
             *self = anim.start();
         }
     }
+    /// Like `play`, but instead of snapping to `anim`, flicker between the
+    /// frame we're on now and `anim` for `fade` ticks.  `anim` starts
+    /// playing right away; the old frame just shows through every other
+    /// tick.  A `fade` of 0 is the same as `play`.
+    pub fn play_blended(&mut self, anim: &Rc<Animation>, force: bool, fade: usize) {
+        if self.done() || force {
+            let old = self.frame();
+            *self = anim.start();
+            if fade > 0 {
+                self.fade_from = Some(old);
+                self.fade_left = fade;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `n` one-tick frames, frame `i` at x = `i`
+    fn frames(n: usize) -> Vec<(Rect, usize)> {
+        (0..n)
+            .map(|i| {
+                (
+                    Rect {
+                        x: i as i32,
+                        y: 0,
+                        w: 1,
+                        h: 1,
+                    },
+                    1,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ping_pong_plays_back_and_forth() {
+        let anim = Rc::new(Animation::with_playback(frames(3), Playback::PingPong));
+        let mut state = anim.start();
+        let mut seen = vec![state.frame().x];
+        for _ in 0..5 {
+            state.tick();
+            seen.push(state.frame().x);
+        }
+        assert_eq!(seen, vec![0, 1, 2, 1, 0, 1]);
+    }
 }
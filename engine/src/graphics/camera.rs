@@ -1,3 +1,4 @@
+#[derive(Clone, Copy, Debug)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
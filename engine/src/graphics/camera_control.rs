@@ -2,6 +2,9 @@ use super::camera::Camera;
 use crate::logic::geom::*;
 use winit::event::*;
 
+/// Orbits the 3D camera around its target with the arrow keys: up and down
+/// move in and out, left and right circle around.  `State` feeds it window
+/// events through `State::input` and applies it every `State::update`.
 pub struct CameraController {
     speed: f32,
     is_forward_pressed: bool,
@@ -57,7 +60,6 @@ impl CameraController {
         }
     }
 
-    /*
     pub fn update_camera(&self, camera: &mut Camera) {
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
@@ -87,5 +89,5 @@ impl CameraController {
         if self.is_left_pressed {
             camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
         }
-    }*/
+    }
 }
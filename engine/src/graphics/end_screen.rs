@@ -0,0 +1,56 @@
+use super::screen::Screen;
+use crate::logic::{
+    state::GameState,
+    timer::{Timer, TimerMode},
+    types::{Rect, Rgba, Vec2i},
+};
+
+const PIP_SIZE: u16 = 6;
+const PIP_GAP: u16 = 4;
+
+/// Draw the win screen: a gold backdrop with a row of pips for every
+/// pickup collected on the way.
+pub fn draw_victory(screen: &mut Screen, state: &GameState) {
+    draw_end_screen(screen, state, Rgba(200, 160, 32, 255), Rgba(255, 240, 160, 255));
+}
+
+/// Draw the game-over screen, like `draw_victory` but in red.
+pub fn draw_game_over(screen: &mut Screen, state: &GameState) {
+    draw_end_screen(screen, state, Rgba(96, 16, 16, 255), Rgba(220, 96, 96, 255));
+}
+
+fn draw_end_screen(screen: &mut Screen, state: &GameState, back: Rgba, pips: Rgba) {
+    screen.clear(back);
+    let bounds = screen.bounds();
+    let (w, h) = screen.size();
+    // As many pips as fit on a centered row
+    let per_row = (w as u16 - PIP_GAP) / (PIP_SIZE + PIP_GAP);
+    let count = (state.collected as u16).min(per_row);
+    let row_w = count * (PIP_SIZE + PIP_GAP);
+    let x0 = bounds.x + (w as i32 - row_w as i32) / 2;
+    let y = bounds.y + h as i32 / 2 - PIP_SIZE as i32 / 2;
+    for i in 0..count {
+        screen.rect(
+            Rect {
+                x: x0 + (i * (PIP_SIZE + PIP_GAP)) as i32,
+                y,
+                w: PIP_SIZE,
+                h: PIP_SIZE,
+            },
+            pips,
+        );
+    }
+}
+
+/// Draw `timer` as minutes and seconds, e.g. 1:05, with its top left
+/// `offset` pixels from the top left of the screen.
+pub fn draw_timer(screen: &mut Screen, timer: &Timer, offset: Vec2i, col: Rgba) {
+    // Round countdowns up so the clock reads 0:00 only once time's up
+    let secs = match timer.mode {
+        TimerMode::Up => timer.seconds().floor(),
+        TimerMode::Down(..) => timer.seconds().ceil(),
+    } as u64;
+    let text = format!("{}:{:02}", secs / 60, secs % 60);
+    let bounds = screen.bounds();
+    screen.digits(&text, Vec2i(bounds.x + offset.0, bounds.y + offset.1), 6, col);
+}
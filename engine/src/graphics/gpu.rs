@@ -1,5 +1,7 @@
 use super::camera::Camera;
 use cgmath::*;
+
+use crate::logic::types::{Rect, Rgba};
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Uniforms {
@@ -28,8 +30,48 @@ impl Uniforms {
     }
 }
 
-pub const GAME_WIDTH: f32 = 480.0;
-pub const GAME_HEIGHT: f32 = 320.0;
+/// The virtual resolution a 2D game is laid out in, whatever the window
+/// size: sprite and tile positions are in these pixels.  Each game picks
+/// its own and hands it to `State::new`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GameSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl GameSize {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width as f32,
+            height: height as f32,
+        }
+    }
+    /// Where game pixel `(x, y)` lands in clip space, which runs from -1 to
+    /// 1 with y up.
+    pub fn to_clip(&self, x: f32, y: f32) -> [f32; 2] {
+        [x / self.width * 2.0 - 1.0, 1.0 - y / self.height * 2.0]
+    }
+    /// How big a `w` by `h` pixel sprite is in clip space.
+    pub fn scale(&self, w: f32, h: f32) -> [f32; 2] {
+        [w / self.width * 2.0, h / self.height * 2.0]
+    }
+    /// `inst` with its position and size moved from game pixels to clip
+    /// space.  Depth, texture coordinates and tint are left alone.
+    pub fn instance_to_clip(&self, inst: &SpriteInstance) -> SpriteInstance {
+        let [x, y] = self.to_clip(inst.pos[0], inst.pos[1]);
+        SpriteInstance {
+            pos: [x, y, inst.pos[2]],
+            size: self.scale(inst.size[0], inst.size[1]),
+            ..*inst
+        }
+    }
+    /// The clip space shift for a camera whose view starts at game pixel
+    /// `camera`; what the sprite shader subtracts from every position.
+    pub fn camera_to_clip(&self, camera: (f32, f32)) -> [f32; 2] {
+        // y is flipped going into clip space, so moving down is positive
+        self.scale(camera.0, -camera.1)
+    }
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -76,3 +118,109 @@ impl InstanceRaw {
         }
     }
 }
+
+/// Per-instance data for the sprite pipeline (shaders/sprites): where the
+/// quad goes, which part of the texture it shows, and a tint that multiplies
+/// the texel, e.g. to fade particles out.  `pos` is the top left corner and
+/// `size` the extent, both in game pixels; `GameSize::instance_to_clip`
+/// makes them what the shader wants, and `SpriteRenderer` does that for
+/// everything it draws, so no producer deals in clip space.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteInstance {
+    pub pos: [f32; 3],
+    pub size: [f32; 2],
+    pub tex_offset: [f32; 2],
+    pub tex_scale: [f32; 2],
+    pub tint: [f32; 4],
+}
+
+impl SpriteInstance {
+    /// Show `frame` of a texture that's `tex_size` pixels big, untinted.
+    pub fn new(pos: [f32; 3], size: [f32; 2], frame: Rect, tex_size: (f32, f32)) -> Self {
+        Self {
+            pos,
+            size,
+            tex_offset: [frame.x as f32 / tex_size.0, frame.y as f32 / tex_size.1],
+            tex_scale: [frame.w as f32 / tex_size.0, frame.h as f32 / tex_size.1],
+            tint: [1.0; 4],
+        }
+    }
+    pub fn with_tint(mut self, Rgba(r, g, b, a): Rgba) -> Self {
+        self.tint = [
+            r as f32 / 255.0,
+            g as f32 / 255.0,
+            b as f32 / 255.0,
+            a as f32 / 255.0,
+        ];
+        self
+    }
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        const LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SpriteInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            // Locations 0 and 1 are the SpriteVertex
+            attributes: &wgpu::vertex_attr_array![2 => Float3, 3 => Float2, 4 => Float2, 5 => Float2, 6 => Float4],
+        };
+        LAYOUT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_corners_land_on_clip_corners() {
+        let game = GameSize::new(320, 240);
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            w: 16,
+            h: 16,
+        };
+        // A sprite covering the whole game area covers all of clip space
+        let whole = SpriteInstance::new([0.0, 0.0, 0.5], [320.0, 240.0], frame, (16.0, 16.0));
+        let clip = game.instance_to_clip(&whole);
+        assert_eq!(clip.pos, [-1.0, 1.0, 0.5]);
+        assert_eq!(clip.size, [2.0, 2.0]);
+        assert_eq!(clip.tex_scale, whole.tex_scale);
+        // The quad hangs down and right from pos, so its far corner is at
+        // pos + size * (1, -1)
+        let tile = SpriteInstance::new([160.0, 120.0, 0.0], [32.0, 24.0], frame, (16.0, 16.0));
+        let clip = game.instance_to_clip(&tile);
+        assert_eq!(clip.pos[..2], [0.0, 0.0]);
+        assert_eq!(
+            [clip.pos[0] + clip.size[0], clip.pos[1] - clip.size[1]],
+            [0.2, -0.2]
+        );
+    }
+
+    #[test]
+    fn camera_shift_matches_moving_the_sprite() {
+        let game = GameSize::new(320, 240);
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            w: 1,
+            h: 1,
+        };
+        let at = |x, y| {
+            game.instance_to_clip(&SpriteInstance::new(
+                [x, y, 0.0],
+                [1.0, 1.0],
+                frame,
+                (1.0, 1.0),
+            ))
+            .pos
+        };
+        // The shader subtracts the camera shift, so a camera at (32, 24)
+        // puts a sprite at (64, 48) where (32, 24) would be without one
+        let cam = game.camera_to_clip((32.0, 24.0));
+        let shifted = at(64.0, 48.0);
+        let expected = at(32.0, 24.0);
+        assert!((shifted[0] - cam[0] - expected[0]).abs() < 1e-6);
+        assert!((shifted[1] - cam[1] - expected[1]).abs() < 1e-6);
+    }
+}
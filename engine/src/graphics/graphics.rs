@@ -1,4 +1,4 @@
-use std::{error::Error, iter, rc::Rc};
+use std::{collections::HashMap, error::Error, iter, rc::Rc};
 
 use wgpu::{
     BindGroupLayout, BlendFactor, BlendOperation, BlendState, CommandBuffer, SwapChainTexture,
@@ -8,22 +8,144 @@ use rand::Rng;
 
 // main.rs
 use wgpu::util::DeviceExt;
-use winit::window::Window;
+use winit::{event::WindowEvent, window::Window};
 
-use super::{camera::Camera, camera_control::CameraController, gpu::InstanceRaw, gpu::Uniforms, model, texture::{CpuTexture, TextureHandle}, vertex::SpriteVertex, vertex::Vertex};
-use crate::{graphics::model::DrawModel, logic::{geom::*, types::*}};
+use super::{camera::Camera, camera_control::CameraController, gpu::GameSize, gpu::InstanceRaw, gpu::SpriteInstance, gpu::Uniforms, model, screen::Framebuffer, sprites::{SpriteBatch, SpriteRenderer}, texture::{CpuTexture, TextureHandle}, vertex::SpriteVertex, vertex::Vertex};
+use crate::{graphics::model::DrawModel, logic::{collision::{self, Contacts, WallBreaker}, geom::*, types::*}};
+#[cfg(feature = "hot-reload")]
+use super::resources::Reloaded;
 
 const NUM_MARBLES: i32 = 10;
+// How big the camera is when it bumps into walls
+const PLAYER_RADIUS: f32 = 0.5;
 
 pub enum GraphicalDisplay {
     Gpu(State),
+    // Frames are drawn into the `Framebuffer` on the CPU; the `State` only
+    // puts them on screen
+    Cpu(State, Framebuffer),
 }
 
+impl GraphicalDisplay {
+    /// The wgpu state behind either kind of display.
+    pub fn state(&mut self) -> &mut State {
+        match self {
+            GraphicalDisplay::Gpu(state) | GraphicalDisplay::Cpu(state, _) => state,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum GraphicsMethod {
+    Cpu,
     OpenGL,
+    Vulkan,
     WGPUDefault,
 }
 
+impl GraphicsMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GraphicsMethod::Cpu => "cpu",
+            GraphicsMethod::OpenGL => "opengl",
+            GraphicsMethod::Vulkan => "vulkan",
+            GraphicsMethod::WGPUDefault => "default",
+        }
+    }
+    /// For clap's `Arg::validator`.
+    pub fn validate(name: String) -> Result<(), String> {
+        name.parse::<GraphicsMethod>().map(|_| ())
+    }
+}
+
+impl std::str::FromStr for GraphicsMethod {
+    type Err = String;
+    /// Parse a backend name as given on the command line or in settings,
+    /// ignoring case.
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "cpu" => Ok(GraphicsMethod::Cpu),
+            "gl" | "opengl" => Ok(GraphicsMethod::OpenGL),
+            "vulkan" => Ok(GraphicsMethod::Vulkan),
+            "wgpu" | "default" => Ok(GraphicsMethod::WGPUDefault),
+            _ => Err(format!(
+                "Invalid backend {:?}; options are cpu, opengl, vulkan, or default",
+                name
+            )),
+        }
+    }
+}
+
+/// Why a `State` couldn't be created.
+#[derive(Debug)]
+pub enum GraphicsError {
+    // The backend doesn't go through wgpu at all, e.g. a CPU `Headless`
+    NotGpu(GraphicsMethod),
+    // wgpu found no adapter for the backend, e.g. no Vulkan driver
+    NoAdapter(GraphicsMethod),
+    Device(wgpu::RequestDeviceError),
+}
+
+impl std::fmt::Display for GraphicsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphicsError::NotGpu(method) => {
+                write!(f, "The {} backend doesn't render through wgpu", method.name())
+            }
+            GraphicsError::NoAdapter(method) => write!(
+                f,
+                "No graphics adapter supports the {} backend; try another one with --backend (cpu, opengl, vulkan, or default)",
+                method.name()
+            ),
+            GraphicsError::Device(e) => write!(f, "Couldn't open the graphics device: {}", e),
+        }
+    }
+}
+
+impl Error for GraphicsError {}
+
+/// A sub-rectangle of the window, in pixels from the top left.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Viewport {
+    pub fn aspect(&self) -> f32 {
+        self.w as f32 / self.h.max(1) as f32
+    }
+    /// The biggest part of a `width` x `height` window with the same
+    /// aspect ratio as `game`, centered, with bars on the sides
+    /// (pillarbox) or top and bottom (letterbox) filling the rest.  Draw
+    /// clip-space sprites into this and they won't stretch.
+    pub fn letterbox(width: u32, height: u32, game: GameSize) -> Viewport {
+        let scale = (width as f32 / game.width).min(height as f32 / game.height);
+        let w = (game.width * scale) as u32;
+        let h = (game.height * scale) as u32;
+        Viewport {
+            x: (width - w) / 2,
+            y: (height - h) / 2,
+            w,
+            h,
+        }
+    }
+    /// Split a `width` x `height` window into `n` side-by-side columns.
+    pub fn columns(width: u32, height: u32, n: u32) -> Vec<Viewport> {
+        let w = width / n.max(1);
+        (0..n)
+            .map(|i| Viewport {
+                x: i * w,
+                y: 0,
+                w,
+                h: height,
+            })
+            .collect()
+    }
+}
+
 pub struct State {
     surface: wgpu::Surface,
     pub device: wgpu::Device,
@@ -31,6 +153,8 @@ pub struct State {
     sc_desc: wgpu::SwapChainDescriptor,
     pub swap_chain: wgpu::SwapChain,
     pub size: winit::dpi::PhysicalSize<u32>,
+    // The game's virtual resolution, which sprites are positioned in
+    pub game_size: GameSize,
     pub clear_color: wgpu::Color,
     render_pipeline: wgpu::RenderPipeline,
     pub camera: Camera,
@@ -47,13 +171,40 @@ pub struct State {
     #[allow(dead_code)]
     marbles_buffer: wgpu::Buffer,
     walls_buffer: wgpu::Buffer,
+    // How many walls fit in `walls_buffer` before it has to grow
+    walls_capacity: usize,
+    // How many walls are in `walls_buffer`, and whether `walls` has changed
+    // since it was written (a wall was added or broken)
+    walls_drawn: usize,
+    walls_dirty: bool,
+    contacts: Contacts,
+    // Where the camera was last step, to tell how fast it's moving
+    last_eye: Pos3,
     texture_bind_group_layout: BindGroupLayout,
     depth_texture: TextureHandle,
+    // For the 2D game's sprites, tiles and particles
+    pub sprites: SpriteRenderer,
+    // Where a CPU-drawn `Framebuffer` goes to be drawn over the game; made
+    // on first use
+    overlay: Option<Overlay>,
+    // Sprite textures already on the GPU, by the `CpuTexture` they came
+    // from; holding the `Rc` keeps its address from being reused
+    sprite_textures: HashMap<*const CpuTexture, (Rc<CpuTexture>, Rc<wgpu::BindGroup>)>,
+}
+
+struct Overlay {
+    texture: TextureHandle,
+    bind_group: wgpu::BindGroup,
+    size: (usize, usize),
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: &Window, render_mode: GraphicsMethod) -> Self {
+    pub async fn new(
+        window: &Window,
+        render_mode: GraphicsMethod,
+        game_size: GameSize,
+    ) -> Result<Self, GraphicsError> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -61,6 +212,10 @@ impl State {
         let backend = match render_mode {
             GraphicsMethod::WGPUDefault => wgpu::BackendBit::PRIMARY,
             GraphicsMethod::OpenGL => wgpu::BackendBit::GL,
+            GraphicsMethod::Vulkan => wgpu::BackendBit::VULKAN,
+            // Drawing happens on the CPU, but the frames still need a way
+            // onto the screen
+            GraphicsMethod::Cpu => wgpu::BackendBit::PRIMARY,
         };
         let instance = wgpu::Instance::new(backend);
         let surface = unsafe { instance.create_surface(window) };
@@ -70,7 +225,14 @@ impl State {
                 compatible_surface: Some(&surface),
             })
             .await
-            .unwrap();
+            .ok_or(GraphicsError::NoAdapter(render_mode))?;
+        let info = adapter.get_info();
+        log::info!(
+            "Using the {} backend on {} ({:?})",
+            render_mode.name(),
+            info.name,
+            info.backend
+        );
 
         let (device, queue) = adapter
             .request_device(
@@ -82,7 +244,7 @@ impl State {
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .map_err(GraphicsError::Device)?;
 
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
@@ -102,7 +264,7 @@ impl State {
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
                             view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
                         },
                         count: None,
                     },
@@ -180,7 +342,8 @@ impl State {
                 n: Vec3::new(0.0, 1.0, 0.0),
                 d: 0.0,
             },
-            distructable:false,
+            destructible: false,
+            destroyed: false,
         };
         let walls = vec![wall];
 
@@ -207,7 +370,13 @@ impl State {
             contents: bytemuck::cast_slice(&marbles_data),
             usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
         });
-        let wall_data = vec![wall.to_raw()];
+        let wall_data = walls
+            .iter()
+            .filter(|w| !w.destroyed)
+            .map(Wall::to_raw)
+            .collect::<Vec<_>>();
+        let walls_capacity = wall_data.len();
+        let walls_drawn = wall_data.len();
         let walls_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Walls Buffer"),
             contents: bytemuck::cast_slice(&wall_data),
@@ -290,7 +459,9 @@ impl State {
             },
         });
 
-        Self {
+        let sprites = SpriteRenderer::new(&device, sc_desc.format, game_size);
+
+        Ok(Self {
             surface,
             device,
             queue,
@@ -298,6 +469,7 @@ impl State {
             swap_chain,
             render_pipeline,
             size,
+            game_size,
             clear_color: wgpu::Color {
                 r: 0.0,
                 g: 0.0,
@@ -311,15 +483,22 @@ impl State {
             uniform_bind_group,
             texture_bind_group_layout,
             depth_texture,
+            sprites,
+            overlay: None,
+            sprite_textures: HashMap::new(),
             marbles,
             walls,
             marbles_buffer,
             walls_buffer,
+            walls_capacity,
+            walls_drawn,
+            walls_dirty: false,
+            contacts: Contacts::new(),
+            last_eye: camera.eye,
             marble_model,
             wall_model,
             g: 10.0,
-           
-        }
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -331,7 +510,36 @@ impl State {
             TextureHandle::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
     }
 
+    /// Let the camera controller see a window event.  Returns true if it
+    /// used the event, so the caller can skip handling it.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        self.camera_controller.process_events(event)
+    }
+
+    /// Step the scene: move the camera, drop the marbles and bounce them
+    /// off each other and the walls.  The camera stands in for the player,
+    /// so flying into a destructible wall fast enough breaks it too.
     pub fn update(&mut self) {
+        self.camera_controller.update_camera(&mut self.camera);
+        for marble in self.marbles.iter_mut() {
+            marble.update(self.g);
+        }
+        let mut broken = collision::update(&mut self.walls, &mut self.marbles, &mut self.contacts);
+        let player = Sphere {
+            c: self.camera.eye,
+            r: PLAYER_RADIUS,
+        };
+        let velocity = (self.camera.eye - self.last_eye) / DT;
+        self.last_eye = self.camera.eye;
+        broken.extend(collision::player_break_walls(&mut self.walls, &player, velocity));
+        for b in broken.iter() {
+            match b.by {
+                WallBreaker::Marble(m) => log::debug!("Marble {} broke wall {}", m, b.wall),
+                WallBreaker::Player => log::debug!("Player broke wall {}", b.wall),
+            }
+        }
+        // Broken walls stop being drawn from the next frame
+        self.walls_dirty |= !broken.is_empty();
         self.uniforms.update_view_proj(&self.camera);
         self.queue.write_buffer(
             &self.uniform_buffer,
@@ -340,74 +548,286 @@ impl State {
         );
     }
 
+    pub fn walls(&self) -> &[Wall] {
+        &self.walls
+    }
+
+    /// Add a wall to the scene; it's drawn from the next frame on.
+    pub fn add_wall(&mut self, wall: Wall) {
+        self.walls.push(wall);
+        self.walls_dirty = true;
+    }
+
+    /// Replace every wall in the scene, e.g. with the sides of a room.
+    pub fn set_walls(&mut self, walls: Vec<Wall>) {
+        self.walls = walls;
+        self.walls_dirty = true;
+    }
+
+    /// The whole window as a viewport.
+    pub fn viewport(&self) -> Viewport {
+        Viewport {
+            x: 0,
+            y: 0,
+            w: self.size.width,
+            h: self.size.height,
+        }
+    }
+
+    /// The part of the window the 2D game shows in at its own aspect
+    /// ratio; see `Viewport::letterbox`.
+    pub fn game_viewport(&self) -> Viewport {
+        Viewport::letterbox(self.size.width, self.size.height, self.game_size)
+    }
+
     pub fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
-        // Update buffers based on dynamics
-        self.queue.write_buffer(
-            &self.walls_buffer,
-            0,
-            bytemuck::cast_slice(&vec![self.walls[0].to_raw()]),
-        );
+        let view = (self.camera, self.viewport());
+        self.render_views(&[view])
+    }
+
+    /// Draw the scene once per `(camera, viewport)` pair, each camera into
+    /// its own part of the window, e.g. one per player for split-screen.
+    /// Each camera's aspect ratio is set to match its viewport.
+    pub fn render_views(&mut self, views: &[(Camera, Viewport)]) -> Result<(), wgpu::SwapChainError> {
+        // Update buffers based on dynamics; walls only change when one is
+        // added or broken
+        if self.walls_dirty {
+            let walls_data = self
+                .walls
+                .iter()
+                .filter(|w| !w.destroyed)
+                .map(Wall::to_raw)
+                .collect::<Vec<_>>();
+            if walls_data.len() > self.walls_capacity {
+                // Out of room; make a new buffer with space to spare
+                self.walls_capacity = walls_data.len().next_power_of_two();
+                self.walls_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Walls Buffer"),
+                    size: (self.walls_capacity * std::mem::size_of::<InstanceRaw>())
+                        as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+                    mapped_at_creation: false,
+                });
+            }
+            self.queue
+                .write_buffer(&self.walls_buffer, 0, bytemuck::cast_slice(&walls_data));
+            self.walls_drawn = walls_data.len();
+            self.walls_dirty = false;
+        }
         // TODO avoid reallocating every frame
         let marbles_data = self.marbles.iter().map(Marble::to_raw).collect::<Vec<_>>();
         self.queue
             .write_buffer(&self.marbles_buffer, 0, bytemuck::cast_slice(&marbles_data));
-        self.uniforms.update_view_proj(&self.camera);
-        self.queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.uniforms]),
-        );
 
         let frame = self.swap_chain.get_current_frame()?.output;
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
-                    attachment: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-
-            render_pass.set_vertex_buffer(1, self.marbles_buffer.slice(..));
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.marble_model,
-                0..self.marbles.len() as u32,
-                &self.uniform_bind_group,
+        for (vi, (camera, viewport)) in views.iter().enumerate() {
+            let mut camera = *camera;
+            camera.aspect = viewport.aspect();
+            self.uniforms.update_view_proj(&camera);
+            // Every view shares one uniform buffer, so each one gets its own
+            // submit; the write lands after the previous view is drawn.
+            self.queue.write_buffer(
+                &self.uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[self.uniforms]),
             );
-            render_pass.set_vertex_buffer(1, self.walls_buffer.slice(..));
-            render_pass.draw_model_instanced(&self.wall_model, 0..1, &self.uniform_bind_group);
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+            {
+                // Only the first view clears; the rest draw over it
+                let first = vi == 0;
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                        attachment: &frame.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: if first {
+                                wgpu::LoadOp::Clear(wgpu::Color {
+                                    r: 0.1,
+                                    g: 0.2,
+                                    b: 0.3,
+                                    a: 1.0,
+                                })
+                            } else {
+                                wgpu::LoadOp::Load
+                            },
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: Some(
+                        wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                            attachment: &self.depth_texture.view,
+                            depth_ops: Some(wgpu::Operations {
+                                load: if first {
+                                    wgpu::LoadOp::Clear(1.0)
+                                } else {
+                                    wgpu::LoadOp::Load
+                                },
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        },
+                    ),
+                });
+                render_pass.set_viewport(
+                    viewport.x as f32,
+                    viewport.y as f32,
+                    viewport.w as f32,
+                    viewport.h as f32,
+                    0.0,
+                    1.0,
+                );
+                render_pass.set_scissor_rect(viewport.x, viewport.y, viewport.w, viewport.h);
+
+                render_pass.set_vertex_buffer(1, self.marbles_buffer.slice(..));
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.draw_model_instanced(
+                    &self.marble_model,
+                    0..self.marbles.len() as u32,
+                    &self.uniform_bind_group,
+                );
+                render_pass.set_vertex_buffer(1, self.walls_buffer.slice(..));
+                render_pass.draw_model_instanced(
+                    &self.wall_model,
+                    0..self.walls_drawn as u32,
+                    &self.uniform_bind_group,
+                );
+            }
+            self.queue.submit(iter::once(encoder.finish()));
         }
 
-        self.queue.submit(iter::once(encoder.finish()));
+        Ok(())
+    }
 
+    /// Draw a frame of the 2D game: `batches` in order over `clear_color`,
+    /// then `overlay` stretched over all of it, letterboxed into
+    /// `game_viewport` so sprites keep their shape at any window size.
+    /// The overlay is drawn on the CPU, the game's size: the HUD and menus,
+    /// or for the CPU backend the whole frame.
+    pub fn render_sprites(
+        &mut self,
+        batches: &[SpriteBatch],
+        overlay: Option<&Framebuffer>,
+    ) -> Result<(), wgpu::SwapChainError> {
+        let frame = self.swap_chain.get_current_frame()?.output;
+        let viewport = self.game_viewport();
+        self.sprites.draw(
+            &self.device,
+            &self.queue,
+            &frame.view,
+            viewport,
+            Some(self.clear_color),
+            batches,
+        );
+        if let Some(fb) = overlay {
+            self.draw_overlay(&frame.view, viewport, fb);
+        }
         Ok(())
     }
 
+    /// `texture` ready for a `SpriteBatch`, uploaded the first time it's
+    /// asked for.  Textures are told apart by `Rc`, so share the one from
+    /// `Resources` rather than loading copies.
+    pub fn sprite_texture(
+        &mut self,
+        texture: &Rc<CpuTexture>,
+    ) -> Result<Rc<wgpu::BindGroup>, std::boxed::Box<dyn Error>> {
+        if let Some((_, bind_group)) = self.sprite_textures.get(&Rc::as_ptr(texture)) {
+            return Ok(bind_group.clone());
+        }
+        let bind_group = Rc::new(self.sprites.texture(&self.device, &self.queue, texture, "sprite")?);
+        self.sprite_textures
+            .insert(Rc::as_ptr(texture), (texture.clone(), bind_group.clone()));
+        Ok(bind_group)
+    }
+
+    /// Upload textures `Resources::reload_changed` loaded again, if the old
+    /// ones are up here.  Sprites drawn with the old `Rc` show the new image
+    /// too, so nothing breaks before the game hands out the new one.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_textures(&mut self, reloaded: &[Reloaded]) {
+        for r in reloaded {
+            let stale = match self.sprite_textures.get(&Rc::as_ptr(&r.old)) {
+                Some((_, bind_group)) => bind_group.clone(),
+                None => continue,
+            };
+            let bind_group = match self.sprites.texture(&self.device, &self.queue, &r.new, "sprite") {
+                Ok(bind_group) => Rc::new(bind_group),
+                Err(e) => {
+                    log::warn!("Couldn't upload {}: {}", r.path.display(), e);
+                    continue;
+                }
+            };
+            // Textures from earlier reloads of the same file share the
+            // stale bind group, and move on with it
+            for (_, shared) in self.sprite_textures.values_mut() {
+                if Rc::ptr_eq(shared, &stale) {
+                    *shared = bind_group.clone();
+                }
+            }
+            self.sprite_textures
+                .insert(Rc::as_ptr(&r.new), (r.new.clone(), bind_group));
+        }
+    }
+
+    fn draw_overlay(&mut self, target: &wgpu::TextureView, viewport: Viewport, fb: &Framebuffer) {
+        if self.overlay.as_ref().map_or(true, |o| o.size != fb.size()) {
+            let texture = match fb.create_texture(&self.device, &self.queue) {
+                Ok(texture) => texture,
+                Err(e) => {
+                    log::error!("{}", e);
+                    return;
+                }
+            };
+            let bind_group = self
+                .sprites
+                .bind_texture(&self.device, &texture, "framebuffer");
+            self.overlay = Some(Overlay {
+                texture,
+                bind_group,
+                size: fb.size(),
+            });
+        }
+        let overlay = self.overlay.as_ref().unwrap();
+        fb.upload(&self.queue, &overlay.texture);
+        let (w, h) = fb.size();
+        let whole = Rect {
+            x: 0,
+            y: 0,
+            w: w as u16,
+            h: h as u16,
+        };
+        let game = self.game_size;
+        let inst = SpriteInstance::new(
+            [0.0, 0.0, 0.0],
+            [game.width, game.height],
+            whole,
+            (w as f32, h as f32),
+        );
+        // Covers the screen whatever the camera is doing
+        let (camera, zoom) = (self.sprites.camera, self.sprites.zoom);
+        self.sprites.camera = (0.0, 0.0);
+        self.sprites.zoom = 1.0;
+        self.sprites.draw(
+            &self.device,
+            &self.queue,
+            target,
+            viewport,
+            None,
+            &[SpriteBatch {
+                texture: &overlay.bind_group,
+                instances: &[inst],
+            }],
+        );
+        self.sprites.camera = camera;
+        self.sprites.zoom = zoom;
+    }
+
     pub fn clear_screen(
         &mut self,
     ) -> Result<(CommandBuffer, SwapChainTexture), wgpu::SwapChainError> {
@@ -446,3 +866,28 @@ impl State {
         self.resize(self.size);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_names_parse_in_any_case() {
+        assert_eq!("CPU".parse(), Ok(GraphicsMethod::Cpu));
+        assert_eq!("OpenGL".parse(), Ok(GraphicsMethod::OpenGL));
+        assert_eq!("vulkan".parse(), Ok(GraphicsMethod::Vulkan));
+        assert!("metal".parse::<GraphicsMethod>().is_err());
+    }
+
+    #[test]
+    fn letterbox_keeps_the_game_aspect() {
+        let game = GameSize::new(320, 240);
+        // Too wide: bars on the sides
+        let wide = Viewport::letterbox(1920, 1080, game);
+        assert_eq!(wide, Viewport { x: 240, y: 0, w: 1440, h: 1080 });
+        // Too tall: bars top and bottom
+        let tall = Viewport::letterbox(640, 960, game);
+        assert_eq!(tall, Viewport { x: 0, y: 240, w: 640, h: 480 });
+        assert!((tall.aspect() - game.width / game.height).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,271 @@
+use std::iter;
+
+use image::RgbaImage;
+
+use super::{
+    gpu::GameSize,
+    graphics::{GraphicsError, GraphicsMethod, Viewport},
+    sprites::{SpriteBatch, SpriteRenderer},
+    texture::padded_bytes_per_row,
+};
+
+// The format we render into; matches what image::RgbaImage stores
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A GPU device with an offscreen render target instead of a window's
+/// swap chain, so the render path can be run from tests.  Render into
+/// `view()` like you would into a swap chain frame (or draw sprites with
+/// `render_sprites`, just like `State` does), then `read_pixels`.
+pub struct Headless {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    target: wgpu::Texture,
+    view: wgpu::TextureView,
+    // Where pixels get copied to so the CPU can read them
+    readback: wgpu::Buffer,
+    width: u32,
+    height: u32,
+}
+
+impl Headless {
+    pub async fn new(
+        width: u32,
+        height: u32,
+        render_mode: GraphicsMethod,
+    ) -> Result<Self, GraphicsError> {
+        let backend = match render_mode {
+            GraphicsMethod::WGPUDefault => wgpu::BackendBit::PRIMARY,
+            GraphicsMethod::OpenGL => wgpu::BackendBit::GL,
+            GraphicsMethod::Vulkan => wgpu::BackendBit::VULKAN,
+            GraphicsMethod::Cpu => return Err(GraphicsError::NotGpu(render_mode)),
+        };
+        let instance = wgpu::Instance::new(backend);
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+            })
+            .await
+            .ok_or(GraphicsError::NoAdapter(render_mode))?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::default(),
+                    label: None,
+                },
+                None, // Trace path
+            )
+            .await
+            .map_err(GraphicsError::Device)?;
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("headless_target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("headless_readback"),
+            size: (padded_bytes_per_row(width) * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Ok(Self {
+            device,
+            queue,
+            target,
+            view,
+            readback,
+            width,
+            height,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        FORMAT
+    }
+
+    /// The view to use as a render pass's color attachment.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// A sprite renderer that can draw into `view()`.
+    pub fn sprite_renderer(&self, game_size: GameSize) -> SpriteRenderer {
+        SpriteRenderer::new(&self.device, FORMAT, game_size)
+    }
+
+    /// Draw `batches` over `clear` the way `State::render_sprites` draws a
+    /// window's frame, letterboxed to the game's aspect ratio.
+    pub fn render_sprites(
+        &self,
+        sprites: &mut SpriteRenderer,
+        batches: &[SpriteBatch],
+        clear: wgpu::Color,
+    ) {
+        let viewport = Viewport::letterbox(self.width, self.height, sprites.game_size);
+        sprites.draw(
+            &self.device,
+            &self.queue,
+            &self.view,
+            viewport,
+            Some(clear),
+            batches,
+        );
+    }
+
+    /// Copy the render target back to the CPU.  Waits for all submitted
+    /// work to finish first.
+    pub async fn read_pixels(&self) -> RgbaImage {
+        let row = padded_bytes_per_row(self.width);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.target,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.readback,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: row,
+                    rows_per_image: self.height,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            },
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        mapped.await.expect("Couldn't map the readback buffer");
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            // Drop the padding wgpu needs at the end of each row
+            for y in 0..self.height {
+                let start = (y * row) as usize;
+                pixels.extend_from_slice(&data[start..start + (self.width * 4) as usize]);
+            }
+        }
+        self.readback.unmap();
+        RgbaImage::from_raw(self.width, self.height, pixels).unwrap()
+    }
+}
+
+/// How many pixels differ between `actual` and `reference` by more than
+/// `tolerance` in some channel?  Images of different sizes differ everywhere.
+pub fn image_diff(actual: &RgbaImage, reference: &RgbaImage, tolerance: u8) -> usize {
+    if actual.dimensions() != reference.dimensions() {
+        return (actual.width() * actual.height()).max(reference.width() * reference.height())
+            as usize;
+    }
+    actual
+        .pixels()
+        .zip(reference.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(ca, cb)| (*ca as i16 - *cb as i16).abs() > tolerance as i16)
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{gpu::SpriteInstance, screen::Framebuffer, texture::CpuTexture};
+    use crate::logic::types::{Rect, Rgba, Vec2i};
+    use futures::executor::block_on;
+
+    // Machines without a GPU (or a software one) skip these rather than fail
+    fn headless(width: u32, height: u32) -> Option<Headless> {
+        match block_on(Headless::new(width, height, GraphicsMethod::WGPUDefault)) {
+            Ok(gpu) => Some(gpu),
+            Err(GraphicsError::NoAdapter(_)) => {
+                eprintln!("No graphics adapter; skipping");
+                None
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn sprites_render_like_the_cpu_draws_them() {
+        let gpu = match headless(16, 16) {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        // Every texel different, so a frame that's off by one shows up
+        let sheet = CpuTexture::new(RgbaImage::from_fn(8, 4, |x, y| {
+            image::Rgba([x as u8 * 32, y as u8 * 64, 128, 255])
+        }));
+        let frame = |x| Rect {
+            x,
+            y: 0,
+            w: 4,
+            h: 4,
+        };
+        let frames = [(frame(4), Vec2i(2, 3)), (frame(0), Vec2i(9, 10))];
+
+        let mut fb = Framebuffer::new(16, 16);
+        {
+            let mut screen = fb.screen(Vec2i(0, 0));
+            screen.clear(Rgba(0, 0, 255, 255));
+            for (frame, to) in frames.iter() {
+                screen.bitblt(&sheet, *frame, *to);
+            }
+        }
+        let reference = RgbaImage::from_raw(16, 16, fb.buffer().to_vec()).unwrap();
+
+        let mut sprites = gpu.sprite_renderer(GameSize::new(16, 16));
+        let texture = sprites
+            .texture(&gpu.device, &gpu.queue, &sheet, "sheet")
+            .unwrap();
+        let instances: Vec<SpriteInstance> = frames
+            .iter()
+            .map(|(frame, Vec2i(x, y))| {
+                SpriteInstance::new(
+                    [*x as f32, *y as f32, 0.0],
+                    [frame.w as f32, frame.h as f32],
+                    *frame,
+                    (8.0, 4.0),
+                )
+            })
+            .collect();
+        gpu.render_sprites(
+            &mut sprites,
+            &[SpriteBatch {
+                texture: &texture,
+                instances: &instances,
+            }],
+            wgpu::Color::BLUE,
+        );
+        let actual = block_on(gpu.read_pixels());
+
+        assert_eq!(image_diff(&actual, &reference, 2), 0);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+
+// How long a file has to sit still before it counts as changed, so an
+// editor's save (often several writes) only reloads once
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches asset files and reports which ones changed on disk, for
+/// reloading art and levels without restarting.  Development only: build
+/// with `--features hot-reload`.
+pub struct HotReload {
+    // Stops watching when dropped
+    watcher: RecommendedWatcher,
+    events: Receiver<DebouncedEvent>,
+    // Directories being watched.  Watching the file itself would lose track
+    // of it when an editor saves by renaming a new file over the old one.
+    dirs: HashSet<PathBuf>,
+    // Canonical path of each watched file to the path it was asked for by
+    files: HashMap<PathBuf, PathBuf>,
+}
+
+impl HotReload {
+    pub fn new() -> notify::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        Ok(Self {
+            watcher: Watcher::new(tx, DEBOUNCE)?,
+            events,
+            dirs: HashSet::new(),
+            files: HashMap::new(),
+        })
+    }
+    /// Start watching the file at `path`.  Watching a file twice is fine.
+    pub fn watch(&mut self, path: impl AsRef<Path>) -> notify::Result<()> {
+        let path = path.as_ref();
+        let full = path.canonicalize()?;
+        if let Some(dir) = full.parent() {
+            if !self.dirs.contains(dir) {
+                self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                self.dirs.insert(dir.to_path_buf());
+            }
+        }
+        self.files.insert(full, path.to_path_buf());
+        Ok(())
+    }
+    /// Every watched file that changed since the last call, once each, as
+    /// the paths they were watched by.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec![];
+        for event in self.events.try_iter() {
+            let full = match event {
+                DebouncedEvent::Write(path) | DebouncedEvent::Create(path) => path,
+                DebouncedEvent::Rename(_, path) => path,
+                DebouncedEvent::Error(e, path) => {
+                    log::warn!("Watching {:?} failed: {}", path, e);
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(path) = self.files.get(&full) {
+                if !changed.contains(path) {
+                    changed.push(path.clone());
+                }
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,44 @@
+use super::screen::Screen;
+use crate::logic::types::Vec2i;
+
+/// A darkness overlay for the 2D renderer: everything is dimmed except
+/// around light sources, which fade smoothly out to the dark at their edge.
+/// Lights only last a frame; add them (at least one for the player) before
+/// every `apply`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Darkness {
+    // How much light gets through where there's no light source, 0 to 1
+    pub ambient: f32,
+    lights: Vec<(Vec2i, f32)>,
+}
+
+impl Darkness {
+    pub fn new(ambient: f32) -> Self {
+        Self {
+            ambient,
+            lights: vec![],
+        }
+    }
+    /// Light up a circle of `radius` around `pos` (in world coordinates)
+    /// for this frame.
+    pub fn add_light_2d(&mut self, pos: Vec2i, radius: f32) {
+        self.lights.push((pos, radius));
+    }
+    /// How lit `pos` is, from `ambient` to 1.
+    pub fn brightness(&self, Vec2i(x, y): Vec2i) -> f32 {
+        self.lights
+            .iter()
+            .map(|(Vec2i(lx, ly), r)| {
+                let (dx, dy) = ((x - lx) as f32, (y - ly) as f32);
+                // Radial gradient: full light at the center, none at the edge
+                1.0 - (dx * dx + dy * dy).sqrt() / r.max(1.0)
+            })
+            .fold(self.ambient, f32::max)
+    }
+    /// Darken everything already drawn on `screen`, then forget this
+    /// frame's lights.
+    pub fn apply(&mut self, screen: &mut Screen) {
+        screen.shade(|pos| self.brightness(pos));
+        self.lights.clear();
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::{
+    resources::{LoadingTexture, Resources},
+    screen::Screen,
+    texture::CpuTexture,
+};
+use crate::logic::types::{Rect, Rgba};
+
+const BAR_H: u16 = 8;
+// Bar width as a fraction of the screen's
+const BAR_W: f32 = 0.6;
+
+/// Everything a game loads before its first real frame, decoded on worker
+/// threads so the window can draw a progress bar in the meantime.  Queue
+/// assets with `texture`, call `poll` and `draw_loading` every frame, and
+/// once `poll` says it's done go on to the menu and `take` what loaded.
+pub struct Preloader {
+    textures: HashMap<&'static str, LoadingTexture>,
+    // Assets whose loading is over, successfully or not
+    done: usize,
+}
+
+impl Preloader {
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            done: 0,
+        }
+    }
+    /// Start loading the image at `path`, to `take` as `name` later.
+    pub fn texture(&mut self, rsrc: &Resources, name: &'static str, path: impl AsRef<Path>) {
+        self.textures.insert(name, rsrc.load_texture_async(path));
+    }
+    /// Check on the workers; true once everything is loaded.
+    pub fn poll(&mut self) -> bool {
+        self.done = self
+            .textures
+            .values_mut()
+            .map(|t| t.poll())
+            .filter(|&ready| ready)
+            .count();
+        self.done == self.textures.len()
+    }
+    /// How much has loaded, from 0 to 1, as of the last `poll`.
+    pub fn progress(&self) -> f32 {
+        if self.textures.is_empty() {
+            1.0
+        } else {
+            self.done as f32 / self.textures.len() as f32
+        }
+    }
+    /// The texture queued as `name`, or the placeholder if it didn't load.
+    /// Panics if nothing was queued as `name`.
+    pub fn take(&mut self, name: &str) -> Rc<CpuTexture> {
+        self.textures
+            .remove(name)
+            .unwrap_or_else(|| panic!("No texture {} was preloaded", name))
+            .get()
+    }
+}
+
+impl Default for Preloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Draw the loading screen: a bar across the middle, filled `progress`
+/// (0 to 1) of the way.
+pub fn draw_loading(screen: &mut Screen, progress: f32) {
+    screen.clear(Rgba(16, 16, 24, 255));
+    let bounds = screen.bounds();
+    let (w, h) = screen.size();
+    let bar_w = (w as f32 * BAR_W) as u16;
+    let outline = Rect {
+        x: bounds.x + (w as i32 - bar_w as i32) / 2,
+        y: bounds.y + (h as i32 - BAR_H as i32) / 2,
+        w: bar_w,
+        h: BAR_H,
+    };
+    screen.rect(outline, Rgba(64, 64, 80, 255));
+    let filled = Rect {
+        w: (bar_w as f32 * progress.max(0.0).min(1.0)) as u16,
+        ..outline
+    };
+    screen.rect(filled, Rgba(200, 200, 240, 255));
+}
@@ -1,10 +1,18 @@
 pub mod animation;
 pub mod camera;
 pub mod camera_control;
+pub mod end_screen;
 pub mod gpu;
 pub mod graphics;
+pub mod headless;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod lighting;
+pub mod loading;
 pub mod model;
+pub mod particles;
 pub mod resources;
 pub mod screen;
+pub mod sprites;
 pub mod texture;
 pub mod vertex;
@@ -79,7 +79,15 @@ impl Model {
         for mat in obj_materials {
             let diffuse_path = mat.diffuse_texture;
             let diffuse_texture =
-                texture::TextureHandle::load(device, queue, containing_folder.join(diffuse_path)).unwrap().0;
+                texture::TextureHandle::load(
+                    device,
+                    queue,
+                    containing_folder.join(diffuse_path),
+                    wgpu::FilterMode::Linear,
+                    true,
+                )
+                .unwrap()
+                .0;
 
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout,
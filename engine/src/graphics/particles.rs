@@ -0,0 +1,199 @@
+use rand::Rng;
+
+use super::gpu::SpriteInstance;
+use crate::logic::types::{Rect, Rgba};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Particle {
+    pos: (f32, f32),
+    vel: (f32, f32),
+    // Frames lived so far
+    age: usize,
+}
+
+/// Spits out short-lived sprites: dust when landing, sparks on a hit, or a
+/// burst when something dies.  Call `update` once a frame and draw
+/// `instances` with a `SpriteRenderer`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParticleEmitter {
+    pub pos: (f32, f32),
+    // Particles per frame; fractions carry over, so 0.25 is one every four
+    // frames.  0 for an emitter that only does bursts.
+    pub rate: f32,
+    // Every new particle gets `velocity` plus up to `spread` in each
+    // direction on each axis
+    pub velocity: (f32, f32),
+    pub spread: f32,
+    // Added to every particle's y velocity each frame
+    pub gravity: f32,
+    // Frames before a particle disappears
+    pub lifetime: usize,
+    pub color: Rgba,
+    // Fade alpha out over each particle's lifetime?
+    pub fade: bool,
+    pub size: (f32, f32),
+    // What part of the texture to draw each particle with
+    pub frame: Rect,
+    particles: Vec<Particle>,
+    // Fraction of a particle owed from previous frames
+    owed: f32,
+}
+
+impl ParticleEmitter {
+    pub fn new(pos: (f32, f32), frame: Rect) -> Self {
+        Self {
+            pos,
+            rate: 0.0,
+            velocity: (0.0, 0.0),
+            spread: 1.0,
+            gravity: 0.0,
+            lifetime: 30,
+            color: Rgba(255, 255, 255, 255),
+            fade: true,
+            size: (frame.w as f32, frame.h as f32),
+            frame,
+            particles: vec![],
+            owed: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Spawn `count` particles at `pos` right now, e.g. for an explosion.
+    pub fn emit_burst(&mut self, pos: (f32, f32), count: usize, rng: &mut impl Rng) {
+        for _ in 0..count {
+            self.spawn(pos, rng);
+        }
+    }
+
+    /// Age and move every particle, drop the expired ones, and spawn this
+    /// frame's share of `rate`.
+    pub fn update(&mut self, rng: &mut impl Rng) {
+        let (lifetime, gravity) = (self.lifetime, self.gravity);
+        self.particles.retain(|p| p.age < lifetime);
+        for p in self.particles.iter_mut() {
+            p.age += 1;
+            p.vel.1 += gravity;
+            p.pos.0 += p.vel.0;
+            p.pos.1 += p.vel.1;
+        }
+        self.owed += self.rate;
+        while self.owed >= 1.0 {
+            self.owed -= 1.0;
+            self.spawn(self.pos, rng);
+        }
+    }
+
+    fn spawn(&mut self, pos: (f32, f32), rng: &mut impl Rng) {
+        let vel = if self.spread > 0.0 {
+            (
+                self.velocity.0 + rng.gen_range(-self.spread, self.spread),
+                self.velocity.1 + rng.gen_range(-self.spread, self.spread),
+            )
+        } else {
+            self.velocity
+        };
+        self.particles.push(Particle { pos, vel, age: 0 });
+    }
+
+    /// One sprite instance per live particle, tinted by `color` and faded
+    /// by age if `fade` is set.  `tex_size` is the size of the texture
+    /// `frame` is on.  Positions are in game pixels, like the emitter's;
+    /// the renderer takes them to clip space.
+    pub fn instances(&self, tex_size: (f32, f32)) -> impl Iterator<Item = SpriteInstance> + '_ {
+        self.particles.iter().map(move |p| {
+            let Rgba(r, g, b, a) = self.color;
+            // Textures are premultiplied, so fading scales every channel
+            let left = if self.fade {
+                1.0 - p.age as f32 / self.lifetime.max(1) as f32
+            } else {
+                1.0
+            };
+            let fade = |c: u8| (c as f32 * left) as u8;
+            SpriteInstance::new(
+                [p.pos.0, p.pos.1, 0.0],
+                [self.size.0, self.size.1],
+                self.frame,
+                tex_size,
+            )
+            .with_tint(Rgba(fade(r), fade(g), fade(b), fade(a)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, StdRng};
+
+    fn emitter() -> ParticleEmitter {
+        ParticleEmitter::new(
+            (0.0, 0.0),
+            Rect {
+                x: 0,
+                y: 0,
+                w: 2,
+                h: 2,
+            },
+        )
+    }
+
+    #[test]
+    fn fractional_rates_carry_over() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let mut particles = ParticleEmitter {
+            rate: 0.25,
+            lifetime: 100,
+            ..emitter()
+        };
+        for _ in 0..3 {
+            particles.update(&mut rng);
+        }
+        assert!(particles.is_empty());
+        particles.update(&mut rng);
+        assert_eq!(particles.len(), 1);
+        for _ in 0..4 {
+            particles.update(&mut rng);
+        }
+        assert_eq!(particles.len(), 2);
+    }
+
+    #[test]
+    fn particles_last_lifetime_frames() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let mut particles = ParticleEmitter {
+            lifetime: 3,
+            ..emitter()
+        };
+        particles.emit_burst((0.0, 0.0), 5, &mut rng);
+        for _ in 0..3 {
+            particles.update(&mut rng);
+        }
+        assert_eq!(particles.len(), 5);
+        particles.update(&mut rng);
+        assert!(particles.is_empty());
+    }
+
+    #[test]
+    fn gravity_accelerates_particles() {
+        let mut rng = StdRng::from_seed([0; 32]);
+        let mut particles = ParticleEmitter {
+            velocity: (2.0, 0.0),
+            spread: 0.0,
+            gravity: 1.0,
+            ..emitter()
+        };
+        particles.emit_burst((10.0, 10.0), 1, &mut rng);
+        particles.update(&mut rng);
+        assert_eq!(particles.particles[0].pos, (12.0, 11.0));
+        particles.update(&mut rng);
+        assert_eq!(particles.particles[0].pos, (14.0, 13.0));
+        assert_eq!(particles.particles[0].vel, (2.0, 2.0));
+    }
+}
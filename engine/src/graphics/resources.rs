@@ -1,13 +1,325 @@
+use crate::audio::audio::SoundBuffer;
+use crate::config::CONTENT_DIR_VAR;
+#[cfg(feature = "hot-reload")]
+use crate::graphics::hot_reload::HotReload;
 use crate::graphics::texture::CpuTexture;
-use std::path::Path;
+use image::{Rgba, RgbaImage};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-pub struct Resources();
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Side of the checkerboard shown in place of textures still loading
+const PLACEHOLDER_SZ: u32 = 16;
+
+/// Why an asset file couldn't be loaded.  Both kinds name the full path
+/// that was tried.
+#[derive(Debug)]
+pub enum AssetError {
+    // Nothing there (or it can't be opened)
+    Missing { path: PathBuf, source: io::Error },
+    // Something there, but it doesn't decode
+    Bad { path: PathBuf, reason: String },
+}
+
+impl AssetError {
+    pub fn path(&self) -> &Path {
+        match self {
+            AssetError::Missing { path, .. } | AssetError::Bad { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Missing { path, source } => write!(
+                f,
+                "Couldn't open {}: {}\n\
+                 Assets are loaded from the game's content directory; run the game \
+                 from its own directory or set {} to where the assets are.",
+                path.display(),
+                source,
+                CONTENT_DIR_VAR
+            ),
+            AssetError::Bad { path, reason } => {
+                write!(f, "Couldn't read {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl Error for AssetError {}
+
+pub struct Resources {
+    // Relative asset paths are relative to this, e.g. `config::content_root`
+    root: PathBuf,
+    // Textures and sounds are decoded once and shared after that
+    textures: TextureCache,
+    sounds: RefCell<HashMap<PathBuf, Rc<SoundBuffer>>>,
+    // Stands in for textures that aren't loaded yet
+    placeholder: Rc<CpuTexture>,
+}
+
+/// A texture `Resources::reload_changed` loaded again, and the one it
+/// replaces.
+#[cfg(feature = "hot-reload")]
+pub struct Reloaded {
+    pub path: PathBuf,
+    pub old: Rc<CpuTexture>,
+    pub new: Rc<CpuTexture>,
+}
 
 impl Resources {
+    /// Resources loaded relative to the working directory.  Prefer
+    /// `with_root`, so the game doesn't care where it's run from.
     pub fn new() -> Self {
-        Self()
+        Self::with_root(PathBuf::new())
+    }
+    /// Resources with relative paths resolved against `root`.
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            textures: TextureCache::default(),
+            sounds: RefCell::new(HashMap::new()),
+            placeholder: Rc::new(placeholder()),
+        }
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Where the asset at `p` is: `p` itself if it's absolute, else `p`
+    /// under the root.
+    pub fn resolve(&self, p: impl AsRef<Path>) -> PathBuf {
+        self.root.join(p)
+    }
+    pub fn load_texture(&self, p: impl AsRef<Path>) -> Result<Rc<CpuTexture>, AssetError> {
+        let path = self.resolve(p);
+        if let Some(tex) = self.textures.get(&path) {
+            return Ok(tex);
+        }
+        let tex = CpuTexture::with_file(&path)?;
+        Ok(self.textures.insert(path, tex))
+    }
+    /// Watch every texture loaded through here, before or after, for
+    /// changes on disk; see `reload_changed`.
+    #[cfg(feature = "hot-reload")]
+    pub fn watch_for_changes(&self) -> notify::Result<()> {
+        *self.textures.hot_reload.borrow_mut() = Some(HotReload::new()?);
+        for path in self.textures.textures.borrow().keys() {
+            self.textures.watch(path);
+        }
+        Ok(())
     }
-    pub fn load_texture(&self, p: impl AsRef<Path>) -> Rc<CpuTexture> {
-        Rc::new(CpuTexture::with_file(p.as_ref()))
+    /// Load again every texture that changed on disk since the last call.
+    /// Textures are shared, so anything holding the old one (a `Tileset`,
+    /// the GPU's copy; see `State::reload_textures`) has to be handed the
+    /// new one; the cache has it from now on.  A file that doesn't decode
+    /// (say, caught half written) keeps the old texture.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_changed(&self) -> Vec<Reloaded> {
+        let changed = match self.textures.hot_reload.borrow_mut().as_mut() {
+            Some(hot_reload) => hot_reload.changed(),
+            None => return vec![],
+        };
+        let mut textures = self.textures.textures.borrow_mut();
+        let mut reloaded = vec![];
+        for path in changed {
+            match CpuTexture::with_file(&path) {
+                Ok(tex) => {
+                    log::info!("Reloaded {}", path.display());
+                    let new = Rc::new(tex);
+                    // Only cached textures are watched, so there's always
+                    // an old one
+                    if let Some(old) = textures.insert(path.clone(), Rc::clone(&new)) {
+                        reloaded.push(Reloaded { path, old, new });
+                    }
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+        reloaded
+    }
+    /// Start decoding the image at `p` on a worker thread, so big images
+    /// don't freeze the window.  The result shows a placeholder until the
+    /// texture is ready, and goes in the cache once it is, the same as
+    /// `load_texture`'s.
+    pub fn load_texture_async(&self, p: impl AsRef<Path>) -> LoadingTexture {
+        let path = self.resolve(p);
+        let load = match self.textures.get(&path) {
+            Some(tex) => Load::Ready(tex),
+            None => {
+                let (tx, rx) = mpsc::channel();
+                let worker_path = path.clone();
+                thread::spawn(move || {
+                    // Nobody's waiting any more if the handle was dropped
+                    let _ = tx.send(CpuTexture::with_file(&worker_path));
+                });
+                Load::Pending(rx)
+            }
+        };
+        LoadingTexture {
+            load,
+            path,
+            cache: self.textures.clone(),
+            placeholder: Rc::clone(&self.placeholder),
+        }
+    }
+    pub fn placeholder(&self) -> &Rc<CpuTexture> {
+        &self.placeholder
+    }
+    pub fn load_sound(&self, p: impl AsRef<Path>) -> Result<Rc<SoundBuffer>, AssetError> {
+        let path = self.resolve(p);
+        if let Some(sound) = self.sounds.borrow().get(&path) {
+            return Ok(Rc::clone(sound));
+        }
+        let sound = Rc::new(SoundBuffer::with_file(&path)?);
+        self.sounds.borrow_mut().insert(path, Rc::clone(&sound));
+        Ok(sound)
+    }
+}
+
+// A magenta and black checkerboard, hard to mistake for real art
+fn placeholder() -> CpuTexture {
+    let half = PLACEHOLDER_SZ / 2;
+    CpuTexture::new(RgbaImage::from_fn(PLACEHOLDER_SZ, PLACEHOLDER_SZ, |x, y| {
+        if (x / half + y / half) % 2 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    }))
+}
+
+// The texture cache, shared by `Resources` and the `LoadingTexture`s it
+// hands out so they can fill it in when they finish
+#[derive(Clone, Default)]
+struct TextureCache {
+    textures: Rc<RefCell<HashMap<PathBuf, Rc<CpuTexture>>>>,
+    // Watches every cached texture once `watch_for_changes` turns it on
+    #[cfg(feature = "hot-reload")]
+    hot_reload: Rc<RefCell<Option<HotReload>>>,
+}
+
+impl TextureCache {
+    fn get(&self, path: &Path) -> Option<Rc<CpuTexture>> {
+        self.textures.borrow().get(path).cloned()
+    }
+    // Cache `tex` as the texture at `path` and share it, unless another
+    // load got there first, in which case that one's shared instead
+    fn insert(&self, path: PathBuf, tex: CpuTexture) -> Rc<CpuTexture> {
+        if let Some(cached) = self.get(&path) {
+            return cached;
+        }
+        #[cfg(feature = "hot-reload")]
+        self.watch(&path);
+        let tex = Rc::new(tex);
+        self.textures.borrow_mut().insert(path, Rc::clone(&tex));
+        tex
+    }
+    #[cfg(feature = "hot-reload")]
+    fn watch(&self, path: &Path) {
+        if let Some(hot_reload) = self.hot_reload.borrow_mut().as_mut() {
+            if let Err(e) = hot_reload.watch(path) {
+                log::warn!("Can't watch {} for changes: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+enum Load {
+    Pending(Receiver<Result<CpuTexture, AssetError>>),
+    Ready(Rc<CpuTexture>),
+    // Logged when it happened; the placeholder stays up for good
+    Failed,
+}
+
+impl Load {
+    // What the worker sent for `path`, or `None` if it died without
+    // sending anything
+    fn finished(
+        result: Option<Result<CpuTexture, AssetError>>,
+        path: &Path,
+        cache: &TextureCache,
+    ) -> Load {
+        match result {
+            Some(Ok(tex)) => Load::Ready(cache.insert(path.to_path_buf(), tex)),
+            Some(Err(e)) => {
+                log::error!("{}", e);
+                Load::Failed
+            }
+            None => {
+                log::error!("Texture loader thread died");
+                Load::Failed
+            }
+        }
+    }
+}
+
+/// A texture from `Resources::load_texture_async`.
+pub struct LoadingTexture {
+    load: Load,
+    path: PathBuf,
+    cache: TextureCache,
+    placeholder: Rc<CpuTexture>,
+}
+
+impl LoadingTexture {
+    /// Pick up the texture if the worker has finished with it.  Returns
+    /// whether loading is over, one way or the other.
+    pub fn poll(&mut self) -> bool {
+        if let Load::Pending(rx) = &self.load {
+            self.load = match rx.try_recv() {
+                Ok(result) => Load::finished(Some(result), &self.path, &self.cache),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => Load::finished(None, &self.path, &self.cache),
+            };
+        }
+        true
+    }
+    pub fn is_ready(&self) -> bool {
+        matches!(self.load, Load::Ready(_))
+    }
+    /// The texture if it's loaded, and the placeholder if not (yet).
+    pub fn get(&mut self) -> Rc<CpuTexture> {
+        self.poll();
+        match &self.load {
+            Load::Ready(tex) => Rc::clone(tex),
+            _ => Rc::clone(&self.placeholder),
+        }
+    }
+    /// Block until loading is over, then `get`.
+    pub fn wait(mut self) -> Rc<CpuTexture> {
+        if let Load::Pending(rx) = &self.load {
+            self.load = Load::finished(rx.recv().ok(), &self.path, &self.cache);
+        }
+        self.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn async_loads_share_the_cache() {
+        let dir = std::env::temp_dir().join(format!("resources-async-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RgbaImage::new(3, 5).save(dir.join("sheet.png")).unwrap();
+        let rsrc = Resources::with_root(&dir);
+
+        let tex = rsrc.load_texture_async("sheet.png").wait();
+        assert_eq!(tex.size(), (3, 5));
+        assert!(Rc::ptr_eq(&tex, &rsrc.load_texture("sheet.png").unwrap()));
+        // Already cached, so there's nothing to wait for
+        let mut again = rsrc.load_texture_async("sheet.png");
+        assert!(again.is_ready());
+        assert!(Rc::ptr_eq(&tex, &again.get()));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
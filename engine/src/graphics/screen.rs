@@ -1,6 +1,6 @@
 use crate::logic::types::{Rect, Rgba, Vec2i};
 
-use super::texture::CpuTexture;
+use super::texture::{pad_rows, CpuTexture, TextureHandle};
 
 // We can pull in definitions from elsewhere in the crate!
 pub struct Screen<'fb> {
@@ -128,7 +128,18 @@ impl<'fb> Screen<'fb> {
         }
     }
     // Bitblt too begins with a translation
-    pub fn bitblt(&mut self, src: &CpuTexture, from: Rect, Vec2i(to_x, to_y): Vec2i) {
+    pub fn bitblt(&mut self, src: &CpuTexture, from: Rect, to: Vec2i) {
+        self.bitblt_tinted(src, from, to, Rgba(255, 255, 255, 255));
+    }
+    // Same as bitblt, but every channel of the source is scaled by `tint`
+    // first (e.g. to flash the player while they're invulnerable)
+    pub fn bitblt_tinted(
+        &mut self,
+        src: &CpuTexture,
+        from: Rect,
+        Vec2i(to_x, to_y): Vec2i,
+        tint: Rgba,
+    ) {
         let (tw, th) = src.size();
         assert!(0 <= from.x);
         assert!(from.x < tw as i32);
@@ -172,6 +183,12 @@ impl<'fb> Screen<'fb> {
                 .chunks_exact(depth);
             // Composite over, assume premultiplied rgba8888
             for (to, from) in to_cols.zip(from_cols) {
+                let from = [
+                    (from[0] as u16 * tint.0 as u16 / 255) as u8,
+                    (from[1] as u16 * tint.1 as u16 / 255) as u8,
+                    (from[2] as u16 * tint.2 as u16 / 255) as u8,
+                    (from[3] as u16 * tint.3 as u16 / 255) as u8,
+                ];
                 let ta = to[3] as f32 / 255.0;
                 let fa = from[3] as f32 / 255.0;
                 for i in 0..3 {
@@ -181,4 +198,211 @@ impl<'fb> Screen<'fb> {
             }
         }
     }
+    // Post-processing: scale each pixel's color by `f` of where it is in
+    // the world, e.g. to darken everything outside a light
+    pub fn shade(&mut self, f: impl Fn(Vec2i) -> f32) {
+        let (w, depth) = (self.width, self.depth);
+        let Vec2i(px, py) = self.position;
+        for (i, p) in self.framebuffer.chunks_exact_mut(depth).enumerate() {
+            let k = f(Vec2i(px + (i % w) as i32, py + (i / w) as i32)).max(0.0).min(1.0);
+            for c in p[0..3].iter_mut() {
+                *c = (*c as f32 * k) as u8;
+            }
+        }
+    }
+    /// Cover `bar` pixels at the top and bottom of the view with `col`, for
+    /// cutscenes.
+    pub fn letterbox(&mut self, bar: u16, col: Rgba) {
+        if bar == 0 {
+            return;
+        }
+        let view = self.bounds();
+        self.rect(Rect { h: bar, ..view }, col);
+        self.rect(
+            Rect {
+                y: view.y + view.h as i32 - bar as i32,
+                h: bar,
+                ..view
+            },
+            col,
+        );
+    }
+    /// Draw `text`'s digits and colons as seven-segment numerals with the
+    /// top left at `pos`; each digit is `scale` pixels wide and twice that
+    /// tall.  Anything else leaves a gap.  Returns the x just past the end.
+    pub fn digits(&mut self, text: &str, Vec2i(x, y): Vec2i, scale: u16, col: Rgba) -> i32 {
+        // Segments a-g as bits 0-6: top, top right, bottom right, bottom,
+        // bottom left, top left, middle
+        const SEGMENTS: [u8; 10] = [
+            0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f,
+        ];
+        let s = scale as i32;
+        let t = (scale / 4).max(1);
+        let ti = t as i32;
+        let mut x = x;
+        for ch in text.chars() {
+            match ch {
+                '0'..='9' => {
+                    let bits = SEGMENTS[ch as usize - '0' as usize];
+                    let horiz = |y| Rect { x, y, w: scale, h: t };
+                    let vert = |x, y| Rect { x, y, w: t, h: scale };
+                    let rects = [
+                        horiz(y),
+                        vert(x + s - ti, y),
+                        vert(x + s - ti, y + s),
+                        horiz(y + 2 * s - ti),
+                        vert(x, y + s),
+                        vert(x, y),
+                        horiz(y + s - ti / 2),
+                    ];
+                    for (i, r) in rects.iter().enumerate() {
+                        if bits & (1 << i) != 0 {
+                            self.rect(*r, col);
+                        }
+                    }
+                    x += s + ti * 2;
+                }
+                ':' => {
+                    self.rect(Rect { x, y: y + s / 2, w: t, h: t }, col);
+                    self.rect(Rect { x, y: y + s * 3 / 2 - ti, w: t, h: t }, col);
+                    x += ti * 3;
+                }
+                _ => x += s + ti * 2,
+            }
+        }
+        x
+    }
+    /// Draw `text` in a blocky 3x5 pixel font with the top left at `pos`,
+    /// each font pixel `scale` screen pixels square, so a line is
+    /// `5 * scale` tall.  Knows letters (either case) and digits; anything
+    /// else leaves a gap.  Returns the x just past the end.
+    pub fn text(&mut self, text: &str, Vec2i(x, y): Vec2i, scale: u16, col: Rgba) -> i32 {
+        let s = scale as i32;
+        let mut x = x;
+        for ch in text.chars() {
+            let glyph = match ch.to_ascii_uppercase() {
+                c @ 'A'..='Z' => Some(LETTERS[c as usize - 'A' as usize]),
+                c @ '0'..='9' => Some(NUMERALS[c as usize - '0' as usize]),
+                _ => None,
+            };
+            for (row, bits) in glyph.iter().flat_map(|g| g.iter()).enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.rect(
+                            Rect {
+                                x: x + column * s,
+                                y: y + row as i32 * s,
+                                w: scale,
+                                h: scale,
+                            },
+                            col,
+                        );
+                    }
+                }
+            }
+            x += 4 * s;
+        }
+        x
+    }
+}
+
+// `Screen::text`'s font: five rows of three pixels per glyph, the high bit
+// on the left
+#[rustfmt::skip]
+const LETTERS: [[u8; 5]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101], [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011], [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111], [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011], [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111], [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101], [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101], [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010], [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011], [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110], [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111], [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101], [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010], [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+#[rustfmt::skip]
+const NUMERALS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111], [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b110, 0b001, 0b010, 0b100, 0b111], [0b110, 0b001, 0b010, 0b001, 0b110],
+    [0b101, 0b101, 0b111, 0b001, 0b001], [0b111, 0b100, 0b110, 0b001, 0b110],
+    [0b011, 0b100, 0b111, 0b101, 0b111], [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111], [0b111, 0b101, 0b111, 0b001, 0b110],
+];
+
+/// The CPU renderer's framebuffer: an rgba8 image the size of the game that
+/// gets wrapped in a `Screen` to draw each frame, then uploaded to a GPU
+/// texture that's shown over the whole game viewport.  Everything that
+/// draws into a `Screen` (`Tilemap::draw`, sprites, HUD digits) works the
+/// same whichever backend ends up presenting it.
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pixels: vec![0; width * height * 4],
+            width,
+            height,
+        }
+    }
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+    pub fn buffer(&self) -> &[u8] {
+        &self.pixels
+    }
+    /// Draw into the framebuffer with the camera at `camera`.
+    pub fn screen(&mut self, camera: Vec2i) -> Screen<'_> {
+        Screen::wrap(&mut self.pixels, self.width, self.height, 4, camera)
+    }
+    /// A nearest-filtered texture to `upload` into, the same size as the
+    /// framebuffer.
+    pub fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<TextureHandle, Box<dyn std::error::Error>> {
+        let dims = (self.width as u32, self.height as u32);
+        let (tex, _) = TextureHandle::from_bytes(
+            device,
+            queue,
+            &self.pixels,
+            dims,
+            "framebuffer",
+            wgpu::FilterMode::Nearest,
+            false,
+        )?;
+        Ok(tex)
+    }
+    /// Copy this frame's pixels into `texture`, which came from
+    /// `create_texture`.
+    pub fn upload(&self, queue: &wgpu::Queue, texture: &TextureHandle) {
+        let dims = (self.width as u32, self.height as u32);
+        let (rows, bytes_per_row) = pad_rows(&self.pixels, dims);
+        queue.write_texture(
+            wgpu::TextureCopyView {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &rows,
+            wgpu::TextureDataLayout {
+                offset: 0,
+                bytes_per_row,
+                rows_per_image: dims.1,
+            },
+            wgpu::Extent3d {
+                width: dims.0,
+                height: dims.1,
+                depth: 1,
+            },
+        );
+    }
 }
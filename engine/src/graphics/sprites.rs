@@ -0,0 +1,322 @@
+use std::{iter, mem};
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    gpu::{GameSize, SpriteInstance},
+    graphics::Viewport,
+    texture::{CpuTexture, TextureHandle},
+    vertex::{SpriteVertex, Vertex},
+};
+
+// A unit quad hanging down and right from the origin, so an instance's
+// `pos` is its top left corner and `size` stretches it (clip space is y up)
+#[rustfmt::skip]
+const QUAD: [SpriteVertex; 6] = [
+    SpriteVertex { position: [0.0, 0.0, 0.0], tex_pos: [0.0, 0.0] },
+    SpriteVertex { position: [0.0, -1.0, 0.0], tex_pos: [0.0, 1.0] },
+    SpriteVertex { position: [1.0, -1.0, 0.0], tex_pos: [1.0, 1.0] },
+    SpriteVertex { position: [0.0, 0.0, 0.0], tex_pos: [0.0, 0.0] },
+    SpriteVertex { position: [1.0, -1.0, 0.0], tex_pos: [1.0, 1.0] },
+    SpriteVertex { position: [1.0, 0.0, 0.0], tex_pos: [1.0, 0.0] },
+];
+
+/// Some sprites that all come from one texture, e.g. a tilemap's tiles or
+/// a particle emitter's particles.
+pub struct SpriteBatch<'a> {
+    // From `SpriteRenderer::texture`
+    pub texture: &'a wgpu::BindGroup,
+    pub instances: &'a [SpriteInstance],
+}
+
+/// Draws sprites with the sprite shader (shaders/sprites) into any render
+/// target: a window's swap chain frame or a `Headless` view.
+pub struct SpriteRenderer {
+    pipeline: wgpu::RenderPipeline,
+    texture_layout: wgpu::BindGroupLayout,
+    quad: wgpu::Buffer,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    instances: wgpu::Buffer,
+    // How many instances fit in `instances` before it has to grow
+    capacity: usize,
+    // This frame's instances in clip space, kept around to save allocating
+    clip: Vec<SpriteInstance>,
+    pub game_size: GameSize,
+    // World position at the top left of the view
+    pub camera: (f32, f32),
+    // Game pixels per world unit, e.g. `logic::camera::Camera::zoom`
+    pub zoom: f32,
+}
+
+impl SpriteRenderer {
+    /// A renderer for targets of `format`, laying sprites out in `game_size`
+    /// pixels.
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, game_size: GameSize) -> Self {
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("sprite_texture_bind_group_layout"),
+        });
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("sprite_camera_bind_group_layout"),
+        });
+        // The shader reads a vec2, padded out to 16 bytes
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Camera Buffer"),
+            contents: bytemuck::cast_slice(&[0.0_f32; 4]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+            label: Some("sprite_camera_bind_group"),
+        });
+        let quad = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Quad Buffer"),
+            contents: bytemuck::cast_slice(&QUAD),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+        let capacity = 64;
+        let instances = Self::instance_buffer(device, capacity);
+
+        let shader_module =
+            device.create_shader_module(&wgpu::include_spirv!(env!("sprite_shader.spv")));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "main_vs",
+                buffers: &[SpriteVertex::desc(), SpriteInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "main_fs",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    // Textures are premultiplied, so color is already
+                    // scaled by alpha
+                    alpha_blend: wgpu::BlendState {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    color_blend: wgpu::BlendState {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Sprites can be flipped with a negative size
+                cull_mode: wgpu::CullMode::None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            // Sprites are drawn back to front in the order they're given
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        });
+
+        Self {
+            pipeline,
+            texture_layout,
+            quad,
+            camera_buffer,
+            camera_bind_group,
+            instances,
+            capacity,
+            clip: vec![],
+            game_size,
+            camera: (0.0, 0.0),
+            zoom: 1.0,
+        }
+    }
+
+    fn instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Instance Buffer"),
+            size: (capacity * mem::size_of::<SpriteInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Upload `texture` for use in a `SpriteBatch`.  Pixel art, so it's
+    /// sampled `Nearest`.
+    pub fn texture(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &CpuTexture,
+        label: &str,
+    ) -> Result<wgpu::BindGroup, Box<dyn std::error::Error>> {
+        let (w, h) = texture.size();
+        let (handle, _) = TextureHandle::from_bytes(
+            device,
+            queue,
+            texture.buffer(),
+            (w as u32, h as u32),
+            label,
+            wgpu::FilterMode::Nearest,
+            false,
+        )?;
+        Ok(self.bind_texture(device, &handle, label))
+    }
+
+    /// A `SpriteBatch` texture for something already on the GPU, e.g. a
+    /// `Framebuffer`'s texture.
+    pub fn bind_texture(
+        &self,
+        device: &wgpu::Device,
+        handle: &TextureHandle,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&handle.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&handle.sampler),
+                },
+            ],
+            label: Some(label),
+        })
+    }
+
+    /// Draw `batches` into `viewport` of `target`, in order, so later
+    /// batches go over earlier ones.  Instances are in game pixels, as
+    /// every producer (tiles, particles, projectiles, entities) makes them;
+    /// they're scaled by `zoom` and put in clip space here, which `viewport`
+    /// then maps onto the target, so give it the game's aspect ratio
+    /// (`Viewport::letterbox`) or sprites stretch.  Clears the whole target
+    /// first if `clear` is set, bars and all.
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &wgpu::TextureView,
+        viewport: Viewport,
+        clear: Option<wgpu::Color>,
+        batches: &[SpriteBatch],
+    ) {
+        let game = self.game_size;
+        let zoom = self.zoom;
+        self.clip.clear();
+        for batch in batches.iter() {
+            self.clip.extend(batch.instances.iter().map(|inst| {
+                let zoomed = SpriteInstance {
+                    pos: [inst.pos[0] * zoom, inst.pos[1] * zoom, inst.pos[2]],
+                    size: [inst.size[0] * zoom, inst.size[1] * zoom],
+                    ..*inst
+                };
+                game.instance_to_clip(&zoomed)
+            }));
+        }
+        if self.clip.len() > self.capacity {
+            // Out of room; make a new buffer with space to spare
+            self.capacity = self.clip.len().next_power_of_two();
+            self.instances = Self::instance_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&self.clip));
+        // Zooming about the world origin moves the camera along with it
+        let camera = game.camera_to_clip((self.camera.0 * zoom, self.camera.1 * zoom));
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[camera[0], camera[1], 0.0, 0.0]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Sprite Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Sprite Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: match clear {
+                            Some(color) => wgpu::LoadOp::Clear(color),
+                            None => wgpu::LoadOp::Load,
+                        },
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_viewport(
+                viewport.x as f32,
+                viewport.y as f32,
+                viewport.w as f32,
+                viewport.h as f32,
+                0.0,
+                1.0,
+            );
+            render_pass.set_scissor_rect(viewport.x, viewport.y, viewport.w, viewport.h);
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_vertex_buffer(0, self.quad.slice(..));
+            render_pass.set_vertex_buffer(1, self.instances.slice(..));
+            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            let mut start = 0;
+            for batch in batches.iter() {
+                let end = start + batch.instances.len() as u32;
+                render_pass.set_bind_group(0, batch.texture, &[]);
+                render_pass.draw(0..QUAD.len() as u32, start..end);
+                start = end;
+            }
+        }
+        queue.submit(iter::once(encoder.finish()));
+    }
+}
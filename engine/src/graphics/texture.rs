@@ -1,10 +1,45 @@
-use image::{self, GenericImageView, RgbaImage};
-use std::{env, error::Error, path::Path};
+use image::{self, GenericImageView, ImageError, RgbaImage};
+use std::{error::Error, path::Path};
 use wgpu::BindGroup;
 
+use crate::graphics::resources::AssetError;
 use crate::logic::types::Rect;
 
 pub type Dimensions = (u32, u32);
+
+/// Why a `TextureHandle` couldn't be created.
+#[derive(Debug)]
+pub enum TextureError {
+    // The bytes don't add up to a width x height rgba8 image
+    WrongSize {
+        label: String,
+        dimensions: Dimensions,
+        len: usize,
+    },
+}
+
+impl std::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureError::WrongSize {
+                label,
+                dimensions: (w, h),
+                len,
+            } => write!(
+                f,
+                "Texture {} should be {} bytes of rgba8 for {}x{} but has {}",
+                label,
+                4 * *w as usize * *h as usize,
+                w,
+                h,
+                len
+            ),
+        }
+    }
+}
+
+impl Error for TextureError {}
+
 pub struct TextureHandle {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -17,55 +52,103 @@ impl TextureHandle {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: &str,
+        filter: wgpu::FilterMode,
+        mipmaps: bool,
     ) -> Result<(Self, Dimensions), Box<dyn Error>> {
-        Self::from_bytes(device, queue, &img.to_bytes(), img.dimensions(), label)
+        // Grayscale, indexed, or 16-bit images all become rgba8 first
+        Self::from_bytes(
+            device,
+            queue,
+            &img.to_rgba8().into_raw(),
+            img.dimensions(),
+            label,
+            filter,
+            mipmaps,
+        )
     }
 
+    /// `filter` picks how the texture is sampled when scaled: `Nearest`
+    /// keeps pixel art crisp, `Linear` smooths backgrounds and 3D textures.
+    /// With `mipmaps` the full mip chain is built on the CPU so minified
+    /// textures don't shimmer; leave it off for pixel art.  `bytes` must
+    /// be rgba8, exactly `4 * width * height` long.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         dimensions: Dimensions,
         label: &str,
+        filter: wgpu::FilterMode,
+        mipmaps: bool,
     ) -> Result<(Self, Dimensions), Box<dyn Error>> {
+        if bytes.len() != 4 * dimensions.0 as usize * dimensions.1 as usize {
+            return Err(Box::new(TextureError::WrongSize {
+                label: label.to_string(),
+                dimensions,
+                len: bytes.len(),
+            }));
+        }
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth: 1,
         };
+        let mip_level_count = if mipmaps {
+            32 - dimensions.0.max(dimensions.1).max(1).leading_zeros()
+        } else {
+            1
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &bytes,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * dimensions.0,
-                rows_per_image: dimensions.1,
-            },
-            size,
-        );
+        let mut level = bytes.to_vec();
+        let mut level_dims = dimensions;
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (smaller, smaller_dims) = downsample(&level, level_dims);
+                level = smaller;
+                level_dims = smaller_dims;
+            }
+            let (rows, bytes_per_row) = pad_rows(&level, level_dims);
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &rows,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row,
+                    rows_per_image: level_dims.1,
+                },
+                wgpu::Extent3d {
+                    width: level_dims.0,
+                    height: level_dims.1,
+                    depth: 1,
+                },
+            );
+        }
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: if mip_level_count > 1 {
+                wgpu::FilterMode::Linear
+            } else {
+                filter
+            },
             ..Default::default()
         });
 
@@ -83,13 +166,15 @@ impl TextureHandle {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         path: P,
+        filter: wgpu::FilterMode,
+        mipmaps: bool,
     ) -> Result<(Self, Dimensions), Box<dyn Error>> {
         // Needed to appease the borrow checker
         let path_copy = path.as_ref().to_path_buf();
         let label = path_copy.to_str();
 
         let img = image::open(path)?;
-        Self::from_image(device, queue, &img, label.unwrap())
+        Self::from_image(device, queue, &img, label.unwrap(), filter, mipmaps)
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
@@ -140,6 +225,53 @@ impl TextureHandle {
     }
 }
 
+/// How many bytes a row of a `width`-pixel rgba8 image takes in a buffer
+/// used for texture copies.  wgpu wants every row of a buffer<->texture
+/// copy to start on a `COPY_BYTES_PER_ROW_ALIGNMENT` (256 byte) boundary,
+/// so anything not 64 pixels wide gets padding at the end of each row.
+/// Readback code has to skip that padding too.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (4 * width + align - 1) / align * align
+}
+
+/// Lay rgba8 `bytes` out with padded rows, returning them and the padded
+/// `bytes_per_row`.  Already-aligned images are just copied.
+pub fn pad_rows(bytes: &[u8], (w, h): Dimensions) -> (Vec<u8>, u32) {
+    let unpadded = 4 * w as usize;
+    let padded = padded_bytes_per_row(w) as usize;
+    if padded == unpadded {
+        return (bytes.to_vec(), padded as u32);
+    }
+    let mut out = vec![0; padded * h as usize];
+    for (src, dst) in bytes.chunks_exact(unpadded).zip(out.chunks_exact_mut(padded)) {
+        dst[..unpadded].copy_from_slice(src);
+    }
+    (out, padded as u32)
+}
+
+// Halve an rgba8 image by averaging each 2x2 block, the way the next mip
+// level is usually defined.  Sides that are already 1 pixel stay 1 pixel.
+fn downsample(bytes: &[u8], (w, h): Dimensions) -> (Vec<u8>, Dimensions) {
+    let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+    let (w, h) = (w as usize, h as usize);
+    let mut out = Vec::with_capacity(nw as usize * nh as usize * 4);
+    for y in 0..nh as usize {
+        for x in 0..nw as usize {
+            let (x0, y0) = ((x * 2).min(w - 1), (y * 2).min(h - 1));
+            let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+            for c in 0..4 {
+                let sum: u32 = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+                    .iter()
+                    .map(|&(sx, sy)| bytes[(sy * w + sx) * 4 + c] as u32)
+                    .sum();
+                out.push(((sum + 2) / 4) as u8);
+            }
+        }
+    }
+    (out, (nw, nh))
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: TextureHandle,
@@ -159,11 +291,18 @@ enum AlphaChannel {
     Last,
 }
 impl CpuTexture {
-    pub fn with_file(path: &Path) -> Self {
-        let pathbuf = env::current_dir().unwrap();
-        println!("The image is {}/{}", pathbuf.display(), path.display());
-
-        Self::new(image::open(path).expect("Couldn't load image").into_rgba8())
+    pub fn with_file(path: &Path) -> Result<Self, AssetError> {
+        match image::open(path) {
+            Ok(img) => Ok(Self::new(img.into_rgba8())),
+            Err(ImageError::IoError(source)) => Err(AssetError::Missing {
+                path: path.to_path_buf(),
+                source,
+            }),
+            Err(e) => Err(AssetError::Bad {
+                path: path.to_path_buf(),
+                reason: e.to_string(),
+            }),
+        }
     }
     pub fn new(image: RgbaImage) -> Self {
         let (width, height) = image.dimensions();
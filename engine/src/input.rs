@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit_input_helper::WinitInputHelper;
+
+use crate::config::Settings;
+
+/// Where game logic reads input from.  The live game uses a `LiveInput`;
+/// tests and replays use a `RecordedInput`.
+pub trait InputSource {
+    /// Did `key` go down this frame?
+    fn pressed(&self, key: VirtualKeyCode) -> bool;
+    /// Is `key` down at all?
+    fn held(&self, key: VirtualKeyCode) -> bool;
+    /// How far the mouse moved this frame, in pixels.
+    fn mouse_delta(&self) -> (f32, f32);
+    /// Did any key or button go down this frame?  For attract modes and
+    /// "press any key" screens; gamepad buttons should count too once
+    /// there are gamepads.
+    fn any_pressed(&self) -> bool;
+    /// Did the player try to close the window this frame?  Games decide
+    /// what that means, e.g. asking whether to really quit.
+    fn close_requested(&self) -> bool {
+        false
+    }
+}
+
+/// Input from the window: a `WinitInputHelper`, plus whether any key or
+/// mouse button went down this frame, which the helper can't tell us
+/// without asking about every key one at a time.
+pub struct LiveInput {
+    pub helper: WinitInputHelper,
+    any_pressed: bool,
+}
+
+impl LiveInput {
+    pub fn new() -> Self {
+        Self {
+            helper: WinitInputHelper::new(),
+            any_pressed: false,
+        }
+    }
+    /// Feed in an event; true once the frame's batch of events is done,
+    /// same as `WinitInputHelper::update`.
+    pub fn update<T>(&mut self, event: &Event<T>) -> bool {
+        match event {
+            Event::NewEvents(_) => self.any_pressed = false,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode,
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                // Held keys send presses over and over; only count the
+                // first one, while the helper still thinks the key is up
+                let repeat = virtual_keycode.map_or(false, |k| self.helper.key_held(k));
+                self.any_pressed |= !repeat;
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => self.any_pressed = true,
+            _ => {}
+        }
+        self.helper.update(event)
+    }
+}
+
+impl Default for LiveInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputSource for LiveInput {
+    fn pressed(&self, key: VirtualKeyCode) -> bool {
+        self.helper.key_pressed(key)
+    }
+    fn held(&self, key: VirtualKeyCode) -> bool {
+        self.helper.key_held(key)
+    }
+    fn mouse_delta(&self) -> (f32, f32) {
+        self.helper.mouse_diff()
+    }
+    fn any_pressed(&self) -> bool {
+        self.any_pressed
+    }
+    fn close_requested(&self) -> bool {
+        self.helper.quit()
+    }
+}
+
+/// One frame's worth of input.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RecordedFrame {
+    pub pressed: Vec<VirtualKeyCode>,
+    pub held: Vec<VirtualKeyCode>,
+    pub mouse_delta: (f32, f32),
+}
+
+/// Input played back from a list of frames.  Call `advance` once per
+/// simulated frame; past the end it reports no input at all.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RecordedInput {
+    frames: Vec<RecordedFrame>,
+    frame: usize,
+}
+
+impl RecordedInput {
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Self { frames, frame: 0 }
+    }
+    pub fn push(&mut self, frame: RecordedFrame) {
+        self.frames.push(frame);
+    }
+    /// Record what `source` says about `keys` this frame.
+    pub fn capture(&mut self, source: &dyn InputSource, keys: &[VirtualKeyCode]) {
+        self.push(RecordedFrame {
+            pressed: keys.iter().copied().filter(|k| source.pressed(*k)).collect(),
+            held: keys.iter().copied().filter(|k| source.held(*k)).collect(),
+            mouse_delta: source.mouse_delta(),
+        });
+    }
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+    /// Move on to the next frame.  Returns false once the recording is over.
+    pub fn advance(&mut self) -> bool {
+        self.frame += 1;
+        self.frame < self.frames.len()
+    }
+    pub fn rewind(&mut self) {
+        self.frame = 0;
+    }
+    fn current(&self) -> Option<&RecordedFrame> {
+        self.frames.get(self.frame)
+    }
+}
+
+impl InputSource for RecordedInput {
+    fn pressed(&self, key: VirtualKeyCode) -> bool {
+        self.current().map_or(false, |f| f.pressed.contains(&key))
+    }
+    fn held(&self, key: VirtualKeyCode) -> bool {
+        // A key that went down this frame is down
+        self.current()
+            .map_or(false, |f| f.held.contains(&key) || f.pressed.contains(&key))
+    }
+    fn mouse_delta(&self) -> (f32, f32) {
+        self.current().map_or((0.0, 0.0), |f| f.mouse_delta)
+    }
+    fn any_pressed(&self) -> bool {
+        self.current().map_or(false, |f| !f.pressed.is_empty())
+    }
+}
+
+/// Held-key repeat, like a text box: a key fires when it goes down, then
+/// again every `rate` frames once it's been held for `delay` frames.
+/// Remembers how long each key has been held, so call `key_repeat` for a
+/// key once every frame.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct KeyRepeat {
+    // Frames each key has been held since it went down
+    held: HashMap<VirtualKeyCode, usize>,
+}
+
+impl KeyRepeat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Should `code` fire this frame?
+    pub fn key_repeat(
+        &mut self,
+        input: &dyn InputSource,
+        code: VirtualKeyCode,
+        delay: usize,
+        rate: usize,
+    ) -> bool {
+        if input.pressed(code) {
+            self.held.insert(code, 0);
+            return true;
+        }
+        if !input.held(code) {
+            self.held.remove(&code);
+            return false;
+        }
+        let frames = self.held.entry(code).or_insert(0);
+        *frames += 1;
+        *frames >= delay && (*frames - delay) % rate.max(1) == 0
+    }
+}
+
+/// Which keys drive one player.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyBindings {
+    pub up: VirtualKeyCode,
+    pub down: VirtualKeyCode,
+    pub left: VirtualKeyCode,
+    pub right: VirtualKeyCode,
+    pub jump: VirtualKeyCode,
+    pub dash: VirtualKeyCode,
+    pub pause: VirtualKeyCode,
+}
+
+impl KeyBindings {
+    /// Player one: arrow keys, space to jump.
+    pub const ARROWS: KeyBindings = KeyBindings {
+        up: VirtualKeyCode::Up,
+        down: VirtualKeyCode::Down,
+        left: VirtualKeyCode::Left,
+        right: VirtualKeyCode::Right,
+        jump: VirtualKeyCode::Space,
+        dash: VirtualKeyCode::LShift,
+        pause: VirtualKeyCode::Escape,
+    };
+    /// Player two: WASD, G to jump and H to dash.
+    pub const WASD: KeyBindings = KeyBindings {
+        up: VirtualKeyCode::W,
+        down: VirtualKeyCode::S,
+        left: VirtualKeyCode::A,
+        right: VirtualKeyCode::D,
+        jump: VirtualKeyCode::G,
+        dash: VirtualKeyCode::H,
+        pause: VirtualKeyCode::Escape,
+    };
+    /// Player one's keys, with whatever `settings` rebinds.  Key names
+    /// that aren't keys keep the default.
+    pub fn from_settings(settings: &Settings) -> KeyBindings {
+        let mut keys = KeyBindings::ARROWS;
+        let mut actions: [(&str, &mut VirtualKeyCode); 7] = [
+            ("up", &mut keys.up),
+            ("down", &mut keys.down),
+            ("left", &mut keys.left),
+            ("right", &mut keys.right),
+            ("jump", &mut keys.jump),
+            ("dash", &mut keys.dash),
+            ("pause", &mut keys.pause),
+        ];
+        for (action, key) in actions.iter_mut() {
+            let name = match settings.binding(action) {
+                Some(name) => name,
+                None => continue,
+            };
+            match key_from_name(name) {
+                Some(code) => **key = code,
+                None => log::warn!("Can't bind {} to unknown key {:?}", action, name),
+            }
+        }
+        keys
+    }
+}
+
+// Keys that can be named in settings
+const NAMED_KEYS: [VirtualKeyCode; 55] = {
+    use VirtualKeyCode::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1,
+        Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Up, Down, Left, Right, Space, Return, Tab,
+        Back, Escape, LShift, RShift, LControl, RControl, LAlt, RAlt, Comma, Period, Slash,
+        Semicolon,
+    ]
+};
+
+/// The key called `name`, as winit spells it (e.g. "Space", "LShift",
+/// "Key1"), ignoring case.
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    NAMED_KEYS
+        .iter()
+        .copied()
+        .find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_rebind_player_one() {
+        let mut settings = Settings::default();
+        settings.set("bind.jump", "z");
+        settings.set("bind.dash", "NotAKey");
+        let keys = KeyBindings::from_settings(&settings);
+        assert_eq!(keys.jump, VirtualKeyCode::Z);
+        assert_eq!(keys.dash, KeyBindings::ARROWS.dash);
+        assert_eq!(keys.left, VirtualKeyCode::Left);
+    }
+
+    #[test]
+    fn key_repeat_waits_out_the_delay_then_fires_every_rate_frames() {
+        let key = VirtualKeyCode::Down;
+        let mut frames = vec![RecordedFrame {
+            pressed: vec![key],
+            ..RecordedFrame::default()
+        }];
+        frames.extend((0..8).map(|_| RecordedFrame {
+            held: vec![key],
+            ..RecordedFrame::default()
+        }));
+        // Let go for a frame, then press again
+        frames.push(RecordedFrame::default());
+        frames.push(RecordedFrame {
+            pressed: vec![key],
+            ..RecordedFrame::default()
+        });
+        let mut input = RecordedInput::new(frames);
+        let mut repeat = KeyRepeat::new();
+        let mut fired = vec![];
+        loop {
+            fired.push(repeat.key_repeat(&input, key, 3, 2));
+            if !input.advance() {
+                break;
+            }
+        }
+        let t = true;
+        let f = false;
+        assert_eq!(fired, vec![t, f, f, t, f, t, f, t, f, f, t]);
+    }
+}
@@ -1,111 +1,187 @@
 use std::error::Error;
+use std::sync::{Arc, Mutex};
 use wgpu::SwapChainError;
 
 use winit::event::Event;
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit::{event::WindowEvent, platform::run_return::EventLoopExtRunReturn};
-use winit_input_helper::WinitInputHelper;
+
+use audio::audio::SoundChannels;
+use config::Settings;
+use input::{InputSource, LiveInput};
+use schedule::{FrameLimiter, Scheduler, StepMode, Time};
 
 use graphics::{
-    graphics::{GraphicalDisplay, GraphicsMethod, State},
+    gpu::GameSize,
+    graphics::{GraphicalDisplay, GraphicsMethod},
     resources::Resources,
+    screen::Framebuffer,
 };
 
 pub mod audio;
+pub mod config;
+pub mod game;
 pub mod graphics;
+pub mod input;
 pub mod logic;
+pub mod net;
+pub mod schedule;
+pub mod util;
 
 const DT: f64 = 1.0 / 60.0;
 
 pub fn run<Rule, State>(
-    _screen_width: usize,
-    _screen_height: usize,
+    game_width: usize,
+    game_height: usize,
     window_builder: WindowBuilder,
     rsrc: Resources,
     mut rules: Rule,
     mut state: State,
     graphics_method: GraphicsMethod,
+    step_mode: StepMode,
+    // `sound` starts at the saved volume and mute; `pause_unfocused` stops
+    // updating (and suspends `sound`, if given) while the window is in the
+    // background, and `max_fps` caps how often frames are drawn
+    settings: &Settings,
+    sound: Option<Arc<Mutex<SoundChannels>>>,
     init: impl Fn(&Resources, &mut Rule, &mut GraphicalDisplay, &State) -> Result<(), Box<dyn Error>>
         + 'static,
     draw: impl Fn(&Resources, &Rule, &State, &mut GraphicalDisplay, usize) -> Result<(), SwapChainError>
         + 'static,
-    update: impl Fn(&mut Rule, &mut State, &WinitInputHelper, usize) -> bool + 'static,
+    // Returns true to quit.  Closing the window doesn't quit by itself;
+    // `update` sees `InputSource::close_requested` and decides
+    update: impl Fn(&mut Rule, &mut State, &dyn InputSource, Time) -> bool + 'static,
 ) {
-    /*
+    use futures::executor::block_on;
     use std::time::Instant;
 
+    // Our own info messages (which adapter got picked, reloaded assets)
+    // are worth seeing by default; everyone else's only from warnings up.
+    // A game that set up its own logger keeps it.
+    let _ = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("warn,engine=info"),
+    )
+    .try_init();
+
     let mut event_loop = EventLoop::new();
-    let mut input = WinitInputHelper::new();
+    let mut input = LiveInput::new();
     let window = window_builder.build(&event_loop).unwrap();
-    use futures::executor::block_on;
 
     // Since main can't be async, we're going to need to block
-    let mut render_target = match graphics_method {
-        _ => GraphicalDisplay::Gpu(block_on(new(&window, graphics_method))),
+    let mut render_target = match block_on(graphics::graphics::State::new(
+        &window,
+        graphics_method,
+        GameSize::new(game_width, game_height),
+    )) {
+        Ok(gpu_state) if graphics_method == GraphicsMethod::Cpu => {
+            GraphicalDisplay::Cpu(gpu_state, Framebuffer::new(game_width, game_height))
+        }
+        Ok(gpu_state) => GraphicalDisplay::Gpu(gpu_state),
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
     };
 
-    init(&rsrc, &mut rules, &mut render_target, &state).unwrap();
+    // Missing assets are the usual first-run failure; their errors say
+    // what's missing and where to put it, so show that and not a backtrace
+    if let Err(e) = init(&rsrc, &mut rules, &mut render_target, &state) {
+        eprintln!("{}", e);
+        return;
+    }
+    // Development builds pick up art edited on disk; see `reload_changed`
+    #[cfg(feature = "hot-reload")]
+    if let Err(e) = rsrc.watch_for_changes() {
+        log::warn!("Can't watch assets for changes: {}", e);
+    }
     // How many frames have we simulated?
     let mut frame_count: usize = 0;
-    // How many unsimulated frames have we saved up?
-    let mut available_time = 0.0;
+    // How many simulation steps to take each time around the loop
+    let mut scheduler = Scheduler::new(step_mode, DT);
     let mut since = Instant::now();
+    let mut limiter = FrameLimiter::new(settings.fps_cap());
+    let pause_unfocused = settings.pause_unfocused;
+    // Start at the volume the player left it at
+    if let Some(sound) = &sound {
+        let mut sound = sound.lock().unwrap();
+        sound.set_volume(settings.volume);
+        sound.set_muted(settings.muted);
+    }
+    // Set while the window is unfocused, if `pause_unfocused`
+    let mut paused = false;
     event_loop.run_return(|event, _, control_flow| {
         match event {
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                window_id,
+            } if window_id == window.id() && pause_unfocused => {
+                paused = !focused;
+                if let Some(sound) = &sound {
+                    sound.lock().unwrap().set_suspended(paused);
+                }
+                // Don't spin while there's nothing to do
+                *control_flow = if paused {
+                    ControlFlow::Wait
+                } else {
+                    ControlFlow::Poll
+                };
+            }
             // Handle window events
             Event::WindowEvent {
                 ref event,
                 window_id,
-            } if window_id == window.id() => match &mut render_target {
-                GraphicalDisplay::Gpu(gpu_state) => match event {
+            } if window_id == window.id() => {
+                let gpu_state = render_target.state();
+                match event {
                     WindowEvent::Resized(physical_size) => {
                         gpu_state.resize(*physical_size);
                     }
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                         gpu_state.resize(**new_inner_size);
                     }
-                    _ => {}
-                },
-            },
+                    event => {
+                        gpu_state.input(event);
+                    }
+                }
+            }
             // Draw new frame
             Event::RedrawRequested(_) => {
-                match (
-                    draw(&rsrc, &rules, &state, &mut render_target, frame_count),
-                    &mut render_target,
-                ) {
-                    (Ok(_), _) => {}
+                // Move the 3D camera along with its controller
+                match &mut render_target {
+                    GraphicalDisplay::Gpu(gpu_state) => gpu_state.update(),
+                    // No 3D scene to move
+                    GraphicalDisplay::Cpu(..) => {}
+                }
+                #[cfg(feature = "hot-reload")]
+                render_target.state().reload_textures(&rsrc.reload_changed());
+                match draw(&rsrc, &rules, &state, &mut render_target, frame_count) {
+                    Ok(_) => {}
                     // Recreate the swap_chain if lost
-                    (Err(wgpu::SwapChainError::Lost), GraphicalDisplay::Gpu(gpu_state)) => {
-                        gpu_state.recreate_swapchain()
-                    }
+                    Err(wgpu::SwapChainError::Lost) => render_target.state().recreate_swapchain(),
                     // The system is out of memory, we should probably quit
-                    (Err(wgpu::SwapChainError::OutOfMemory), GraphicalDisplay::Gpu(_gpu_state)) => {
-                        *control_flow = ControlFlow::Exit
-                    }
+                    Err(wgpu::SwapChainError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    (Err(e), _) => eprintln!("{:?}", e),
+                    Err(e) => eprintln!("{:?}", e),
                 }
-                available_time += since.elapsed().as_secs_f64();
             }
             _ => {}
         }
-        // Handle input events
-        if input.update(&event) {
-            // Close events
-            if input.quit() {
-                *control_flow = ControlFlow::Exit;
-                return;
-            }
+        // Handle input events; this is true once per batch of events, so
+        // the rest runs once per time around the loop
+        if !input.update(&event) {
+            return;
         }
-
-        // And the simulation "consumes" it
-        while available_time >= DT {
-            // Eat up one frame worth of time
-            available_time -= DT;
-
+        // The simulation "consumes" however much time the scheduler says
+        let elapsed = since.elapsed().as_secs_f64();
+        since = Instant::now();
+        // Time spent paused is dropped rather than caught up on afterwards
+        if paused {
+            return;
+        }
+        for _ in 0..scheduler.steps(elapsed) {
             // Exit if update says to quit
-            if update(&mut rules, &mut state, &input, frame_count) {
+            if update(&mut rules, &mut state, &input, Time::new(frame_count, DT)) {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
@@ -115,8 +191,14 @@ pub fn run<Rule, State>(
         }
         // Request redraw
         window.request_redraw();
-        // When did the last frame end?
-        since = Instant::now();
+        limiter.wait();
     });
-    */
+
+    // Tear down in order rather than whenever: the game and any sounds it
+    // holds, then the GPU state, then the window it was drawing to
+    drop(state);
+    drop(rules);
+    drop(sound);
+    drop(render_target);
+    drop(window);
 }
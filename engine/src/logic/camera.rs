@@ -0,0 +1,356 @@
+use crate::logic::types::{Rect, Vec2i};
+use crate::util::easing;
+
+/// How a `CameraPath` segment speeds up and slows down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Easing {
+    Linear,
+    // Start and end slowly
+    EaseInOut,
+}
+
+impl Easing {
+    /// How far along (0 to 1) a segment is at fraction `t` of its time.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            Easing::Linear => easing::linear(t),
+            Easing::EaseInOut => easing::smoothstep(t),
+        }
+    }
+}
+
+/// One stop on a `CameraPath`: where to center, how zoomed in to be, and
+/// how long to take getting there from the previous stop.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Keyframe {
+    pub center: Vec2i,
+    // Leave the zoom alone if None
+    pub zoom: Option<f32>,
+    pub frames: usize,
+    pub easing: Easing,
+}
+
+/// A scripted camera move for intros and transitions, ignoring the
+/// player.  Hand it to `Camera::play`; the first segment starts wherever
+/// the camera is.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    // Should the game ignore player input while this plays?
+    pub lock_input: bool,
+    // Height of the black bars at the top and bottom of the view while
+    // this plays, for a cinematic look; 0 for none
+    pub letterbox: u16,
+}
+
+impl CameraPath {
+    pub fn new(keyframes: Vec<Keyframe>) -> Self {
+        Self {
+            keyframes,
+            lock_input: true,
+            letterbox: 0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Playing {
+    path: CameraPath,
+    segment: usize,
+    // Frames into the current segment
+    time: usize,
+    // Where the current segment started: position and zoom
+    from: ((f32, f32), f32),
+}
+
+/// The 2D game's camera: the top left corner of the view, easing toward
+/// whatever it's following instead of snapping there every frame.  Put
+/// `position()` in `GameState::camera` to draw with it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Camera {
+    // Top left of the view, in world units; fractional so slow easing
+    // still gets somewhere
+    pos: (f32, f32),
+    // Size of the view on screen, in game pixels
+    pub view: (u16, u16),
+    // How much of the gap to the target is left after a frame: 0 snaps
+    // straight there, 0.9 drifts in slowly
+    pub smoothing: f32,
+    // Keep the view inside this rect, if set
+    pub bounds: Option<Rect>,
+    // How many game pixels a world unit takes up; above 1 zooms in, so
+    // the view covers less of the world
+    pub zoom: f32,
+    playing: Option<Playing>,
+}
+
+impl Camera {
+    pub fn new(view: (u16, u16)) -> Self {
+        Self {
+            pos: (0.0, 0.0),
+            view,
+            smoothing: 0.85,
+            bounds: None,
+            zoom: 1.0,
+            playing: None,
+        }
+    }
+    pub fn position(&self) -> Vec2i {
+        Vec2i(self.pos.0.round() as i32, self.pos.1.round() as i32)
+    }
+    /// How much of the world the view covers at the current zoom.
+    pub fn world_view(&self) -> (f32, f32) {
+        let zoom = self.zoom.max(f32::EPSILON);
+        (self.view.0 as f32 / zoom, self.view.1 as f32 / zoom)
+    }
+    /// The part of the world in view, e.g. to pick which tiles to draw.
+    pub fn view_rect(&self) -> Rect {
+        let Vec2i(x, y) = self.position();
+        let (w, h) = self.world_view();
+        Rect {
+            x,
+            y,
+            w: w.ceil() as u16,
+            h: h.ceil() as u16,
+        }
+    }
+    // Where the top left goes to center `target`, kept in bounds
+    fn centered_on(&self, Vec2i(x, y): Vec2i) -> (f32, f32) {
+        let (w, h) = self.world_view();
+        self.clamp((x as f32 - w / 2.0, y as f32 - h / 2.0))
+    }
+    fn clamp(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        match self.bounds {
+            Some(b) => {
+                // A view bigger than the bounds just sits at their top left
+                let (w, h) = self.world_view();
+                let max_x = (b.x as f32 + b.w as f32 - w).max(b.x as f32);
+                let max_y = (b.y as f32 + b.h as f32 - h).max(b.y as f32);
+                (x.max(b.x as f32).min(max_x), y.max(b.y as f32).min(max_y))
+            }
+            None => (x, y),
+        }
+    }
+    /// Ease a frame's worth toward centering on `target`.  While a path is
+    /// playing this advances the path instead, and following picks up
+    /// again from wherever the path ends.
+    pub fn follow(&mut self, target: Vec2i) {
+        if self.playing.is_some() {
+            self.advance_path();
+            return;
+        }
+        let (tx, ty) = self.centered_on(target);
+        let k = self.smoothing.max(0.0).min(1.0);
+        self.pos = (tx + (self.pos.0 - tx) * k, ty + (self.pos.1 - ty) * k);
+    }
+    /// Center on `target` right away, e.g. after loading a level or
+    /// respawning, so the camera doesn't sweep across the map.
+    pub fn snap_to(&mut self, target: Vec2i) {
+        self.pos = self.centered_on(target);
+    }
+    /// Start playing `path`, replacing any path already playing.
+    pub fn play(&mut self, path: CameraPath) {
+        self.playing = Some(Playing {
+            path,
+            segment: 0,
+            time: 0,
+            from: (self.pos, self.zoom),
+        });
+    }
+    /// Stop the current path where it is, e.g. when the player skips a
+    /// cutscene.
+    pub fn stop(&mut self) {
+        self.playing = None;
+    }
+    pub fn playing_path(&self) -> bool {
+        self.playing.is_some()
+    }
+    /// Should the game ignore player input this frame?
+    pub fn input_locked(&self) -> bool {
+        self.playing.as_ref().map_or(false, |p| p.path.lock_input)
+    }
+    /// Height of the letterbox bars to draw this frame.
+    pub fn letterbox(&self) -> u16 {
+        self.playing.as_ref().map_or(0, |p| p.path.letterbox)
+    }
+    fn advance_path(&mut self) {
+        let mut playing = match self.playing.take() {
+            Some(playing) => playing,
+            None => return,
+        };
+        let key = match playing.path.keyframes.get(playing.segment) {
+            Some(key) => *key,
+            None => return,
+        };
+        playing.time += 1;
+        let t = key
+            .easing
+            .apply(playing.time as f32 / key.frames.max(1) as f32);
+        let ((fx, fy), fzoom) = playing.from;
+        let tzoom = key.zoom.unwrap_or(fzoom);
+        self.zoom = fzoom + (tzoom - fzoom) * t;
+        // Zoom first, so the stop ends up centered at its own zoom
+        let (tx, ty) = self.centered_on(key.center);
+        self.pos = (fx + (tx - fx) * t, fy + (ty - fy) * t);
+        if playing.time >= key.frames {
+            playing.segment += 1;
+            playing.time = 0;
+            playing.from = (self.pos, self.zoom);
+        }
+        if playing.segment < playing.path.keyframes.len() {
+            self.playing = Some(playing);
+        }
+    }
+}
+
+/// Rooms for the camera, metroidvania style: instead of roaming the whole
+/// level, the view stays inside whichever region holds the player's center
+/// and moves on to the next one when they cross over.  Where regions
+/// overlap, the player keeps the room they're already in.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CameraRegions {
+    regions: Vec<Rect>,
+    // Index into regions of the room the camera is in
+    current: Option<usize>,
+    // Frames to slide between rooms; 0 cuts straight over
+    pub slide_frames: usize,
+}
+
+impl CameraRegions {
+    pub fn new(regions: Vec<Rect>) -> Self {
+        Self {
+            regions,
+            current: None,
+            slide_frames: 30,
+        }
+    }
+    pub fn regions(&self) -> &[Rect] {
+        &self.regions
+    }
+    /// The room the camera is in, if the player has been in one yet.
+    pub fn current(&self) -> Option<Rect> {
+        self.current.map(|i| self.regions[i])
+    }
+    /// Forget the current room, e.g. when loading a level, so the next
+    /// `update` cuts to the player's room instead of sliding there.
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+    /// Call once per frame with the player's rect, before
+    /// `Camera::follow`.  On entering a new room this bounds `camera` to it
+    /// and slides (or cuts) the view over; between rooms nothing changes.
+    /// Returns true on the frame the room changes.
+    pub fn update(&mut self, camera: &mut Camera, player: Rect) -> bool {
+        let center = Vec2i(player.x + player.w as i32 / 2, player.y + player.h as i32 / 2);
+        if self.current.map_or(false, |i| contains(self.regions[i], center)) {
+            return false;
+        }
+        let next = match self.regions.iter().position(|&r| contains(r, center)) {
+            Some(next) => next,
+            None => return false,
+        };
+        let first = self.current.is_none();
+        self.current = Some(next);
+        camera.bounds = Some(self.regions[next]);
+        if first || self.slide_frames == 0 {
+            camera.snap_to(center);
+        } else {
+            camera.play(CameraPath::new(vec![Keyframe {
+                center,
+                zoom: None,
+                frames: self.slide_frames,
+                easing: Easing::EaseInOut,
+            }]));
+        }
+        true
+    }
+}
+
+fn contains(r: Rect, Vec2i(x, y): Vec2i) -> bool {
+    r.x <= x && x < r.x + r.w as i32 && r.y <= y && y < r.y + r.h as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: u16, h: u16) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn zoom_shrinks_the_view_it_centers_and_clamps() {
+        let mut camera = Camera::new((100, 100));
+        camera.zoom = 2.0;
+        camera.snap_to(Vec2i(200, 200));
+        assert_eq!(camera.position(), Vec2i(175, 175));
+        assert_eq!(camera.view_rect(), rect(175, 175, 50, 50));
+        camera.bounds = Some(rect(0, 0, 300, 300));
+        camera.snap_to(Vec2i(290, 290));
+        assert_eq!(camera.position(), Vec2i(250, 250));
+    }
+
+    #[test]
+    fn path_eases_through_its_keyframes_then_follows_again() {
+        let mut camera = Camera::new((100, 100));
+        camera.smoothing = 0.0;
+        let mut path = CameraPath::new(vec![
+            Keyframe {
+                center: Vec2i(150, 50),
+                zoom: None,
+                frames: 10,
+                easing: Easing::Linear,
+            },
+            Keyframe {
+                center: Vec2i(50, 50),
+                zoom: Some(2.0),
+                frames: 4,
+                easing: Easing::EaseInOut,
+            },
+        ]);
+        path.letterbox = 12;
+        camera.play(path);
+        assert!(camera.input_locked());
+        assert_eq!(camera.letterbox(), 12);
+
+        // Whatever it's told to follow, the path wins while it plays
+        let player = Vec2i(1000, 1000);
+        for _ in 0..5 {
+            camera.follow(player);
+        }
+        assert_eq!(camera.position(), Vec2i(50, 0));
+        for _ in 5..10 {
+            camera.follow(player);
+        }
+        assert_eq!(camera.position(), Vec2i(100, 0));
+        assert_eq!(camera.zoom, 1.0);
+        for _ in 0..4 {
+            camera.follow(player);
+        }
+        // Centered on the last stop at its zoom, with a 50x50 view
+        assert_eq!(camera.zoom, 2.0);
+        assert_eq!(camera.position(), Vec2i(25, 25));
+
+        assert!(!camera.playing_path());
+        assert!(!camera.input_locked());
+        assert_eq!(camera.letterbox(), 0);
+        camera.follow(player);
+        assert_eq!(camera.position(), Vec2i(975, 975));
+    }
+
+    #[test]
+    fn stopping_a_path_leaves_the_camera_where_it_got_to() {
+        let mut camera = Camera::new((100, 100));
+        camera.play(CameraPath::new(vec![Keyframe {
+            center: Vec2i(250, 50),
+            zoom: None,
+            frames: 4,
+            easing: Easing::Linear,
+        }]));
+        camera.follow(Vec2i(0, 0));
+        camera.stop();
+        assert!(!camera.playing_path());
+        assert_eq!(camera.position(), Vec2i(50, 0));
+    }
+}
@@ -2,12 +2,17 @@ use core::f32;
 use crate::graphics::animation::AnimationState;
 use crate::logic::{
     geom::*,
-    state::{EntityAnims, EntityState},
+    platformer::WallSide,
+    state::{EntityAnims, EntityState, EntityType, GameState},
+    tiles::{Tile, Tilemap, World},
     types::*,
 };
 use cgmath::{num_traits::Pow, vec3, Vector3};
 
 const SAMPLE_DENSITY: f32 = 1.0;
+// How fast (in units per second, into the wall) a marble has to be going to
+// knock out a destructible wall
+pub const WALL_BREAK_SPEED: f32 = 8.0;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Contact<T: Copy> {
@@ -39,6 +44,218 @@ impl Contacts {
     }
 }
 
+/// Are r1 and r2 touching?  Edges are inclusive, so rects that share an
+/// edge count; use `rect_overlapping` if that's not what you want.
+pub fn rect_touching(r1: Rect, r2: Rect) -> bool {
+    r1.touches(&r2)
+}
+
+/// Strict variant of `rect_touching`: rects that only share an edge don't count.
+pub fn rect_overlapping(r1: Rect, r2: Rect) -> bool {
+    r1.overlaps(&r2)
+}
+
+/// How much do r1 and r2 overlap on each axis?  `None` if they aren't touching.
+pub fn rect_displacement(r1: Rect, r2: Rect) -> Option<Vec2i> {
+    r1.displacement(&r2)
+}
+
+/// A 2D contact between entities `a` and `b`.  `mtv` is the shortest push
+/// (along a single axis) that would move `a` out of `b`, so its sign gives
+/// the contact normal.
+#[derive(Clone, Copy, Debug)]
+pub struct RectContact {
+    pub a: usize,
+    pub b: usize,
+    pub mtv: Vec2i,
+}
+
+/// The shortest single-axis push that would move `a` out of `b`, pointing
+/// away from `b`'s center.  `None` if they aren't touching.
+pub fn rect_mtv(a: Rect, b: Rect) -> Option<Vec2i> {
+    let Vec2i(ox, oy) = rect_displacement(a, b)?;
+    // Push out along whichever axis overlaps least
+    Some(if ox <= oy {
+        let dir = if a.x * 2 + a.w as i32 <= b.x * 2 + b.w as i32 {
+            -1
+        } else {
+            1
+        };
+        Vec2i(dir * ox, 0)
+    } else {
+        let dir = if a.y * 2 + a.h as i32 <= b.y * 2 + b.h as i32 {
+            -1
+        } else {
+            1
+        };
+        Vec2i(0, dir * oy)
+    })
+}
+
+/// Find every entity each player is touching; `a` is the player's entity.
+pub fn gather_player_contacts(state: &GameState, into: &mut Vec<RectContact>) {
+    into.clear();
+    for p in state.players.iter() {
+        let player = state.entity_rect(p.entity);
+        for bi in 0..state.types.len() {
+            if bi == p.entity {
+                continue;
+            }
+            if let Some(mtv) = rect_mtv(player, state.entity_rect(bi)) {
+                into.push(RectContact { a: p.entity, b: bi, mtv });
+            }
+        }
+    }
+}
+
+/// Which side (if any) of `player` is up against one of `solids`?  Only
+/// x-axis contacts count, so standing on a floor isn't a wall.
+pub fn wall_side(player: Rect, solids: &[Rect]) -> Option<WallSide> {
+    solids.iter().find_map(|s| match rect_mtv(player, *s) {
+        // Pushed right means the wall is on the left
+        Some(Vec2i(x, 0)) if x > 0 => Some(WallSide::Left),
+        Some(Vec2i(x, 0)) if x < 0 => Some(WallSide::Right),
+        _ => None,
+    })
+}
+
+/// Add the world rect of every solid tile of `map` that `r` touches to
+/// `into`, e.g. to build `Sim::solids` around an entity.  Parts of `r` off
+/// the map are fine; they just don't add anything.
+pub fn tile_solids(map: &Tilemap, r: Rect, into: &mut Vec<Rect>) {
+    tiles_touching(map, r, Tile::is_solid, into)
+}
+
+/// Like `tile_solids`, for whichever tiles `pred` picks, e.g. hazards.
+pub fn tiles_touching(map: &Tilemap, r: Rect, pred: impl Fn(Tile) -> bool, into: &mut Vec<Rect>) {
+    let tileset = map.tileset();
+    let (tw, th) = (tileset.tile_w as i32, tileset.tile_h as i32);
+    let Vec2i(mx, my) = map.position;
+    // Every tile column and row the (inclusive) rect reaches into
+    let (tx0, tx1) = ((r.x - mx).div_euclid(tw), (r.x + r.w as i32 - mx).div_euclid(tw));
+    let (ty0, ty1) = ((r.y - my).div_euclid(th), (r.y + r.h as i32 - my).div_euclid(th));
+    for ty in ty0..=ty1 {
+        for tx in tx0..=tx1 {
+            if let Some(rect) = map.try_get_tile_rect(Vec2i(tx, ty)) {
+                let picked = map
+                    .try_tile_id_at(Vec2i(rect.x, rect.y))
+                    .map_or(false, |id| pred(tileset.tiles[id]));
+                if picked {
+                    into.push(rect);
+                }
+            }
+        }
+    }
+}
+
+/// `tile_solids` for every chunk of `world` that `r` reaches.
+pub fn world_solids(world: &World, r: Rect, into: &mut Vec<Rect>) {
+    for map in world.tilemaps.iter() {
+        let (w, h) = map.size();
+        let bounds = Rect {
+            x: map.position.0,
+            y: map.position.1,
+            w: w as u16,
+            h: h as u16,
+        };
+        if r.touches(&bounds) {
+            tile_solids(map, r, into);
+        }
+    }
+}
+
+/// Hurt the players for each enemy they're touching, knocking them away
+/// from it by `knockback` units/frame.  The invincibility window keeps a
+/// single enemy from chain-hitting.  Returns true if that hit was fatal, in
+/// which case the player has started dying.
+pub fn hurt_player(state: &mut GameState, contacts: &[RectContact], knockback: i32) -> bool {
+    for c in contacts.iter() {
+        if state.types[c.b] != EntityType::Enemy || state.invulnerable() || state.dying() {
+            continue;
+        }
+        if state.take_damage(1) {
+            state.kill_player();
+            return true;
+        }
+        // Edge-touching contacts have a zero mtv; knock back up and away instead
+        let Vec2i(nx, ny) = c.mtv;
+        state.velocities[c.a] = Vec2i(
+            nx.signum() * knockback,
+            if nx == 0 && ny == 0 { -knockback } else { ny.signum() * knockback },
+        );
+    }
+    false
+}
+
+/// Can `player` climb one of `ladders`?  They have to be on the ladder or
+/// standing right on top of it (to climb down); just brushing its side
+/// doesn't count.
+pub fn on_ladder(player: Rect, ladders: &[Rect]) -> bool {
+    ladders.iter().any(|l| {
+        player.overlaps(l)
+            || (player.y + player.h as i32 == l.y
+                && player.x < l.x + l.w as i32
+                && l.x < player.x + player.w as i32)
+    })
+}
+
+/// Is `r` standing right on top of one of `floors`, e.g. ice tiles?
+pub fn standing_on(r: Rect, floors: &[Rect]) -> bool {
+    floors.iter().any(|f| {
+        r.y + r.h as i32 == f.y && r.x < f.x + f.w as i32 && f.x < r.x + r.w as i32
+    })
+}
+
+/// The total push on `r` from the force tiles in `forces`: every one it's
+/// inside of or standing right on top of adds its force.  Like ladders,
+/// brushing a side doesn't count.
+pub fn force_on(r: Rect, forces: &[(Rect, Vec2i)]) -> Vec2i {
+    let mut total = Vec2i(0, 0);
+    for (f, force) in forces.iter() {
+        let standing_on =
+            r.y + r.h as i32 == f.y && r.x < f.x + f.w as i32 && f.x < r.x + r.w as i32;
+        if r.overlaps(f) || standing_on {
+            total.0 += force.0;
+            total.1 += force.1;
+        }
+    }
+    total
+}
+
+/// The top tile of each ladder in `ladders`: the ones with no ladder right
+/// above them.  These are solid from above, so players not climbing can
+/// stand on top of a ladder.
+pub fn ladder_tops(ladders: &[Rect]) -> impl Iterator<Item = &Rect> + '_ {
+    ladders.iter().filter(move |l| {
+        !ladders
+            .iter()
+            .any(|above| above.x == l.x && above.y + above.h as i32 == l.y)
+    })
+}
+
+/// Hurt the players for touching any of `hazards` (spikes, lava), knocking
+/// them up and away by `knockback` units/frame.  Like enemies, a hazard
+/// takes a hit of health rather than ending the game outright.  Returns
+/// true if that hit was fatal, in which case the player has started dying.
+pub fn hurt_player_hazards(state: &mut GameState, hazards: &[Rect], knockback: i32) -> bool {
+    for i in 0..state.players.len() {
+        let e = state.players[i].entity;
+        let player = state.entity_rect(e);
+        let hit = hazards.iter().find_map(|h| rect_mtv(player, *h));
+        let Vec2i(nx, _) = match hit {
+            Some(mtv) if !state.invulnerable() && !state.dying() => mtv,
+            _ => continue,
+        };
+        if state.take_damage(1) {
+            state.kill_player();
+            return true;
+        }
+        // Always up, so the player doesn't land straight back on the spikes
+        state.velocities[e] = Vec2i(nx.signum() * knockback, -knockback);
+    }
+    false
+}
+
 // return a unit vector pointing from marble 1 to marble 2, i.e. contact normal
 fn direction(marble1: &Marble, marble2: &Marble) -> Vector3<f32> {
     let mut disp = vec3(
@@ -69,10 +286,71 @@ fn avg_momentum(marble1: &Marble, marble2: &Marble, direction: Vector3<f32>) ->
     sum_momentum * 0.5
 }
 
-pub fn update(walls: &[Wall], marbles: &mut [Marble], contacts: &mut Contacts) {
+/// What knocked a wall out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WallBreaker {
+    // Index into the marbles
+    Marble(usize),
+    Player,
+}
+
+/// Destructible wall `wall` was hit hard enough to break it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WallBroken {
+    pub wall: usize,
+    pub by: WallBreaker,
+}
+
+/// Collide marbles with each other and with the walls.  Destructible walls
+/// hit faster than `WALL_BREAK_SPEED` are marked destroyed (the marble goes
+/// straight through) and reported, once each.
+pub fn update(walls: &mut [Wall], marbles: &mut [Marble], contacts: &mut Contacts) -> Vec<WallBroken> {
     contacts.clear();
     gather_contacts(walls, marbles, contacts);
+    let broken = break_walls(walls, marbles, contacts);
     restitute(walls, marbles, contacts);
+    broken
+}
+
+/// Let the player, a sphere moving at `velocity` (units per second), break
+/// destructible walls the same way marbles do.  Only breaking is handled
+/// here: keeping the player out of the walls that are left is up to
+/// whatever moves them.
+pub fn player_break_walls(walls: &mut [Wall], player: &Sphere, velocity: Vec3) -> Vec<WallBroken> {
+    let mut broken = vec![];
+    for (i, wall) in walls.iter_mut().enumerate() {
+        if breaks(wall, velocity) && disp_sphere_plane(player, &wall.body).is_some() {
+            wall.destroyed = true;
+            broken.push(WallBroken {
+                wall: i,
+                by: WallBreaker::Player,
+            });
+        }
+    }
+    broken
+}
+
+// Would something moving at `velocity` knock `wall` out if it hit it?
+fn breaks(wall: &Wall, velocity: Vec3) -> bool {
+    // Speed into the wall, against its normal
+    wall.destructible && !wall.destroyed && -velocity.dot(wall.body.n) >= WALL_BREAK_SPEED
+}
+
+fn break_walls(walls: &mut [Wall], marbles: &[Marble], contacts: &mut Contacts) -> Vec<WallBroken> {
+    let mut broken = vec![];
+    for c in contacts.wm.iter() {
+        let wall = &mut walls[c.b];
+        if breaks(wall, marbles[c.a].velocity) {
+            wall.destroyed = true;
+            broken.push(WallBroken {
+                wall: c.b,
+                by: WallBreaker::Marble(c.a),
+            });
+        }
+    }
+    // Nothing left to push off of
+    contacts.wm.retain(|c| !walls[c.b].destroyed);
+    broken
 }
 
 fn gather_contacts(statics: &[Wall], dynamics: &[Marble], into: &mut Contacts) {
@@ -90,9 +368,10 @@ fn gather_contacts(statics: &[Wall], dynamics: &[Marble], into: &mut Contacts) {
         }
     }
     // collide mobiles against walls
-    todo!();
-    /*
     for (bi, b) in statics.iter().enumerate() {
+        if b.destroyed {
+            continue;
+        }
         for (ai, a) in dynamics.iter().enumerate() {
             if let Some(disp) = disp_sphere_plane(&a.body, &b.body) {
                 into.wm.push(Contact {
@@ -102,7 +381,7 @@ fn gather_contacts(statics: &[Wall], dynamics: &[Marble], into: &mut Contacts) {
                 });
             }
         }
-    }*/
+    }
 }
 
 fn restitute(walls: &[Wall], marbles: &mut [Marble], contacts: &mut Contacts) {
@@ -113,8 +392,6 @@ fn restitute(walls: &[Wall], marbles: &mut [Marble], contacts: &mut Contacts) {
         let b = c.b;
         // Are they still touching?  This way we don't need to track disps or anything
         // at the expense of some extra collision checks
-        todo!();
-        /*
         if let Some(disp) = disp_sphere_plane(&marbles[a].body, &walls[b].body) {
             // We can imagine we're instantaneously applying a
             // velocity change to pop the object just above the floor.
@@ -124,7 +401,7 @@ fn restitute(walls: &[Wall], marbles: &mut [Marble], contacts: &mut Contacts) {
             // with it.  We're not exactly modeling a normal force
             // here but it's something like that.
             marbles[a].velocity += disp;
-        }*/
+        }
     }
     // That can bump into each other in perfectly elastic collisions!
     for c in contacts.mm.iter() {
@@ -142,3 +419,79 @@ fn restitute(walls: &[Wall], marbles: &mut [Marble], contacts: &mut Contacts) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor() -> Wall {
+        Wall {
+            body: Plane {
+                n: Vec3::new(0.0, 1.0, 0.0),
+                d: 0.0,
+            },
+            destructible: true,
+            destroyed: false,
+        }
+    }
+
+    fn ball(y: f32, vy: f32) -> (Sphere, Vec3) {
+        let body = Sphere {
+            c: Pos3::new(0.0, y, 0.0),
+            r: 0.5,
+        };
+        (body, Vec3::new(0.0, vy, 0.0))
+    }
+
+    #[test]
+    fn player_breaks_walls_like_a_marble() {
+        // Touching the floor but too slow
+        let mut walls = vec![floor()];
+        let (body, velocity) = ball(0.4, -WALL_BREAK_SPEED / 2.0);
+        assert!(player_break_walls(&mut walls, &body, velocity).is_empty());
+        assert!(!walls[0].destroyed);
+
+        // Fast enough, but not there yet
+        let (body, velocity) = ball(2.0, -WALL_BREAK_SPEED);
+        assert!(player_break_walls(&mut walls, &body, velocity).is_empty());
+
+        let (body, velocity) = ball(0.4, -WALL_BREAK_SPEED);
+        let broken = player_break_walls(&mut walls, &body, velocity);
+        assert_eq!(
+            broken,
+            vec![WallBroken {
+                wall: 0,
+                by: WallBreaker::Player
+            }]
+        );
+        assert!(walls[0].destroyed);
+        // Only once
+        assert!(player_break_walls(&mut walls, &body, velocity).is_empty());
+    }
+
+    #[test]
+    fn marbles_report_which_one_broke_the_wall() {
+        let mut walls = vec![floor()];
+        let (slow, v) = ball(3.0, 0.0);
+        let (fast, fast_v) = ball(0.4, -WALL_BREAK_SPEED * 2.0);
+        let mut marbles = vec![
+            Marble {
+                body: slow,
+                velocity: v,
+            },
+            Marble {
+                body: fast,
+                velocity: fast_v,
+            },
+        ];
+        let broken = update(&mut walls, &mut marbles, &mut Contacts::new());
+        assert_eq!(
+            broken,
+            vec![WallBroken {
+                wall: 0,
+                by: WallBreaker::Marble(1)
+            }]
+        );
+        assert!(walls[0].destroyed);
+    }
+}
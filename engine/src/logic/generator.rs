@@ -0,0 +1,113 @@
+use rand::{Rng, RngCore};
+
+use crate::logic::{
+    tiles::{Tilemap, World},
+    types::Vec2i,
+};
+
+/// Makes the chunks of an endless level, one at a time.  `difficulty` runs
+/// from 0 (the start) to 1 (as hard as it gets); what it means is up to
+/// the generator.  Chunks can come back at any position: `stream` moves
+/// each one to where it goes.
+pub trait WorldGenerator {
+    fn next_chunk(&mut self, rng: &mut dyn RngCore, difficulty: f32) -> Tilemap;
+}
+
+/// The simplest generator: a fixed starting chunk, then random picks from
+/// a pool of hand-made chunks ordered from easiest to hardest.  Higher
+/// difficulty lets harder chunks in.
+#[derive(Clone)]
+pub struct MapPool {
+    pub start: Tilemap,
+    pub pool: Vec<Tilemap>,
+    started: bool,
+}
+
+impl MapPool {
+    pub fn new(start: Tilemap, pool: Vec<Tilemap>) -> Self {
+        Self {
+            start,
+            pool,
+            started: false,
+        }
+    }
+}
+
+impl WorldGenerator for MapPool {
+    fn next_chunk(&mut self, rng: &mut dyn RngCore, difficulty: f32) -> Tilemap {
+        if !self.started || self.pool.is_empty() {
+            self.started = true;
+            return self.start.clone();
+        }
+        let unlocked = ((self.pool.len() as f32 * difficulty.max(0.0).min(1.0)).ceil() as usize)
+            .max(1)
+            .min(self.pool.len());
+        self.pool[rng.gen_range(0, unlocked)].clone()
+    }
+}
+
+/// Keep an endless, rightward-scrolling `world` covering the view from
+/// `left` to `right` (world x): chunks from `gen` are laid end to end at
+/// `next` until the view is covered, and chunks that have scrolled
+/// entirely off the left are dropped.  Start `next` at where the first
+/// chunk goes and keep it between calls; it's the level's frontier, so the
+/// level carries on from the right place even if every chunk got dropped.
+pub fn stream(
+    world: &mut World,
+    gen: &mut dyn WorldGenerator,
+    rng: &mut dyn RngCore,
+    difficulty: f32,
+    next: &mut Vec2i,
+    (left, right): (i32, i32),
+) {
+    world
+        .tilemaps
+        .retain(|map| map.position.0 + map.size().0 as i32 > left);
+    while world.tilemaps.is_empty() || next.0 <= right {
+        let mut chunk = gen.next_chunk(rng, difficulty);
+        let width = chunk.size().0 as i32;
+        // A zero-width chunk would never cover anything
+        if width == 0 {
+            break;
+        }
+        chunk.position = *next;
+        next.0 += width;
+        world.tilemaps.push(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::texture::CpuTexture;
+    use crate::logic::tiles::{Tile, Tileset};
+    use image::RgbaImage;
+    use rand::{SeedableRng, StdRng};
+    use std::rc::Rc;
+
+    // A 2x1 chunk of 16px tiles: 32 pixels wide
+    fn chunk() -> Tilemap {
+        let tex = Rc::new(CpuTexture::new(RgbaImage::new(16, 16)));
+        let tileset = Rc::new(Tileset::new(vec![Tile::EMPTY], tex));
+        Tilemap::new(Vec2i(0, 0), (2, 1), &tileset, vec![0, 0])
+    }
+
+    #[test]
+    fn stream_carries_on_after_every_chunk_scrolls_off() {
+        let mut world = World::new(vec![]);
+        let mut gen = MapPool::new(chunk(), vec![chunk()]);
+        let mut rng = StdRng::from_seed([0; 32]);
+        let mut next = Vec2i(0, 8);
+        stream(&mut world, &mut gen, &mut rng, 0.0, &mut next, (0, 40));
+        let xs: Vec<i32> = world.tilemaps.iter().map(|m| m.position.0).collect();
+        assert_eq!(xs, vec![0, 32]);
+        assert_eq!(next, Vec2i(64, 8));
+
+        // Jump far enough right that nothing on screen survives; the level
+        // picks up where it left off rather than back at the start
+        stream(&mut world, &mut gen, &mut rng, 0.0, &mut next, (100, 120));
+        let xs: Vec<i32> = world.tilemaps.iter().map(|m| m.position.0).collect();
+        assert_eq!(xs, vec![64, 96]);
+        assert!(world.tilemaps.iter().all(|m| m.position.1 == 8));
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::Path;
+
+use crate::config::Settings;
+use crate::graphics::screen::Screen;
+use crate::input::{InputSource, KeyRepeat};
+use crate::logic::{
+    state::{GameState, Inputs},
+    types::{Rect, Rgba, Vec2i},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuEntry {
+    Start,
+    // Left/right turn it down/up
+    Volume,
+    Mute,
+    Quit,
+}
+
+pub const MENU_ENTRIES: [MenuEntry; 4] = [
+    MenuEntry::Start,
+    MenuEntry::Volume,
+    MenuEntry::Mute,
+    MenuEntry::Quit,
+];
+
+impl MenuEntry {
+    pub fn label(self) -> &'static str {
+        match self {
+            MenuEntry::Start => "Start",
+            MenuEntry::Volume => "Volume",
+            MenuEntry::Mute => "Mute",
+            MenuEntry::Quit => "Quit",
+        }
+    }
+}
+
+/// A vertical list of labeled entries with one highlighted, for title
+/// screens, pause menus and the like.  `A` is whatever the game wants back
+/// when an entry is picked.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Menu<A: Copy> {
+    entries: Vec<(&'static str, A)>,
+    pub selected: usize,
+    // For `poll`, so holding up or down scrolls
+    repeat: KeyRepeat,
+}
+
+impl<A: Copy> Menu<A> {
+    pub fn new(entries: Vec<(&'static str, A)>) -> Self {
+        assert!(!entries.is_empty(), "A menu needs at least one entry");
+        Self {
+            entries,
+            selected: 0,
+            repeat: KeyRepeat::new(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn selected(&self) -> A {
+        self.entries[self.selected % self.len()].1
+    }
+    /// Up moves the highlight up the list and down moves it down, wrapping
+    /// around at either end.
+    pub fn navigate(&mut self, inputs: &Inputs) {
+        let count = self.len();
+        // A stale index from somewhere else counts as whatever it wraps to,
+        // the same entry `selected()` shows
+        let current = self.selected % count;
+        if inputs.up {
+            self.selected = (current + count - 1) % count;
+        } else if inputs.down {
+            self.selected = (current + 1) % count;
+        } else {
+            self.selected = current;
+        }
+    }
+    /// Run a frame off of `inputs` (keys pressed this frame): navigate,
+    /// and return the highlighted entry's action if space picked it.
+    pub fn update(&mut self, inputs: &Inputs) -> Option<A> {
+        self.navigate(inputs);
+        if inputs.space {
+            Some(self.selected())
+        } else {
+            None
+        }
+    }
+    /// `update` with inputs read straight from `input`, repeating held
+    /// arrow keys.  Keep the menu around between frames for repeat to work.
+    pub fn poll(&mut self, input: &dyn InputSource) -> Option<A> {
+        let inputs = Inputs::menu_repeating(input, &mut self.repeat);
+        self.update(&inputs)
+    }
+    /// Where each entry goes when the list starts at `top_left`, every
+    /// entry `size` big and `spacing` apart, with its label and whether
+    /// it's highlighted.  Draw the labels with whatever text the game has.
+    pub fn layout(
+        &self,
+        Vec2i(x, y): Vec2i,
+        size: (u16, u16),
+        spacing: i32,
+    ) -> impl Iterator<Item = (Rect, &'static str, bool)> + '_ {
+        self.entries.iter().enumerate().map(move |(i, (label, _))| {
+            let r = Rect {
+                x,
+                y: y + i as i32 * (size.1 as i32 + spacing),
+                w: size.0,
+                h: size.1,
+            };
+            (r, *label, i == self.selected % self.entries.len())
+        })
+    }
+    /// Draw a box per entry from `layout`, the highlighted one in
+    /// `highlight`, with its label written across it in the other color
+    /// (`Screen::text`, as big as fits the box's height).
+    pub fn draw(
+        &self,
+        screen: &mut Screen,
+        top_left: Vec2i,
+        size: (u16, u16),
+        spacing: i32,
+        col: Rgba,
+        highlight: Rgba,
+    ) {
+        // A font pixel's worth of margin above and below the 5-pixel glyphs
+        let scale = (size.1 / 7).max(1);
+        for (r, label, selected) in self.layout(top_left, size, spacing) {
+            let (back, fore) = if selected {
+                (highlight, col)
+            } else {
+                (col, highlight)
+            };
+            screen.rect(r, back);
+            let pad = (r.h as i32 - 5 * scale as i32) / 2;
+            screen.text(label, Vec2i(r.x + pad.max(0), r.y + pad), scale, fore);
+        }
+    }
+}
+
+/// The title screen's menu, built from `MENU_ENTRIES`.
+pub fn main_menu() -> Menu<MenuEntry> {
+    Menu::new(MENU_ENTRIES.iter().map(|&e| (e.label(), e)).collect())
+}
+
+/// What the game should do after a frame on the menu.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuAction {
+    Stay,
+    Start,
+    Quit,
+}
+
+/// Run the menu for a frame off of `state.inputs`, which should only hold
+/// keys pressed (or repeated) this frame, e.g. from `read_menu_inputs`.
+/// Up and down move through `state.menu`; space picks the highlighted
+/// entry.  Volume and mute changes go to `settings` as well as the sound
+/// channels, and `settings` is saved to `settings_path` right away so they
+/// carry over to the next run.
+pub fn update_menu(
+    state: &mut GameState,
+    settings: &mut Settings,
+    settings_path: &Path,
+) -> MenuAction {
+    let inputs = state.inputs;
+    state.menu.navigate(&inputs);
+    let entry = state.menu.selected();
+    let mut sound = state.sound_channels.lock().unwrap();
+    match entry {
+        MenuEntry::Volume if inputs.left => sound.volume_down(),
+        MenuEntry::Volume if inputs.right => sound.volume_up(),
+        MenuEntry::Mute if inputs.space => sound.toggle_mute(),
+        MenuEntry::Start if inputs.space => return MenuAction::Start,
+        MenuEntry::Quit if inputs.space => return MenuAction::Quit,
+        _ => return MenuAction::Stay,
+    }
+    settings.set("volume", &sound.volume().to_string());
+    settings.set("muted", &sound.muted().to_string());
+    if let Err(e) = settings.save(settings_path) {
+        log::warn!("Couldn't save {}: {}", settings_path.display(), e);
+    }
+    MenuAction::Stay
+}
+
+/// Run a frame of the game-over or victory screen: space plays again and
+/// escape goes back to the menu, same as each other.
+pub fn update_end_screen(state: &GameState) -> EndAction {
+    if state.inputs.space {
+        EndAction::Restart
+    } else if state.inputs.esc {
+        EndAction::Menu
+    } else {
+        EndAction::Stay
+    }
+}
+
+/// What the game should do after a frame on the game-over or victory screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EndAction {
+    Stay,
+    Restart,
+    Menu,
+}
+
+/// Run a frame of `StateType::ConfirmQuit`: space really quits and escape
+/// goes back to wherever the player came from.
+pub fn update_confirm_quit(state: &GameState) -> QuitAction {
+    if state.inputs.space {
+        QuitAction::Quit
+    } else if state.inputs.esc {
+        QuitAction::Back
+    } else {
+        QuitAction::Stay
+    }
+}
+
+/// What the game should do after a frame of asking whether to quit.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuitAction {
+    Stay,
+    Quit,
+    Back,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::texture::CpuTexture;
+    use image::RgbaImage;
+    use std::rc::Rc;
+
+    fn menu_state(entry: MenuEntry) -> GameState {
+        let tex = Rc::new(CpuTexture::new(RgbaImage::new(1, 1)));
+        let mut state = GameState::new(0, tex);
+        state.menu.selected = MENU_ENTRIES.iter().position(|&e| e == entry).unwrap();
+        state
+    }
+
+    #[test]
+    fn volume_and_mute_reach_the_settings() {
+        let path = std::env::temp_dir().join(format!("menu-settings-{}.cfg", std::process::id()));
+        let mut settings = Settings::default();
+        let mut state = menu_state(MenuEntry::Volume);
+        state.inputs.left = true;
+        assert_eq!(
+            update_menu(&mut state, &mut settings, &path),
+            MenuAction::Stay
+        );
+        let volume = state.sound_channels.lock().unwrap().volume();
+        assert!(volume < 1.0);
+        assert_eq!(settings.volume, volume);
+
+        let mut state = menu_state(MenuEntry::Mute);
+        state.inputs.space = true;
+        update_menu(&mut state, &mut settings, &path);
+        assert!(state.sound_channels.lock().unwrap().muted());
+        assert!(settings.muted);
+
+        // Both changes were saved as they happened
+        assert_eq!(Settings::load(&path), settings);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn draw_writes_each_label_over_its_box() {
+        let menu = main_menu();
+        let (w, h) = (80, 80);
+        let mut fb = vec![0; w * h * 4];
+        let mut screen = Screen::wrap(&mut fb, w, h, 4, Vec2i(0, 0));
+        let (col, highlight) = (Rgba(0, 0, 255, 255), Rgba(255, 255, 0, 255));
+        menu.draw(&mut screen, Vec2i(0, 0), (60, 14), 2, col, highlight);
+        let count = |r: Rect, c: Rgba| {
+            (r.y..r.y + r.h as i32)
+                .flat_map(|y| (r.x..r.x + r.w as i32).map(move |x| (x, y)))
+                .filter(|&(x, y)| {
+                    fb[(y as usize * w + x as usize) * 4..][..4] == [c.0, c.1, c.2, c.3]
+                })
+                .count()
+        };
+        let boxes: Vec<_> = menu.layout(Vec2i(0, 0), (60, 14), 2).collect();
+        // The selected entry's box is highlighted with its label in `col`,
+        // the rest the other way around
+        let (first, _, selected) = boxes[0];
+        assert!(selected);
+        assert!(count(first, highlight) > count(first, col));
+        assert!(count(first, col) > 0);
+        let (second, _, _) = boxes[1];
+        assert!(count(second, col) > count(second, highlight));
+        assert!(count(second, highlight) > 0);
+    }
+}
@@ -1,4 +1,17 @@
+pub mod camera;
 pub mod collision;
+pub mod generator;
 pub mod geom;
+pub mod menu;
+pub mod platformer;
+pub mod pool;
+pub mod projectiles;
+pub mod rng;
+pub mod save;
+pub mod score;
+pub mod sim;
 pub mod state;
+pub mod tiles;
+pub mod timer;
+pub mod triggers;
 pub mod types;
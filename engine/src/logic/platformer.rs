@@ -0,0 +1,382 @@
+use crate::logic::{
+    state::{EntityState, GameState},
+    types::{Rect, Vec2i},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PlatformerConfig {
+    // Horizontal speed in units/frame
+    pub run_speed: i32,
+    // Upwards speed right after a jump
+    pub jump_speed: i32,
+    pub gravity: i32,
+    pub max_fall_speed: i32,
+    // Jumps allowed before touching the ground again, counting the first one
+    pub max_jumps: u8,
+    // Fastest the player can fall while sliding down a wall
+    pub wall_slide_speed: i32,
+    // Horizontal speed away from the wall after a wall jump
+    pub wall_jump_push: i32,
+    // How far a dash carries the player, over how many frames
+    pub dash_distance: i32,
+    pub dash_frames: usize,
+    // Frames after starting a dash before the next one is allowed
+    pub dash_cooldown: usize,
+    // Is the player invulnerable while dashing?
+    pub dash_iframes: bool,
+    // Vertical speed on a ladder
+    pub climb_speed: i32,
+    // Collision box height while crouching, and how fast crouching moves
+    pub crouch_height: usize,
+    pub crouch_speed: i32,
+    // How much horizontal speed can change a frame on ice
+    pub ice_accel: i32,
+    // Fastest the player sinks in water
+    pub swim_fall_speed: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WallSide {
+    Left,
+    Right,
+}
+
+impl Default for PlatformerConfig {
+    fn default() -> Self {
+        Self {
+            run_speed: 2,
+            jump_speed: 6,
+            gravity: 1,
+            max_fall_speed: 6,
+            max_jumps: 1,
+            wall_slide_speed: 1,
+            wall_jump_push: 4,
+            dash_distance: 48,
+            dash_frames: 8,
+            dash_cooldown: 45,
+            dash_iframes: true,
+            climb_speed: 2,
+            crouch_height: 10,
+            crouch_speed: 1,
+            ice_accel: 1,
+            swim_fall_speed: 2,
+        }
+    }
+}
+
+/// Is player `p` in the air and pushing into the wall they're touching?
+/// Games set the player's `wall_contact` after collision, e.g. with
+/// `collision::wall_side`.
+pub fn wall_sliding(state: &GameState, p: usize) -> bool {
+    let player = &state.players[p];
+    let airborne = matches!(
+        state.ent_states[player.entity],
+        EntityState::Jumping | EntityState::Falling | EntityState::WallSliding
+    );
+    airborne
+        && match player.wall_contact {
+            Some(WallSide::Left) => player.inputs.left,
+            Some(WallSide::Right) => player.inputs.right,
+            None => false,
+        }
+}
+
+/// Crouch player `p` while they hold down on the ground, shrinking their
+/// collision box to `config.crouch_height` with their feet where they were.
+/// Letting go stands them back up, unless that would put their head in one
+/// of `solids`; then they stay down until there's room.
+pub fn update_crouch(
+    state: &mut GameState,
+    p: usize,
+    config: &PlatformerConfig,
+    solids: &[Rect],
+) {
+    let e = state.players[p].entity;
+    let player = state.players[p];
+    let (w, h) = state.sizes[e];
+    match player.crouch {
+        None => {
+            let grounded = matches!(
+                state.ent_states[e],
+                EntityState::Standing
+                    | EntityState::StartRun
+                    | EntityState::Running
+                    | EntityState::Landing
+            );
+            // Down on a ladder means climbing down it instead
+            if player.inputs.down && grounded && !player.ladder_contact {
+                let low = config.crouch_height.min(h);
+                state.positions[e].1 += (h - low) as i32;
+                state.sizes[e].1 = low;
+                state.players[p].crouch = Some(h);
+            }
+        }
+        Some(stand) if !player.inputs.down => {
+            let Vec2i(x, y) = state.positions[e];
+            let standing = Rect {
+                x,
+                y: y - (stand - h) as i32,
+                w: w as u16,
+                h: stand as u16,
+            };
+            if !solids.iter().any(|s| s.overlaps(&standing)) {
+                state.positions[e].1 = standing.y;
+                state.sizes[e].1 = stand;
+                state.players[p].crouch = None;
+            }
+        }
+        Some(_) => {}
+    }
+}
+
+/// Steer player `p` from their `inputs`.  `inputs.space` should only be set
+/// on the frame the jump key goes down, or holding it would burn through
+/// every mid-air jump at once.
+pub fn update_velocity(state: &mut GameState, p: usize, config: &PlatformerConfig) {
+    let e = state.players[p].entity;
+    if state.dying() {
+        // No more steering once the death animation has started
+        let vel = &mut state.velocities[e];
+        vel.0 = 0;
+        vel.1 = (vel.1 + config.gravity).min(config.max_fall_speed);
+        return;
+    }
+    if state.ent_states[e] == EntityState::Landing {
+        state.players[p].jumps_left = config.max_jumps;
+    }
+    let sliding = wall_sliding(state, p);
+    let player = &mut state.players[p];
+    let inputs = player.inputs;
+    let vel = &mut state.velocities[e];
+    // Up or down grabs a ladder; jumping or leaving it lets go.  Games
+    // set `ladder_contact` before this, e.g. with `collision::on_ladder`.
+    if !player.ladder_contact || inputs.space {
+        player.climbing = false;
+    } else if inputs.up || inputs.down {
+        player.climbing = true;
+    }
+    if player.climbing {
+        // No gravity: up and down move the player directly
+        vel.0 = if inputs.left {
+            -config.run_speed
+        } else if inputs.right {
+            config.run_speed
+        } else {
+            0
+        };
+        vel.1 = if inputs.up {
+            -config.climb_speed
+        } else if inputs.down {
+            config.climb_speed
+        } else {
+            0
+        };
+        player.jumps_left = config.max_jumps;
+        return;
+    }
+    let speed = if player.crouch.is_some() {
+        config.crouch_speed
+    } else {
+        config.run_speed
+    };
+    let target = if inputs.left {
+        -speed
+    } else if inputs.right {
+        speed
+    } else {
+        0
+    };
+    // Ice takes a while to get going and to stop; `on_ice` is set like
+    // `ladder_contact`
+    vel.0 = if player.on_ice {
+        let accel = config.ice_accel.max(1);
+        vel.0 + (target - vel.0).max(-accel).min(accel)
+    } else {
+        target
+    };
+    if target != 0 {
+        player.facing = target.signum();
+    }
+    // Every stroke in water is another jump: swimming up
+    if player.in_water {
+        player.jumps_left = config.max_jumps.max(1);
+    }
+    player.dash_cooldown = player.dash_cooldown.saturating_sub(1);
+    if inputs.dash && player.dash_cooldown == 0 && config.dash_frames > 0 {
+        player.dash_frames = config.dash_frames;
+        player.dash_cooldown = config.dash_cooldown;
+        if config.dash_iframes {
+            state.iframes = state.iframes.max(config.dash_frames);
+        }
+    }
+    if player.dash_frames > 0 {
+        // Speed falls off linearly over the dash so it eases out.  Each
+        // frame moves from where the dash should be by the last frame to
+        // where it should be by this one, so rounding never builds up and
+        // the whole dash covers exactly dash_distance.
+        let n = config.dash_frames as i32;
+        let done = n - player.dash_frames as i32;
+        let covered = |j: i32| config.dash_distance * j * (2 * n - j + 1) / (n * (n + 1));
+        vel.0 = player.facing * (covered(done + 1) - covered(done));
+        player.dash_frames -= 1;
+    }
+    if inputs.space && sliding {
+        // Wall jumps kick up and away from the wall and don't use up a jump
+        vel.0 = match player.wall_contact {
+            Some(WallSide::Left) => config.wall_jump_push,
+            _ => -config.wall_jump_push,
+        };
+        vel.1 = -config.jump_speed;
+    } else if inputs.space && player.jumps_left > 0 && player.crouch.is_none() {
+        player.jumps_left -= 1;
+        vel.1 = -config.jump_speed;
+    } else if sliding {
+        vel.1 = (vel.1 + config.gravity).min(config.wall_slide_speed);
+    } else if player.in_water {
+        vel.1 = (vel.1 + config.gravity).min(config.swim_fall_speed);
+    } else {
+        vel.1 = (vel.1 + config.gravity).min(config.max_fall_speed);
+    }
+}
+
+/// Move player `p`'s state machine along based on its velocity, and start
+/// the matching animation whenever the state changes.  Collision is expected
+/// to have zeroed `vel.1` if the player is standing on something.
+pub fn update_player_state(state: &mut GameState, p: usize) {
+    let e = state.players[p].entity;
+    let vel = state.velocities[e];
+    let anim_done = state.anim_state[e].done();
+    let next = match state.ent_states[e] {
+        // kill_player already started the animation; play it out
+        EntityState::Dying => return,
+        _ if state.players[p].climbing => EntityState::Climbing,
+        _ if vel.1 < 0 => EntityState::Jumping,
+        _ if vel.1 > 0 && wall_sliding(state, p) => EntityState::WallSliding,
+        _ if vel.1 > 0 => EntityState::Falling,
+        _ if state.players[p].crouch.is_some() => EntityState::Crouching,
+        EntityState::Jumping | EntityState::Falling | EntityState::WallSliding => {
+            EntityState::Landing
+        }
+        // Stepping off a ladder onto solid ground
+        EntityState::Climbing => EntityState::Standing,
+        EntityState::Landing if !anim_done => EntityState::Landing,
+        EntityState::StartRun if vel.0 != 0 && anim_done => EntityState::Running,
+        EntityState::StartRun | EntityState::Running if vel.0 != 0 => state.ent_states[e].clone(),
+        _ if vel.0 != 0 => EntityState::StartRun,
+        _ => EntityState::Standing,
+    };
+    if next != state.ent_states[e] {
+        let anim = state.entity_anims.for_state(&next);
+        state.anim_state[e].play(anim, true);
+        state.ent_states[e] = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::state::tests::game_state;
+    use crate::logic::types::Vec2i;
+
+    // Run a frame of `update_velocity` with `space` pressed or not
+    fn frame(state: &mut GameState, config: &PlatformerConfig, space: bool) -> Vec2i {
+        state.players[0].inputs.space = space;
+        update_velocity(state, 0, config);
+        state.velocities[0]
+    }
+
+    #[test]
+    fn exactly_max_jumps_jumps_before_landing() {
+        let config = PlatformerConfig {
+            max_jumps: 3,
+            ..PlatformerConfig::default()
+        };
+        let mut state = game_state();
+        // Landing refills the jumps
+        state.ent_states[0] = EntityState::Landing;
+        frame(&mut state, &config, false);
+        assert_eq!(state.players[0].jumps_left, 3);
+
+        state.ent_states[0] = EntityState::Jumping;
+        for _ in 0..config.max_jumps {
+            assert_eq!(frame(&mut state, &config, true).1, -config.jump_speed);
+            // Holding off between pulses just falls
+            assert_eq!(
+                frame(&mut state, &config, false).1,
+                -config.jump_speed + config.gravity
+            );
+        }
+        assert_eq!(state.players[0].jumps_left, 0);
+        // One more pulse does nothing but fall
+        let before = state.velocities[0].1;
+        assert_eq!(frame(&mut state, &config, true).1, before + config.gravity);
+    }
+
+    #[test]
+    fn dash_covers_exactly_dash_distance() {
+        let config = PlatformerConfig::default();
+        let mut state = game_state();
+        state.players[0].inputs.dash = true;
+        update_velocity(&mut state, 0, &config);
+        let mut moved = state.velocities[0].0;
+        state.players[0].inputs.dash = false;
+        for _ in 1..config.dash_frames {
+            moved += frame(&mut state, &config, false).0;
+        }
+        assert_eq!(moved, config.dash_distance);
+        assert_eq!(state.players[0].dash_frames, 0);
+    }
+
+    #[test]
+    fn dash_waits_for_its_cooldown() {
+        let config = PlatformerConfig::default();
+        let mut state = game_state();
+        state.players[0].inputs.dash = true;
+        update_velocity(&mut state, 0, &config);
+        assert_eq!(state.players[0].dash_frames, config.dash_frames - 1);
+        // Let the dash play out, then keep pressing until the cooldown's up
+        for _ in 1..config.dash_cooldown {
+            update_velocity(&mut state, 0, &config);
+        }
+        assert_eq!(state.players[0].dash_frames, 0);
+        assert_eq!(state.velocities[0].0, 0);
+        // The next press dashes again
+        update_velocity(&mut state, 0, &config);
+        assert_eq!(state.players[0].dash_frames, config.dash_frames - 1);
+        assert_ne!(state.velocities[0].0, 0);
+    }
+
+    #[test]
+    fn stays_crouched_under_a_low_ceiling() {
+        let config = PlatformerConfig::default();
+        let mut state = game_state();
+        state.positions[0] = Vec2i(0, 100);
+        state.sizes[0] = (16, 16);
+        state.ent_states[0] = EntityState::Standing;
+        // Bottom edge 3px into where the player's head was, clear of the
+        // top of a crouch
+        let ceiling = Rect {
+            x: 0,
+            y: 95,
+            w: 16,
+            h: 8,
+        };
+        state.players[0].inputs.down = true;
+        update_crouch(&mut state, 0, &config, &[ceiling]);
+        assert_eq!(state.positions[0].1, 106);
+        assert_eq!(state.sizes[0].1, config.crouch_height);
+
+        state.players[0].inputs.down = false;
+        for _ in 0..2 {
+            update_crouch(&mut state, 0, &config, &[ceiling]);
+            assert_eq!(state.players[0].crouch, Some(16));
+            assert_eq!(state.sizes[0].1, config.crouch_height);
+            assert_eq!(state.positions[0].1, 106);
+        }
+        // Out from under it, they stand up with their feet where they were
+        update_crouch(&mut state, 0, &config, &[]);
+        assert_eq!(state.players[0].crouch, None);
+        assert_eq!(state.sizes[0], (16, 16));
+        assert_eq!(state.positions[0].1, 100);
+    }
+}
@@ -0,0 +1,128 @@
+/// A handle to something in a `Pool`.  Each slot counts how many times
+/// it's been reused, so an id kept after its object was released won't
+/// find whatever took the slot next.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PoolId {
+    index: usize,
+    generation: u32,
+}
+
+impl PoolId {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Storage whose released slots get reused by the next `acquire`, so
+/// spawning and killing enemies for a whole session doesn't keep growing
+/// it.  Ids stay valid until released, unlike `GameState` entity indices.
+#[derive(Clone, Debug)]
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    // Empty slots, most recently released last
+    free: Vec<usize>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Pool<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+            free: vec![],
+        }
+    }
+    /// Live objects.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Slots allocated, live or not; the most there have ever been at once.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+    /// Store `value`, in a released slot if there is one.
+    pub fn acquire(&mut self, value: T) -> PoolId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index];
+                slot.value = Some(value);
+                PoolId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                PoolId {
+                    index: self.slots.len() - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+    /// Take `id`'s object out and free its slot.  Returns `None` if `id`
+    /// was already released.
+    pub fn release(&mut self, id: PoolId) -> Option<T> {
+        let slot = self.slots.get_mut(id.index)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        Some(value)
+    }
+    pub fn get(&self, id: PoolId) -> Option<&T> {
+        self.slots
+            .get(id.index)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.value.as_ref())
+    }
+    pub fn get_mut(&mut self, id: PoolId) -> Option<&mut T> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.value.as_mut())
+    }
+    pub fn contains(&self, id: PoolId) -> bool {
+        self.get(id).is_some()
+    }
+    /// Every live object and its id.
+    pub fn iter(&self) -> impl Iterator<Item = (PoolId, &T)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, s)| {
+            s.value.as_ref().map(|v| {
+                (
+                    PoolId {
+                        index,
+                        generation: s.generation,
+                    },
+                    v,
+                )
+            })
+        })
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (PoolId, &mut T)> + '_ {
+        self.slots.iter_mut().enumerate().filter_map(|(index, s)| {
+            let generation = s.generation;
+            s.value
+                .as_mut()
+                .map(move |v| (PoolId { index, generation }, v))
+        })
+    }
+}
@@ -0,0 +1,229 @@
+use crate::graphics::gpu::SpriteInstance;
+use crate::logic::{
+    state::{EntityType, GameState},
+    types::{Rect, Vec2i},
+};
+
+/// Who fired a projectile, which decides who it can hurt: players' shots
+/// hit enemies and enemies' shots hit players.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Owner {
+    Player,
+    Enemy,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Projectile {
+    pos: Vec2i,
+    vel: Vec2i,
+    // Frames left before it fizzles out
+    life: usize,
+    owner: Owner,
+}
+
+/// What projectiles hit on a frame of `Projectiles::update`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct ProjectileHits {
+    pub enemies_killed: usize,
+    pub player_hurt: bool,
+    // That hit was fatal and the players have started dying
+    pub player_died: bool,
+}
+
+/// Every bullet in flight.  Dead projectiles are swapped out of a single
+/// `Vec` that keeps its capacity, so firing every frame doesn't allocate.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Projectiles {
+    pub size: (u16, u16),
+    // Frames a projectile lasts if it doesn't hit anything
+    pub lifetime: usize,
+    // Health an enemy projectile takes from the players
+    pub damage: u8,
+    // What part of the texture to draw each projectile with
+    pub frame: Rect,
+    projectiles: Vec<Projectile>,
+}
+
+impl Default for Projectiles {
+    /// 4x4 bullets drawn from the top left of their texture.
+    fn default() -> Self {
+        Self::new(
+            (4, 4),
+            Rect {
+                x: 0,
+                y: 0,
+                w: 4,
+                h: 4,
+            },
+        )
+    }
+}
+
+impl Projectiles {
+    pub fn new(size: (u16, u16), frame: Rect) -> Self {
+        Self {
+            size,
+            lifetime: 120,
+            damage: 1,
+            frame,
+            projectiles: vec![],
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.projectiles.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.projectiles.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.projectiles.clear();
+    }
+    /// Fire a projectile with its top left at `pos`, moving `vel`
+    /// units/frame.
+    pub fn spawn(&mut self, pos: Vec2i, vel: Vec2i, owner: Owner) {
+        self.projectiles.push(Projectile {
+            pos,
+            vel,
+            life: self.lifetime,
+            owner,
+        });
+    }
+    fn rect(&self, p: &Projectile) -> Rect {
+        Rect {
+            x: p.pos.0,
+            y: p.pos.1,
+            w: self.size.0,
+            h: self.size.1,
+        }
+    }
+    /// Move every projectile a frame.  Projectiles that run out of time or
+    /// whose center is in one of `solids` (the level's solid tiles, as
+    /// `Sim::solids` has them) go away; so do ones that hit something they
+    /// can hurt.  Player shots despawn the enemy they hit, and enemy shots
+    /// damage the players like touching an enemy does.
+    pub fn update(&mut self, state: &mut GameState, solids: &[Rect]) -> ProjectileHits {
+        let mut hits = ProjectileHits::default();
+        // Walk backwards so swap_remove doesn't skip anyone
+        for i in (0..self.projectiles.len()).rev() {
+            let p = &mut self.projectiles[i];
+            p.pos.0 += p.vel.0;
+            p.pos.1 += p.vel.1;
+            p.life = p.life.saturating_sub(1);
+            let p = self.projectiles[i];
+            let r = self.rect(&p);
+            let center = Rect {
+                x: r.x + r.w as i32 / 2,
+                y: r.y + r.h as i32 / 2,
+                w: 1,
+                h: 1,
+            };
+            let in_wall = solids.iter().any(|s| s.overlaps(&center));
+            let hit = match p.owner {
+                Owner::Player => match (0..state.types.len()).find(|&e| {
+                    state.types[e] == EntityType::Enemy && state.entity_rect(e).overlaps(&r)
+                }) {
+                    Some(e) => {
+                        state.despawn(e);
+                        hits.enemies_killed += 1;
+                        true
+                    }
+                    None => false,
+                },
+                Owner::Enemy => {
+                    let touching = state
+                        .players
+                        .iter()
+                        .any(|pl| state.entity_rect(pl.entity).overlaps(&r));
+                    if touching && !state.invulnerable() && !state.dying() {
+                        hits.player_hurt = true;
+                        if state.take_damage(self.damage) {
+                            state.kill_player();
+                            hits.player_died = true;
+                        }
+                    }
+                    touching
+                }
+            };
+            if p.life == 0 || in_wall || hit {
+                self.projectiles.swap_remove(i);
+            }
+        }
+        hits
+    }
+    /// One sprite instance per projectile in flight, in game pixels.
+    /// `tex_size` is the size of the texture `frame` is on.
+    pub fn instances(&self, tex_size: (f32, f32)) -> impl Iterator<Item = SpriteInstance> + '_ {
+        self.projectiles.iter().map(move |p| {
+            SpriteInstance::new(
+                [p.pos.0 as f32, p.pos.1 as f32, 0.0],
+                [self.size.0 as f32, self.size.1 as f32],
+                self.frame,
+                tex_size,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::state::tests::{game_state, texture};
+
+    fn wall() -> Rect {
+        Rect {
+            x: 40,
+            y: 0,
+            w: 16,
+            h: 16,
+        }
+    }
+
+    #[test]
+    fn projectiles_stop_at_walls() {
+        let mut state = game_state();
+        let mut shots = Projectiles::default();
+        shots.spawn(Vec2i(30, 4), Vec2i(4, 0), Owner::Player);
+        // Its center gets to x = 36 and then 40, inside the wall
+        shots.update(&mut state, &[wall()]);
+        assert_eq!(shots.len(), 1);
+        shots.update(&mut state, &[wall()]);
+        assert!(shots.is_empty());
+    }
+
+    #[test]
+    fn player_shots_kill_enemies_and_enemy_shots_hurt_players() {
+        let mut state = game_state();
+        let anim = state.entity_anims.standing.start();
+        state.spawn(EntityType::Enemy, Vec2i(20, 0), (4, 4), texture(4, 4), anim);
+        let mut shots = Projectiles::default();
+        shots.spawn(Vec2i(14, 0), Vec2i(4, 0), Owner::Player);
+        // An enemy's shot doesn't hurt other enemies
+        shots.spawn(Vec2i(14, 0), Vec2i(4, 0), Owner::Enemy);
+        let hits = shots.update(&mut state, &[]);
+        assert_eq!(hits.enemies_killed, 1);
+        assert!(!state.types.contains(&EntityType::Enemy));
+        assert_eq!(shots.len(), 1);
+
+        let mut shots = Projectiles::default();
+        shots.spawn(Vec2i(6, 0), Vec2i(-4, 0), Owner::Enemy);
+        let health = state.health;
+        let hits = shots.update(&mut state, &[]);
+        assert!(hits.player_hurt && !hits.player_died);
+        assert_eq!(state.health, health - shots.damage);
+        assert!(shots.is_empty());
+    }
+
+    #[test]
+    fn projectiles_fizzle_out_after_their_lifetime() {
+        let mut state = game_state();
+        let mut shots = Projectiles::default();
+        shots.lifetime = 3;
+        shots.spawn(Vec2i(100, 100), Vec2i(1, 0), Owner::Player);
+        for _ in 0..2 {
+            shots.update(&mut state, &[]);
+        }
+        assert_eq!(shots.len(), 1);
+        shots.update(&mut state, &[]);
+        assert!(shots.is_empty());
+    }
+}
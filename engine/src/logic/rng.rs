@@ -0,0 +1,56 @@
+use rand::{RngCore, SeedableRng, StdRng};
+
+/// The game's random number generator.  `StdRng` can't be serialized, so
+/// this counts how many 32-bit words have been drawn from it: reseeding
+/// and drawing that many again lands on exactly the same spot in the
+/// stream, which is all a save file needs to store.
+#[derive(Clone, Debug)]
+pub struct GameRng {
+    seed: u64,
+    draws: u64,
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self::restore(seed, 0)
+    }
+    /// The generator `new(seed)` becomes after `draws` words.
+    pub fn restore(seed: u64, draws: u64) -> Self {
+        let mut rng_seed = [0; 32];
+        rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+        let mut rng = StdRng::from_seed(rng_seed);
+        for _ in 0..draws {
+            rng.next_u32();
+        }
+        Self { seed, draws, rng }
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    /// 32-bit words drawn so far.
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+}
+
+// StdRng hands out its stream a word at a time whatever the call, so a
+// u64 is two words and n bytes are n/4 words, rounded up.
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 2;
+        self.rng.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.draws += (dest.len() as u64 + 3) / 4;
+        self.rng.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
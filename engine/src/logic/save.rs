@@ -0,0 +1,380 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::graphics::texture::CpuTexture;
+use crate::input::KeyBindings;
+use crate::logic::{
+    rng::GameRng,
+    score::ScoreTracker,
+    state::{EntityState, EntityType, GameState, Player},
+    timer::{Interval, Timer},
+    types::Vec2i,
+};
+
+/// The parts of a `Player` that last longer than a frame.  Inputs and wall
+/// contact get recomputed on the next step, and bindings belong to
+/// whoever is playing rather than the save.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct PlayerSave {
+    entity: usize,
+    jumps_left: u8,
+    facing: i32,
+    dash_frames: usize,
+    dash_cooldown: usize,
+    // Missing from saves made before ladders and crouching
+    #[serde(default)]
+    climbing: bool,
+    #[serde(default)]
+    crouch: Option<usize>,
+}
+
+/// Everything in a `GameState` except GPU, audio, and animation handles,
+/// which are rebuilt on load.  Enough to pick up exactly where the save
+/// was made, random numbers included.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct SaveState {
+    types: Vec<EntityType>,
+    ent_states: Vec<EntityState>,
+    positions: Vec<Vec2i>,
+    velocities: Vec<Vec2i>,
+    restitution: Vec<f32>,
+    sizes: Vec<(usize, usize)>,
+    // Missing from saves made before draw layers
+    #[serde(default)]
+    layers: Vec<i32>,
+    // Ticks into each entity's animation
+    anim_times: Vec<usize>,
+    level: usize,
+    camera: Vec2i,
+    score: usize,
+    combo: ScoreTracker,
+    collected: usize,
+    health: u8,
+    lives: u8,
+    iframes: usize,
+    players: Vec<PlayerSave>,
+    timer: Timer,
+    seed: u64,
+    rng_draws: u64,
+}
+
+/// Where autosaves go, kept apart from manual saves so an autosave never
+/// overwrites a save the player chose to make.  Join it onto
+/// `config::save_dir`.
+pub const AUTOSAVE_FILE: &str = "autosave.json";
+
+fn bad_data(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// `path` with `suffix` tacked on, e.g. save.json.bak
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(OsStr::new(suffix));
+    PathBuf::from(name)
+}
+
+/// Where `write_atomic` keeps the previous version of `path`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, ".bak")
+}
+
+/// Replace `path` with `contents` all at once: write a temporary file next
+/// to it and flush it to disk, move the old `path` to `backup_path`, then
+/// rename the temporary file into place.  A crash or power loss partway
+/// through leaves a complete save in one place or the other, never half of
+/// one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = sibling(path, ".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    if path.exists() {
+        fs::rename(path, backup_path(path))?;
+    }
+    fs::rename(&tmp, path)
+}
+
+// Read and sanity check a save without touching any game state
+fn read_save(path: &Path) -> io::Result<SaveState> {
+    let text = fs::read_to_string(path)?;
+    let save: SaveState = serde_json::from_str(&text).map_err(bad_data)?;
+    let n = save.types.len();
+    let lengths_match = [
+        save.ent_states.len(),
+        save.positions.len(),
+        save.velocities.len(),
+        save.restitution.len(),
+        save.sizes.len(),
+        save.anim_times.len(),
+    ]
+    .iter()
+    .all(|&len| len == n);
+    if !lengths_match || save.players.iter().any(|p| p.entity >= n) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Save file entities don't line up",
+        ));
+    }
+    Ok(save)
+}
+
+/// Saves the game every so often without the player asking.  Call `tick`
+/// every step, and `save_now` at moments worth saving, like starting a
+/// new level.
+pub struct Autosave {
+    pub path: PathBuf,
+    interval: Interval,
+}
+
+impl Autosave {
+    /// Autosave to `path` every `every` seconds (never if it's 0).
+    pub fn new(path: impl Into<PathBuf>, every: f64) -> Self {
+        Self {
+            path: path.into(),
+            interval: Interval::new(every),
+        }
+    }
+    /// Advance by `dt` seconds, saving if it's time.  Returns whether it
+    /// saved.
+    pub fn tick(&mut self, state: &GameState, dt: f64) -> io::Result<bool> {
+        if self.interval.tick(dt) > 0 {
+            state.save_full(&self.path)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    /// Save right away, and wait a full interval before the next one.
+    pub fn save_now(&mut self, state: &GameState) -> io::Result<()> {
+        self.interval.reset();
+        state.save_full(&self.path)
+    }
+}
+
+impl GameState {
+    /// Write the whole game to `path`, as JSON, so `load_full` can restore
+    /// it mid-run.  The file is replaced atomically with `write_atomic`.
+    pub fn save_full(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let save = SaveState {
+            types: self.types.clone(),
+            ent_states: self.ent_states.clone(),
+            positions: self.positions.clone(),
+            velocities: self.velocities.clone(),
+            restitution: self.restitution.clone(),
+            sizes: self.sizes.clone(),
+            layers: self.layers.clone(),
+            anim_times: self.anim_state.iter().map(|a| a.time()).collect(),
+            level: self.level,
+            camera: self.camera,
+            score: self.score,
+            combo: self.combo,
+            collected: self.collected,
+            health: self.health,
+            lives: self.lives,
+            iframes: self.iframes,
+            players: self
+                .players
+                .iter()
+                .map(|p| PlayerSave {
+                    entity: p.entity,
+                    jumps_left: p.jumps_left,
+                    facing: p.facing,
+                    dash_frames: p.dash_frames,
+                    dash_cooldown: p.dash_cooldown,
+                    climbing: p.climbing,
+                    crouch: p.crouch,
+                })
+                .collect(),
+            timer: self.timer,
+            seed: self.seed,
+            rng_draws: self.rng.draws(),
+        };
+        let text = serde_json::to_string(&save).map_err(bad_data)?;
+        write_atomic(path.as_ref(), text.as_bytes())
+    }
+
+    /// Replace this game with the one `save_full` wrote to `path`.  If
+    /// that's missing or corrupt, the previous save in `backup_path` is
+    /// loaded instead.  Entity textures come from `entity_tex`, like
+    /// `load_level`; players keep their current key bindings.  Tilemaps
+    /// aren't saved, so `world` is left alone: load the saved `level`'s
+    /// world afterwards.  On error nothing is changed, and the error is the
+    /// one for `path`.
+    pub fn load_full(
+        &mut self,
+        path: impl AsRef<Path>,
+        entity_tex: impl Fn(EntityType) -> Rc<CpuTexture>,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let save = match read_save(path) {
+            Ok(save) => save,
+            Err(e) => match read_save(&backup_path(path)) {
+                Ok(save) => {
+                    log::warn!("Couldn't load {} ({}); using the backup", path.display(), e);
+                    save
+                }
+                Err(_) => return Err(e),
+            },
+        };
+        self.anim_state = save
+            .ent_states
+            .iter()
+            .zip(save.anim_times.iter())
+            .map(|(es, &time)| {
+                let mut anim = self.entity_anims.for_state(es).start();
+                anim.seek(time);
+                anim
+            })
+            .collect();
+        self.textures = save.types.iter().map(|&t| entity_tex(t)).collect();
+        self.players = save
+            .players
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let bindings = self
+                    .players
+                    .get(i)
+                    .map_or(KeyBindings::ARROWS, |old| old.bindings);
+                Player {
+                    jumps_left: p.jumps_left,
+                    facing: p.facing,
+                    dash_frames: p.dash_frames,
+                    dash_cooldown: p.dash_cooldown,
+                    climbing: p.climbing,
+                    crouch: p.crouch,
+                    ..Player::new(p.entity, bindings)
+                }
+            })
+            .collect();
+        self.layers = if save.layers.len() == save.types.len() {
+            save.layers
+        } else {
+            save.types.iter().map(|t| t.default_layer()).collect()
+        };
+        self.types = save.types;
+        self.ent_states = save.ent_states;
+        self.positions = save.positions;
+        self.velocities = save.velocities;
+        self.restitution = save.restitution;
+        self.sizes = save.sizes;
+        self.level = save.level;
+        self.camera = save.camera;
+        self.score = save.score;
+        self.combo = save.combo;
+        self.collected = save.collected;
+        self.health = save.health;
+        self.lives = save.lives;
+        self.iframes = save.iframes;
+        self.timer = save.timer;
+        self.seed = save.seed;
+        self.rng = GameRng::restore(save.seed, save.rng_draws);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::state::tests::{game_state, texture};
+    use crate::logic::timer::{TimeUp, TimerMode};
+    use rand::RngCore;
+
+    // A fresh, empty directory of our own under the system's temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("engine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut saved = game_state();
+        saved.add_player(Vec2i(20, -8), texture(4, 4), KeyBindings::WASD);
+        saved.positions[0] = Vec2i(-3, 7);
+        saved.velocities[1] = Vec2i(2, -5);
+        saved.ent_states[1] = EntityState::Jumping;
+        saved.players[1].jumps_left = 1;
+        saved.players[1].facing = -1;
+        saved.score = 1200;
+        saved.lives = 2;
+        saved.timer = Timer::new(TimerMode::Down(90.0, TimeUp::GameOver));
+        saved.timer.start();
+        saved.timer.tick(1.5);
+        for _ in 0..5 {
+            saved.rng.next_u32();
+        }
+        let path = std::env::temp_dir().join(format!("engine-save-{}.json", std::process::id()));
+        saved.save_full(&path).unwrap();
+
+        let mut loaded = game_state();
+        loaded.load_full(&path, |_| texture(4, 4)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.types, saved.types);
+        assert_eq!(loaded.ent_states, saved.ent_states);
+        assert_eq!(loaded.positions, saved.positions);
+        assert_eq!(loaded.velocities, saved.velocities);
+        assert_eq!(loaded.players.len(), 2);
+        assert_eq!(loaded.players[1].entity, saved.players[1].entity);
+        assert_eq!(loaded.players[1].jumps_left, 1);
+        assert_eq!(loaded.players[1].facing, -1);
+        assert_eq!(loaded.score, 1200);
+        assert_eq!(loaded.lives, 2);
+        assert_eq!(loaded.timer, saved.timer);
+        assert_eq!(loaded.timer.seconds(), 88.5);
+        // Picks up the random stream where the save left off
+        assert_eq!(loaded.rng.next_u32(), saved.rng.next_u32());
+    }
+
+    #[test]
+    fn write_atomic_moves_the_old_file_to_the_backup() {
+        let dir = temp_dir("atomic");
+        let path = dir.join("save.json");
+        write_atomic(&path, b"first").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+        assert!(!backup_path(&path).exists());
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(fs::read(backup_path(&path)).unwrap(), b"first");
+        // The temporary file got renamed into place, not left behind
+        assert!(!sibling(&path, ".tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_save_falls_back_to_the_backup() {
+        let dir = temp_dir("fallback");
+        let path = dir.join("save.json");
+        let mut state = game_state();
+        state.score = 100;
+        state.save_full(&path).unwrap();
+        state.score = 200;
+        state.save_full(&path).unwrap();
+        // Cut off partway through, as if the disk filled up
+        let text = fs::read(&path).unwrap();
+        fs::write(&path, &text[..text.len() / 2]).unwrap();
+
+        let mut loaded = game_state();
+        loaded.load_full(&path, |_| texture(4, 4)).unwrap();
+        assert_eq!(loaded.score, 100);
+
+        // With both gone bad, it's an error and nothing changes
+        fs::write(backup_path(&path), b"{").unwrap();
+        let mut loaded = game_state();
+        let err = loaded.load_full(&path, |_| texture(4, 4)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(loaded.score, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+/// Combo scoring: every scoring event within `window` frames of the last
+/// one bumps the multiplier, and going `window` frames without scoring
+/// drops it back to 1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ScoreTracker {
+    // Frames a combo survives without scoring
+    pub window: usize,
+    pub max_multiplier: usize,
+    multiplier: usize,
+    // Frames since the last scoring event
+    idle: usize,
+}
+
+impl ScoreTracker {
+    pub fn new(window: usize, max_multiplier: usize) -> Self {
+        Self {
+            window,
+            max_multiplier,
+            multiplier: 1,
+            idle: 0,
+        }
+    }
+    /// What points are worth right now, for the HUD.
+    pub fn multiplier(&self) -> usize {
+        self.multiplier
+    }
+    /// Frames left before the combo drops, or 0 with no combo going.
+    pub fn time_left(&self) -> usize {
+        if self.multiplier > 1 {
+            self.window.saturating_sub(self.idle)
+        } else {
+            0
+        }
+    }
+    /// Score `points` at the current multiplier, then raise the multiplier
+    /// for the next one.  Returns the points actually earned.
+    pub fn score(&mut self, points: usize) -> usize {
+        let earned = points * self.multiplier;
+        self.multiplier = (self.multiplier + 1).min(self.max_multiplier);
+        self.idle = 0;
+        earned
+    }
+    /// Call once per frame; ends the combo once it's been idle too long.
+    pub fn tick(&mut self) {
+        self.idle += 1;
+        if self.idle >= self.window {
+            self.multiplier = 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplier_rises_inside_the_window() {
+        let mut combo = ScoreTracker::new(10, 3);
+        assert_eq!(combo.score(5), 5);
+        combo.tick();
+        assert_eq!(combo.score(5), 10);
+        combo.tick();
+        assert_eq!(combo.score(5), 15);
+        // Capped at max_multiplier
+        assert_eq!(combo.score(5), 15);
+        assert_eq!(combo.multiplier(), 3);
+    }
+
+    #[test]
+    fn multiplier_holds_up_to_the_window_boundary() {
+        let mut combo = ScoreTracker::new(10, 8);
+        combo.score(1);
+        // One frame short of the window: the combo is still going
+        for _ in 0..9 {
+            combo.tick();
+        }
+        assert_eq!(combo.multiplier(), 2);
+        assert_eq!(combo.time_left(), 1);
+        assert_eq!(combo.score(1), 2);
+        assert_eq!(combo.multiplier(), 3);
+    }
+
+    #[test]
+    fn multiplier_resets_after_an_idle_window() {
+        let mut combo = ScoreTracker::new(10, 8);
+        combo.score(1);
+        combo.score(1);
+        for _ in 0..10 {
+            combo.tick();
+        }
+        assert_eq!(combo.multiplier(), 1);
+        assert_eq!(combo.time_left(), 0);
+        assert_eq!(combo.score(1), 1);
+    }
+}
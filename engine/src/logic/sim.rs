@@ -0,0 +1,453 @@
+use crate::logic::{
+    collision::{
+        force_on, gather_player_contacts, hurt_player, hurt_player_hazards, ladder_tops, on_ladder,
+        rect_mtv, standing_on, wall_side, RectContact,
+    },
+    platformer::{update_crouch, update_player_state, update_velocity, PlatformerConfig},
+    projectiles::Projectiles,
+    state::{GameState, Inputs},
+    tiles::{Tile, World},
+    timer::TimeUp,
+    types::{Rect, Vec2i},
+};
+use crate::schedule::Time;
+
+/// One frame of gameplay with no window, GPU, or input device involved:
+/// everything it needs is the `GameState`, the level's solid geometry, and
+/// that frame's `Inputs`.  Given the same seed and the same inputs it always
+/// produces the same state, so a script of inputs can drive it from a test.
+pub struct Sim {
+    pub config: PlatformerConfig,
+    // Level geometry the player can't move through
+    pub solids: Vec<Rect>,
+    // Spots that hurt the player to touch
+    pub hazards: Vec<Rect>,
+    // Spots the player can climb
+    pub ladders: Vec<Rect>,
+    // Slippery floors, and water to swim in
+    pub ice: Vec<Rect>,
+    pub water: Vec<Rect>,
+    // Spots that push whatever is on or in them, and how hard
+    pub forces: Vec<(Rect, Vec2i)>,
+    // Speed the player gets knocked away from an enemy at
+    pub knockback: i32,
+    // Reaching this region, or any of `goals`, finishes the level
+    pub exit: Option<Rect>,
+    pub goals: Vec<Rect>,
+    // Shots in flight; games `spawn` into this and `step` moves them
+    pub projectiles: Projectiles,
+    contacts: Vec<RectContact>,
+}
+
+/// What happened on a frame of `Sim::step`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct StepEvents {
+    pub hurt: bool,
+    pub died: bool,
+    pub picked_up: usize,
+    // Enemies the players' shots took out
+    pub enemies_shot: usize,
+    // The death animation is over; time for `StateType::GameOver`
+    pub game_over: bool,
+    // A player reached `exit`; time for `GameState::next_level`
+    pub level_complete: bool,
+    // The level's countdown ran out on this frame
+    pub time_up: Option<TimeUp>,
+}
+
+impl Sim {
+    pub fn new(config: PlatformerConfig, solids: Vec<Rect>) -> Self {
+        Self {
+            config,
+            solids,
+            hazards: vec![],
+            ladders: vec![],
+            ice: vec![],
+            water: vec![],
+            forces: vec![],
+            knockback: 3,
+            exit: None,
+            goals: vec![],
+            projectiles: Projectiles::default(),
+            contacts: vec![],
+        }
+    }
+
+    /// A sim for the tiles of `world`: its solid, hazard, ladder, ice,
+    /// water, goal and force tiles.
+    pub fn with_world(config: PlatformerConfig, world: &World) -> Self {
+        let mut sim = Self::new(config, world.tile_rects(Tile::is_solid));
+        sim.hazards = world.tile_rects(Tile::is_hazard);
+        sim.ladders = world.tile_rects(Tile::is_ladder);
+        sim.ice = world.tile_rects(Tile::is_ice);
+        sim.water = world.tile_rects(Tile::is_water);
+        sim.goals = world.tile_rects(Tile::is_goal);
+        sim.forces = world.tile_forces();
+        sim
+    }
+
+    /// Run a frame.  `inputs` holds one entry per player, in the order of
+    /// `state.players`; players without an entry get no input.  `time` is
+    /// the step `run` hands to `update`, and drives the level's `timer`.
+    pub fn step(&mut self, state: &mut GameState, inputs: &[Inputs], time: Time) -> StepEvents {
+        let mut events = StepEvents::default();
+        for (p, player) in state.players.iter_mut().enumerate() {
+            player.inputs = inputs.get(p).copied().unwrap_or_else(Inputs::new);
+        }
+        for p in 0..state.players.len() {
+            let e = state.players[p].entity;
+            let r = state.entity_rect(e);
+            state.players[p].ladder_contact = on_ladder(r, &self.ladders);
+            state.players[p].on_ice = standing_on(r, &self.ice);
+            state.players[p].in_water = self.water.iter().any(|w| r.overlaps(w));
+            update_crouch(state, p, &self.config, &self.solids);
+            update_velocity(state, p, &self.config);
+        }
+        // Force tiles move things along without touching their velocity, so
+        // a player standing still on a conveyor still gets carried
+        for e in 0..state.positions.len() {
+            let Vec2i(fx, fy) = force_on(state.entity_rect(e), &self.forces);
+            let vel = state.velocities[e];
+            state.positions[e].0 += vel.0 + fx;
+            state.positions[e].1 += vel.1 + fy;
+        }
+        for p in 0..state.players.len() {
+            let e = state.players[p].entity;
+            // Wall contact has to be checked before pushing the player back
+            // out, or they'd only ever be touching the wall edge-on
+            state.players[p].wall_contact = wall_side(state.entity_rect(e), &self.solids);
+            self.push_out_of_solids(state, e);
+            if !state.players[p].climbing {
+                self.stand_on_ladder_tops(state, e);
+            }
+        }
+        // Anything bouncy (a ball, a thrown object) bounces off the level too
+        for e in 0..state.types.len() {
+            if state.restitution[e] > 0.0 && !state.is_player(e) {
+                self.push_out_of_solids(state, e);
+            }
+        }
+
+        let health = (state.health, state.lives);
+        let shots = self.projectiles.update(state, &self.solids);
+        events.enemies_shot = shots.enemies_killed;
+        gather_player_contacts(state, &mut self.contacts);
+        events.died = shots.player_died
+            || hurt_player(state, &self.contacts, self.knockback)
+            || hurt_player_hazards(state, &self.hazards, self.knockback);
+        events.hurt = events.died || (state.health, state.lives) != health;
+        events.picked_up = state.collect_pickups();
+        events.level_complete = !state.dying()
+            && state.players.iter().any(|p| {
+                let r = state.entity_rect(p.entity);
+                self.exit
+                    .iter()
+                    .chain(self.goals.iter())
+                    .any(|g| r.touches(g))
+            });
+
+        for p in 0..state.players.len() {
+            update_player_state(state, p);
+        }
+        events.time_up = state.timer.tick(time.dt);
+        state.tick_iframes();
+        state.combo.tick();
+        state.tick_anims();
+        events.game_over = state.death_finished();
+        events
+    }
+
+    /// Feed `script` to `step` one frame at a time, stopping early on game
+    /// over.  Each frame of the script has inputs for every player and lasts
+    /// `dt` seconds.  Returns the events of every frame that ran.
+    pub fn run_script(
+        &mut self,
+        state: &mut GameState,
+        script: &[Vec<Inputs>],
+        dt: f64,
+    ) -> Vec<StepEvents> {
+        let mut log = Vec::with_capacity(script.len());
+        for (frame, inputs) in script.iter().enumerate() {
+            let events = self.step(state, inputs, Time::new(frame, dt));
+            log.push(events);
+            if events.game_over {
+                break;
+            }
+        }
+        log
+    }
+
+    // Ladder tops only stop things coming down onto them from above
+    fn stand_on_ladder_tops(&self, state: &mut GameState, e: usize) {
+        let r = state.entity_rect(e);
+        let vel = state.velocities[e];
+        let bottom = r.y + r.h as i32;
+        for top in ladder_tops(&self.ladders) {
+            let was_above = bottom - vel.1 <= top.y;
+            let across = r.x < top.x + top.w as i32 && top.x < r.x + r.w as i32;
+            if vel.1 >= 0 && was_above && bottom >= top.y && across {
+                state.positions[e].1 = top.y - r.h as i32;
+                state.velocities[e].1 = 0;
+            }
+        }
+    }
+
+    fn push_out_of_solids(&self, state: &mut GameState, e: usize) {
+        for solid in self.solids.iter() {
+            if let Some(Vec2i(x, y)) = rect_mtv(state.entity_rect(e), *solid) {
+                let bounce = state.restitution[e];
+                let (pos, vel) = (&mut state.positions[e], &mut state.velocities[e]);
+                pos.0 += x;
+                pos.1 += y;
+                // Stop moving into whatever we hit, or bounce off it
+                if x.signum() == -vel.0.signum() {
+                    vel.0 = (-vel.0 as f32 * bounce) as i32;
+                }
+                if y.signum() == -vel.1.signum() {
+                    vel.1 = (-vel.1 as f32 * bounce) as i32;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::projectiles::Owner;
+    use crate::logic::state::{
+        tests::{game_state, texture},
+        EntityState, MAX_HEALTH,
+    };
+    use crate::logic::tiles::{Tilemap, Tileset};
+    use crate::logic::timer::{Timer, TimerMode};
+    use std::rc::Rc;
+
+    // Long enough to get through the start of the run animation
+    const FRAMES: usize = 60;
+
+    // A long floor for `game_state`'s 4x4 player to stand on
+    fn sim() -> Sim {
+        let floor = Rect {
+            x: -1000,
+            y: 4,
+            w: 2000,
+            h: 16,
+        };
+        Sim::new(PlatformerConfig::default(), vec![floor])
+    }
+
+    #[test]
+    fn walking_right_covers_run_speed_a_frame() {
+        let mut sim = sim();
+        let mut state = game_state();
+        let right = Inputs {
+            right: true,
+            ..Inputs::new()
+        };
+        let log = sim.run_script(&mut state, &vec![vec![right]; FRAMES], 1.0 / 60.0);
+        assert_eq!(log.len(), FRAMES);
+        assert_eq!(
+            state.positions[0],
+            Vec2i(sim.config.run_speed * FRAMES as i32, 0)
+        );
+        assert!(state.ent_states[0] == EntityState::Running);
+    }
+
+    #[test]
+    fn countdown_runs_out_after_its_seconds_of_steps() {
+        let mut sim = sim();
+        let mut state = game_state();
+        state.timer = Timer::new(TimerMode::Down(1.0, TimeUp::GameOver));
+        state.timer.start();
+        let log = sim.run_script(&mut state, &vec![vec![]; 5], 0.25);
+        let ups: Vec<_> = log.iter().map(|events| events.time_up).collect();
+        assert_eq!(ups, vec![None, None, None, Some(TimeUp::GameOver), None]);
+        assert_eq!(state.timer.seconds(), 0.0);
+    }
+
+    #[test]
+    fn step_moves_shots_and_reports_what_they_hit() {
+        let mut sim = sim();
+        let mut state = game_state();
+        sim.projectiles
+            .spawn(Vec2i(6, 0), Vec2i(-4, 0), Owner::Enemy);
+        let events = sim.step(&mut state, &[], Time::new(0, 1.0 / 60.0));
+        assert!(events.hurt);
+        assert!(sim.projectiles.is_empty());
+    }
+
+    fn rect(x: i32, y: i32, w: u16, h: u16) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    fn step(sim: &mut Sim, state: &mut GameState, inputs: Inputs) -> StepEvents {
+        sim.step(state, &[inputs], Time::new(0, 1.0 / 60.0))
+    }
+
+    #[test]
+    fn hazards_hurt_knock_back_and_kill() {
+        let mut sim = sim();
+        let mut state = game_state();
+        // Overlapping the player's right side
+        sim.hazards = vec![rect(2, 0, 8, 4)];
+        let events = step(&mut sim, &mut state, Inputs::new());
+        assert!(events.hurt && !events.died);
+        assert_eq!(state.health, MAX_HEALTH - 1);
+        // Knocked left, away from the hazard, and up
+        assert_eq!(state.velocities[0], Vec2i(-sim.knockback, -sim.knockback));
+
+        // Still invulnerable from that hit
+        let events = step(&mut sim, &mut state, Inputs::new());
+        assert!(!events.hurt);
+
+        state.iframes = 0;
+        state.health = 1;
+        state.lives = 1;
+        state.positions[0] = Vec2i(0, 0);
+        let events = step(&mut sim, &mut state, Inputs::new());
+        assert!(events.died);
+        assert!(state.dying());
+    }
+
+    #[test]
+    fn ice_speeds_up_and_slows_down_gradually() {
+        let mut sim = sim();
+        sim.ice = sim.solids.clone();
+        let mut state = game_state();
+        let right = Inputs {
+            right: true,
+            ..Inputs::new()
+        };
+        let mut xs = vec![];
+        for inputs in [right, right, right, Inputs::new(), Inputs::new()].iter() {
+            step(&mut sim, &mut state, *inputs);
+            xs.push(state.positions[0].0);
+        }
+        assert_eq!(xs, vec![1, 3, 5, 6, 6]);
+    }
+
+    #[test]
+    fn water_slows_falling_and_lets_the_player_swim_up() {
+        let mut sim = Sim::new(PlatformerConfig::default(), vec![]);
+        sim.water = vec![rect(-100, -100, 200, 200)];
+        let mut state = game_state();
+        for _ in 0..10 {
+            step(&mut sim, &mut state, Inputs::new());
+        }
+        assert_eq!(state.velocities[0].1, sim.config.swim_fall_speed);
+        let stroke = Inputs {
+            space: true,
+            ..Inputs::new()
+        };
+        for _ in 0..3 {
+            step(&mut sim, &mut state, stroke);
+            assert_eq!(state.velocities[0].1, -sim.config.jump_speed);
+        }
+    }
+
+    #[test]
+    fn reaching_a_goal_tile_completes_the_level() {
+        let mut sim = sim();
+        sim.goals = vec![rect(20, 0, 4, 4)];
+        let mut state = game_state();
+        let right = Inputs {
+            right: true,
+            ..Inputs::new()
+        };
+        let log = sim.run_script(&mut state, &vec![vec![right]; FRAMES], 1.0 / 60.0);
+        let first = log.iter().position(|events| events.level_complete);
+        // Touching it from x = 16, 8 frames of walking at 2 a frame
+        assert_eq!(first, Some(7));
+    }
+
+    #[test]
+    fn climbing_up_a_ladder_and_jumping_off() {
+        let mut sim = sim();
+        // Up from the floor the player is standing on
+        sim.ladders = vec![rect(0, -44, 16, 48)];
+        let mut state = game_state();
+        let up = Inputs {
+            up: true,
+            ..Inputs::new()
+        };
+        for _ in 0..5 {
+            step(&mut sim, &mut state, up);
+        }
+        assert!(state.players[0].climbing);
+        assert_eq!(state.ent_states[0], EntityState::Climbing);
+        assert_eq!(state.positions[0], Vec2i(0, -5 * sim.config.climb_speed));
+
+        // Letting go of up holds on; jumping lets go
+        step(&mut sim, &mut state, Inputs::new());
+        assert_eq!(state.positions[0], Vec2i(0, -5 * sim.config.climb_speed));
+        let jump = Inputs {
+            space: true,
+            ..Inputs::new()
+        };
+        step(&mut sim, &mut state, jump);
+        assert!(!state.players[0].climbing);
+        assert_eq!(state.velocities[0].1, -sim.config.jump_speed);
+        assert_eq!(state.ent_states[0], EntityState::Jumping);
+    }
+
+    #[test]
+    fn falling_onto_a_ladder_lands_on_its_top() {
+        let mut sim = Sim::new(PlatformerConfig::default(), vec![]);
+        sim.ladders = vec![rect(0, 8, 16, 16), rect(0, 24, 16, 16)];
+        let mut state = game_state();
+        state.positions[0] = Vec2i(0, -20);
+        // Long enough to land and finish the landing animation
+        for _ in 0..FRAMES {
+            step(&mut sim, &mut state, Inputs::new());
+        }
+        assert_eq!(state.positions[0], Vec2i(0, 4));
+        assert_eq!(state.velocities[0].1, 0);
+        assert!(!state.players[0].climbing);
+        assert_eq!(state.ent_states[0], EntityState::Standing);
+
+        // Down from the top climbs down onto the ladder
+        let down = Inputs {
+            down: true,
+            ..Inputs::new()
+        };
+        step(&mut sim, &mut state, down);
+        assert!(state.players[0].climbing);
+        assert_eq!(state.positions[0], Vec2i(0, 4 + sim.config.climb_speed));
+    }
+
+    // A one-row world of 16px tiles along y = 4, under `game_state`'s player
+    fn floor_world(tiles: Vec<Tile>, map: Vec<usize>) -> World {
+        let tex = texture(16 * tiles.len() as u32, 16);
+        let tileset = Rc::new(Tileset::new(tiles, tex));
+        let dims = (map.len(), 1);
+        World::new(vec![Tilemap::new(Vec2i(0, 4), dims, &tileset, map)])
+    }
+
+    #[test]
+    fn conveyor_carries_the_idle_player() {
+        let conveyor = Tile::SOLID.with_force(Vec2i(1, 0));
+        let world = floor_world(vec![conveyor], vec![0; 8]);
+        let mut sim = Sim::with_world(PlatformerConfig::default(), &world);
+        let mut state = game_state();
+        for frame in 1..=10 {
+            step(&mut sim, &mut state, Inputs::new());
+            assert_eq!(state.positions[0], Vec2i(frame, 0));
+        }
+        // Carried, not walking
+        assert_eq!(state.velocities[0], Vec2i(0, 0));
+    }
+
+    #[test]
+    fn overlapping_forces_add_up() {
+        let conveyor = Tile::SOLID.with_force(Vec2i(1, 0));
+        let world = floor_world(vec![conveyor], vec![0; 8]);
+        let mut sim = Sim::with_world(PlatformerConfig::default(), &world);
+        // Wind blowing the same way, and a little up, over the belt
+        sim.forces.push((rect(0, -16, 128, 20), Vec2i(2, -1)));
+        let mut state = game_state();
+        step(&mut sim, &mut state, Inputs::new());
+        // Both push right, and the lift cancels out a frame of gravity
+        assert_eq!(state.positions[0], Vec2i(3, 0));
+    }
+}
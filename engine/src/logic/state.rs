@@ -1,35 +1,125 @@
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
-use crate::logic::types::{Rect, Vec2i};
+use crate::logic::camera::Camera;
+use crate::logic::platformer::WallSide;
+use crate::logic::rng::GameRng;
+use crate::logic::score::ScoreTracker;
+use crate::logic::generator::MapPool;
+use crate::logic::menu::{main_menu, Menu, MenuEntry};
+use crate::logic::tiles::{Tilemap, World};
+use crate::logic::timer::{Timer, TimerMode};
+use crate::logic::types::{Rect, Rgba, Vec2i};
 use crate::{
     audio::audio::SoundChannels,
+    input::{InputSource, KeyBindings, KeyRepeat},
     graphics::{
-        animation::{Animation, AnimationState},
+        animation::{Animation, AnimationState, SpriteSheet},
+        gpu::SpriteInstance,
+        screen::Screen,
         texture::CpuTexture,
     },
 };
-use rand::StdRng;
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
 
 #[derive(Clone)]
 pub enum StateType {
+    // Assets are still loading in the background (see `Preloader`)
+    Loading(GameState),
     Menu(GameState),
     Playing(GameState),
     GameOver(GameState),
+    // The game's win condition was met (e.g. `LevelProgress::Finished`)
+    Victory(GameState),
+    // Asking whether to really quit, e.g. after picking `MenuEntry::Quit`
+    // or closing the window; backing out returns to the state inside
+    ConfirmQuit(Box<StateType>),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+impl StateType {
+    pub fn game_state(&self) -> &GameState {
+        match self {
+            StateType::Loading(s)
+            | StateType::Menu(s)
+            | StateType::Playing(s)
+            | StateType::GameOver(s)
+            | StateType::Victory(s) => s,
+            StateType::ConfirmQuit(from) => from.game_state(),
+        }
+    }
+    pub fn game_state_mut(&mut self) -> &mut GameState {
+        match self {
+            StateType::Loading(s)
+            | StateType::Menu(s)
+            | StateType::Playing(s)
+            | StateType::GameOver(s)
+            | StateType::Victory(s) => s,
+            StateType::ConfirmQuit(from) => from.game_state_mut(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum EntityType {
     Player,
     Enemy,
+    Pickup,
+}
+
+impl EntityType {
+    /// The draw layer `GameState::spawn` gives this type: pickups at the
+    /// bottom, then enemies, with players on top of everything.
+    pub fn default_layer(self) -> i32 {
+        match self {
+            EntityType::Pickup => 0,
+            EntityType::Enemy => 1,
+            EntityType::Player => 2,
+        }
+    }
+}
+
+/// Everything that makes up one level.  This is the one definition the
+/// engine and both kinds of game share: fixed levels fill in `tilemaps`
+/// and `spawns`, and endless runners also fill in the last three fields.
+#[derive(Clone)]
+pub struct Level {
+    /// The chunks on screen when the level starts, in world coordinates.
+    pub tilemaps: Vec<Tilemap>,
+    /// Entities to spawn, as type and world position.  The `Player` entry
+    /// is where the players start rather than an extra player.
+    pub spawns: Vec<(EntityType, i32, i32)>,
+    /// Endless levels: chunks to pick from as the player runs on, easiest
+    /// first.  Empty for fixed levels.
+    pub map_pool: Vec<Tilemap>,
+    /// Endless levels: a chunk with nothing to dodge, for breathers or to
+    /// start with when there's no `start_map`.
+    pub empty_map: Option<Tilemap>,
+    /// Endless levels: the first chunk generated.
+    pub start_map: Option<Tilemap>,
+}
+
+impl Level {
+    /// The level's starting chunks, for collision and drawing.
+    pub fn world(&self) -> World {
+        World::new(self.tilemaps.clone())
+    }
+    /// A generator for the rest of an endless level, or `None` for a fixed
+    /// level.
+    pub fn generator(&self) -> Option<MapPool> {
+        let start = self.start_map.as_ref().or_else(|| self.empty_map.as_ref())?;
+        Some(MapPool::new(start.clone(), self.map_pool.clone()))
+    }
 }
 
-pub type Level = (
-    Vec<(EntityType, i32, i32)>,
-    Vec<Vec<usize>>,
-    Vec<usize>,
-    Vec<usize>,
-);
+/// Where `GameState::next_level` left things.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LevelProgress {
+    // Now playing this level
+    Started(usize),
+    // That was the last level
+    Finished,
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Inputs {
@@ -38,6 +128,7 @@ pub struct Inputs {
     pub left: bool,
     pub right: bool,
     pub space: bool,
+    pub dash: bool,
     pub esc: bool,
 }
 
@@ -49,25 +140,162 @@ impl Inputs {
             left: false,
             right: false,
             space: false,
+            dash: false,
             esc: false,
         }
     }
+    /// Pack into a byte, one bit per button, e.g. to send over the network.
+    pub fn to_bits(&self) -> u8 {
+        [
+            self.up, self.down, self.left, self.right, self.space, self.dash, self.esc,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (i, b)| bits | ((*b as u8) << i))
+    }
+    pub fn from_bits(bits: u8) -> Inputs {
+        let bit = |i: u8| bits & (1 << i) != 0;
+        Inputs {
+            up: bit(0),
+            down: bit(1),
+            left: bit(2),
+            right: bit(3),
+            space: bit(4),
+            dash: bit(5),
+            esc: bit(6),
+        }
+    }
+    /// Inputs for gameplay with the default (arrow key) bindings.
+    pub fn gameplay(input: &dyn InputSource) -> Inputs {
+        Inputs::gameplay_with(input, &KeyBindings::ARROWS)
+    }
+    /// Inputs for gameplay: directions are held, but jump and dash only
+    /// count on the frame their key goes down.
+    pub fn gameplay_with(input: &dyn InputSource, keys: &KeyBindings) -> Inputs {
+        Inputs {
+            up: input.held(keys.up),
+            down: input.held(keys.down),
+            left: input.held(keys.left),
+            right: input.held(keys.right),
+            space: input.pressed(keys.jump),
+            dash: input.pressed(keys.dash),
+            esc: input.pressed(keys.pause),
+        }
+    }
+    /// Inputs for menus, with held arrow keys repeating after `MENU_DELAY`
+    /// frames, every `MENU_RATE` frames, so long lists can be scrolled.
+    /// Everything else only counts when it goes down.
+    pub fn menu_repeating(input: &dyn InputSource, repeat: &mut KeyRepeat) -> Inputs {
+        let mut arrow = |key| repeat.key_repeat(input, key, MENU_DELAY, MENU_RATE);
+        Inputs {
+            up: arrow(VirtualKeyCode::Up),
+            down: arrow(VirtualKeyCode::Down),
+            left: arrow(VirtualKeyCode::Left),
+            right: arrow(VirtualKeyCode::Right),
+            ..Inputs::menu(input)
+        }
+    }
+    /// Inputs for menus, where every key only counts when it goes down.
+    pub fn menu(input: &dyn InputSource) -> Inputs {
+        Inputs {
+            up: input.pressed(VirtualKeyCode::Up),
+            down: input.pressed(VirtualKeyCode::Down),
+            left: input.pressed(VirtualKeyCode::Left),
+            right: input.pressed(VirtualKeyCode::Right),
+            space: input.pressed(VirtualKeyCode::Space),
+            dash: input.pressed(VirtualKeyCode::LShift),
+            esc: input.pressed(VirtualKeyCode::Escape),
+        }
+    }
+}
+
+/// Control state for one player-controlled entity.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Player {
+    // Index of the player's entity
+    pub entity: usize,
+    pub bindings: KeyBindings,
+    pub inputs: Inputs,
+    // Jumps the player can still make before landing
+    pub jumps_left: u8,
+    // Which side of the player is up against a wall, if any
+    pub wall_contact: Option<WallSide>,
+    // Is the player on a ladder (or standing on top of one), and are they
+    // holding on to it?
+    pub ladder_contact: bool,
+    pub climbing: bool,
+    // Is the player standing on ice, or in water?  Set every frame, like
+    // `ladder_contact`.
+    pub on_ice: bool,
+    pub in_water: bool,
+    // While crouching, the height to stand back up to
+    pub crouch: Option<usize>,
+    // -1 if the player last moved left, 1 if right
+    pub facing: i32,
+    // Frames left in the current dash, and until another dash is allowed
+    pub dash_frames: usize,
+    pub dash_cooldown: usize,
+}
+
+impl Player {
+    pub fn new(entity: usize, bindings: KeyBindings) -> Self {
+        Self {
+            entity,
+            bindings,
+            inputs: Inputs::new(),
+            jumps_left: 0,
+            wall_contact: None,
+            ladder_contact: false,
+            climbing: false,
+            on_ice: false,
+            in_water: false,
+            crouch: None,
+            facing: 1,
+            dash_frames: 0,
+            dash_cooldown: 0,
+        }
+    }
 }
 
 // Frame Numbers
-const STAND_FRAME: u16 = 0;
-const RUN_START: u16 = 1;
-const MID_RUN: u16 = 14;
-const RUN_END: u16 = 22;
-const JUMP_START: u16 = 24;
-const JUMP_END: u16 = 29;
-const FALL_START: u16 = 30;
-const FALL_END: u16 = 36;
-const LAND_START: u16 = 37;
-const LAND_END: u16 = 47;
+const STAND_FRAME: usize = 0;
+const RUN_START: usize = 1;
+const MID_RUN: usize = 14;
+const RUN_END: usize = 22;
+const JUMP_START: usize = 24;
+const JUMP_END: usize = 29;
+const FALL_START: usize = 30;
+const FALL_END: usize = 36;
+const LAND_START: usize = 37;
+const LAND_END: usize = 47;
+// No wall-slide frames on the sheet; hold the last falling one
+const WALL_SLIDE_START: usize = 35;
+const WALL_SLIDE_END: usize = 36;
+// The bottom row squashes flat, which reads as dying well enough
+const DIE_START: usize = 40;
+const DIE_END: usize = 48;
 const FRAME_LEN: usize = 3;
+// The player sheet is 8 columns and 6 rows of 25x16 frames
+const SHEET_COLS: u16 = 8;
+const SHEET_ROWS: u16 = 6;
+const FRAME_W: u16 = 25;
+const FRAME_H: u16 = 16;
 
-#[derive(Clone, Eq, PartialEq)]
+// Damage
+pub const MAX_HEALTH: u8 = 3;
+pub const START_LIVES: u8 = 3;
+// How many frames of invulnerability after a hit
+pub const IFRAMES: usize = 90;
+// Frames between pickups to keep a combo going, and the biggest multiplier
+pub const COMBO_WINDOW: usize = 120;
+pub const MAX_MULTIPLIER: usize = 5;
+// Frames before a held menu key repeats, and between repeats
+pub const MENU_DELAY: usize = 20;
+pub const MENU_RATE: usize = 5;
+// Blink the player on and off this many frames at a time while invulnerable
+const BLINK_LEN: usize = 6;
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum EntityState {
     Standing,
     StartRun,
@@ -75,6 +303,10 @@ pub enum EntityState {
     Jumping,
     Falling,
     Landing,
+    WallSliding,
+    Climbing,
+    Crouching,
+    Dying,
 }
 
 #[derive(Clone)]
@@ -85,61 +317,765 @@ pub struct EntityAnims {
     pub jumping: Rc<Animation>,
     pub falling: Rc<Animation>,
     pub landing: Rc<Animation>,
+    pub wall_sliding: Rc<Animation>,
+    pub climbing: Rc<Animation>,
+    pub crouching: Rc<Animation>,
+    pub dying: Rc<Animation>,
 }
 
 impl EntityAnims {
     pub fn new() -> EntityAnims {
+        let sheet = SpriteSheet::new(SHEET_COLS, SHEET_ROWS, FRAME_W, FRAME_H);
         EntityAnims {
-            standing: Rc::new(gen_frames(STAND_FRAME, RUN_START, FRAME_LEN, true)),
-            start_run: Rc::new(gen_frames(RUN_START, MID_RUN, FRAME_LEN, false)),
-            running: Rc::new(gen_frames(MID_RUN, RUN_END, FRAME_LEN, true)),
-            jumping: Rc::new(gen_frames(JUMP_START, JUMP_END, FRAME_LEN, false)),
-            falling: Rc::new(gen_frames(FALL_START, FALL_END, FRAME_LEN, true)),
-            landing: Rc::new(gen_frames(LAND_START, LAND_END, FRAME_LEN, false)),
+            standing: Rc::new(sheet.animation(STAND_FRAME..RUN_START, FRAME_LEN, true)),
+            start_run: Rc::new(sheet.animation(RUN_START..MID_RUN, FRAME_LEN, false)),
+            running: Rc::new(sheet.animation(MID_RUN..RUN_END, FRAME_LEN, true)),
+            jumping: Rc::new(sheet.animation(JUMP_START..JUMP_END, FRAME_LEN, false)),
+            falling: Rc::new(sheet.animation(FALL_START..FALL_END, FRAME_LEN, true)),
+            landing: Rc::new(sheet.animation(LAND_START..LAND_END, FRAME_LEN, false)),
+            wall_sliding: Rc::new(sheet.animation(WALL_SLIDE_START..WALL_SLIDE_END, FRAME_LEN, true)),
+            // No climbing frames yet; hugging a ladder looks enough like
+            // hugging a wall
+            climbing: Rc::new(sheet.animation(WALL_SLIDE_START..WALL_SLIDE_END, FRAME_LEN, true)),
+            // Nor crouching frames; hold the squash from landing
+            crouching: Rc::new(sheet.animation(LAND_START..LAND_START + 1, FRAME_LEN, true)),
+            dying: Rc::new(sheet.animation(DIE_START..DIE_END, FRAME_LEN, false)),
+        }
+    }
+    /// The animation an entity in `state` plays.
+    pub fn for_state(&self, state: &EntityState) -> &Rc<Animation> {
+        match state {
+            EntityState::Standing => &self.standing,
+            EntityState::StartRun => &self.start_run,
+            EntityState::Running => &self.running,
+            EntityState::Jumping => &self.jumping,
+            EntityState::Falling => &self.falling,
+            EntityState::Landing => &self.landing,
+            EntityState::WallSliding => &self.wall_sliding,
+            EntityState::Climbing => &self.climbing,
+            EntityState::Crouching => &self.crouching,
+            EntityState::Dying => &self.dying,
         }
     }
 }
 
-fn gen_frames(start: u16, end: u16, len: usize, looping: bool) -> Animation {
-    let mut anim = Vec::new();
-    for frame in start..end {
-        let w = 25;
-        let h = 16;
-        let x = (frame * w) % 200;
-        let y = (frame / 8) * h;
-        anim.push((
-            Rect {
-                w,
-                h,
-                x: x as i32,
-                y: y as i32,
-            },
-            len,
-        ));
+/// One entity's slice of the `GameState` parallel Vecs, from
+/// `GameState::entity`.
+pub struct EntityRef<'a> {
+    pub id: usize,
+    pub etype: EntityType,
+    pub state: &'a EntityState,
+    pub pos: Vec2i,
+    pub vel: Vec2i,
+    pub restitution: f32,
+    pub size: (usize, usize),
+    pub layer: i32,
+    pub texture: &'a Rc<CpuTexture>,
+    pub anim: &'a AnimationState,
+}
+
+impl EntityRef<'_> {
+    pub fn rect(&self) -> Rect {
+        Rect {
+            x: self.pos.0,
+            y: self.pos.1,
+            w: self.size.0 as u16,
+            h: self.size.1 as u16,
+        }
     }
-    Animation::new(anim, looping)
+}
+
+/// Like `EntityRef` but mutable, from `GameState::entity_mut`.  The type
+/// and texture are read-only: change those by despawning and respawning.
+pub struct EntityMut<'a> {
+    pub id: usize,
+    pub etype: EntityType,
+    pub state: &'a mut EntityState,
+    pub pos: &'a mut Vec2i,
+    pub vel: &'a mut Vec2i,
+    pub restitution: &'a mut f32,
+    pub size: &'a mut (usize, usize),
+    pub layer: &'a mut i32,
+    pub anim: &'a mut AnimationState,
 }
 
 #[derive(Clone)]
 pub struct GameState {
     // Every entity has a position, a size, a texture, and animation state.
-    // Assume entity 0 is the player
+    // Entity 0 is always player one; `players` lists any others
     pub types: Vec<EntityType>,
     pub ent_states: Vec<EntityState>,
     pub positions: Vec<Vec2i>,
     pub velocities: Vec<Vec2i>,
+    // How bouncy each entity is against solids: 0 stops dead, 1 bounces
+    // back at full speed
+    pub restitution: Vec<f32>,
     pub sizes: Vec<(usize, usize)>,
+    // Entities on higher layers draw on top; see `draw_order`
+    pub layers: Vec<i32>,
     pub textures: Vec<Rc<CpuTexture>>,
     pub anim_state: Vec<AnimationState>,
-    // Current level
+    // Current level, and its tilemaps as they stand (endless levels
+    // stream chunks in and out)
     pub level: usize,
+    pub world: World,
     // Camera position
     pub camera: Vec2i,
     pub inputs: Inputs,
-    pub menu_entry: usize,
+    // The title screen's menu, highlight and all
+    pub menu: Menu<MenuEntry>,
+    // How long each menu key has been held, for `Inputs::menu_repeating`
+    pub menu_repeat: KeyRepeat,
     pub entity_anims: EntityAnims,
     pub sound_channels: Arc<Mutex<SoundChannels>>,
     pub score: usize,
+    // Scoring goes through here so quick chains of pickups multiply
+    pub combo: ScoreTracker,
+    // How many pickups the player has grabbed
+    pub collected: usize,
+    pub health: u8,
+    pub lives: u8,
+    // Frames of invulnerability left
+    pub iframes: usize,
+    // Everyone under player control; health, lives and iframes are shared
+    pub players: Vec<Player>,
+    // Speedrun or survival clock; games pick the mode and start it
+    pub timer: Timer,
     pub seed: u64,
-    pub rng: StdRng,
+    pub rng: GameRng,
+}
+
+impl GameState {
+    /// A fresh game with just the player, standing at the origin.  Everything
+    /// random in the game should come from `rng`, so the same seed and the
+    /// same inputs always play out the same way.
+    pub fn new(seed: u64, player_tex: Rc<CpuTexture>) -> Self {
+        let entity_anims = EntityAnims::new();
+        let mut state = GameState {
+            types: vec![],
+            ent_states: vec![],
+            positions: vec![],
+            velocities: vec![],
+            restitution: vec![],
+            sizes: vec![],
+            layers: vec![],
+            textures: vec![],
+            anim_state: vec![],
+            level: 0,
+            world: World::new(vec![]),
+            camera: Vec2i(0, 0),
+            inputs: Inputs::new(),
+            menu: main_menu(),
+            menu_repeat: KeyRepeat::new(),
+            entity_anims,
+            sound_channels: Arc::new(Mutex::new(SoundChannels::new())),
+            score: 0,
+            combo: ScoreTracker::new(COMBO_WINDOW, MAX_MULTIPLIER),
+            collected: 0,
+            health: MAX_HEALTH,
+            lives: START_LIVES,
+            iframes: 0,
+            players: vec![],
+            timer: Timer::new(TimerMode::Up),
+            seed,
+            rng: GameRng::new(seed),
+        };
+        state.add_player(Vec2i(0, 0), player_tex, KeyBindings::ARROWS);
+        state
+    }
+    /// Spawn another player-controlled entity, e.g. for local co-op.
+    /// Returns its index in `players`.
+    pub fn add_player(&mut self, pos: Vec2i, tex: Rc<CpuTexture>, bindings: KeyBindings) -> usize {
+        let standing = self.entity_anims.standing.start();
+        let entity = self.spawn(
+            EntityType::Player,
+            pos,
+            (FRAME_W as usize, FRAME_H as usize),
+            tex,
+            standing,
+        );
+        self.players.push(Player::new(entity, bindings));
+        self.players.len() - 1
+    }
+    /// Read this frame's menu inputs into `inputs`, with key repeat.
+    pub fn read_menu_inputs(&mut self, input: &dyn InputSource) {
+        self.inputs = Inputs::menu_repeating(input, &mut self.menu_repeat);
+    }
+    /// Read every player's inputs from their own bindings.
+    pub fn read_player_inputs(&mut self, input: &dyn InputSource) {
+        for p in self.players.iter_mut() {
+            p.inputs = Inputs::gameplay_with(input, &p.bindings);
+        }
+    }
+    /// Where the camera should aim to keep everyone in view: the middle of
+    /// the box around all the players.
+    pub fn players_center(&self) -> Vec2i {
+        let rects: Vec<Rect> = self.players.iter().map(|p| self.entity_rect(p.entity)).collect();
+        let x0 = rects.iter().map(|r| r.x).min().unwrap_or(0);
+        let y0 = rects.iter().map(|r| r.y).min().unwrap_or(0);
+        let x1 = rects.iter().map(|r| r.x + r.w as i32).max().unwrap_or(0);
+        let y1 = rects.iter().map(|r| r.y + r.h as i32).max().unwrap_or(0);
+        Vec2i((x0 + x1) / 2, (y0 + y1) / 2)
+    }
+    pub fn is_player(&self, entity: usize) -> bool {
+        self.players.iter().any(|p| p.entity == entity)
+    }
+    /// How many entities there are.  If the parallel Vecs have somehow
+    /// come apart, only the entities every one of them has count.
+    pub fn entity_count(&self) -> usize {
+        [
+            self.types.len(),
+            self.ent_states.len(),
+            self.positions.len(),
+            self.velocities.len(),
+            self.restitution.len(),
+            self.sizes.len(),
+            self.layers.len(),
+            self.textures.len(),
+            self.anim_state.len(),
+        ]
+        .iter()
+        .copied()
+        .min()
+        .unwrap_or(0)
+    }
+    /// Entity `id`, or `None` if there's no such entity.  Prefer this to
+    /// indexing the Vecs directly when `id` came from somewhere that might
+    /// be stale.
+    pub fn entity(&self, id: usize) -> Option<EntityRef<'_>> {
+        if id >= self.entity_count() {
+            return None;
+        }
+        Some(EntityRef {
+            id,
+            etype: self.types[id],
+            state: &self.ent_states[id],
+            pos: self.positions[id],
+            vel: self.velocities[id],
+            restitution: self.restitution[id],
+            size: self.sizes[id],
+            layer: self.layers[id],
+            texture: &self.textures[id],
+            anim: &self.anim_state[id],
+        })
+    }
+    pub fn entity_mut(&mut self, id: usize) -> Option<EntityMut<'_>> {
+        if id >= self.entity_count() {
+            return None;
+        }
+        Some(EntityMut {
+            id,
+            etype: self.types[id],
+            state: &mut self.ent_states[id],
+            pos: &mut self.positions[id],
+            vel: &mut self.velocities[id],
+            restitution: &mut self.restitution[id],
+            size: &mut self.sizes[id],
+            layer: &mut self.layers[id],
+            anim: &mut self.anim_state[id],
+        })
+    }
+    /// Player one's entity, rather than assuming it's entity 0.
+    pub fn player(&self) -> Option<EntityRef<'_>> {
+        self.entity(self.players.first()?.entity)
+    }
+    pub fn player_mut(&mut self) -> Option<EntityMut<'_>> {
+        let id = self.players.first()?.entity;
+        self.entity_mut(id)
+    }
+    /// Add an entity and return its index.
+    pub fn spawn(
+        &mut self,
+        etype: EntityType,
+        pos: Vec2i,
+        size: (usize, usize),
+        tex: Rc<CpuTexture>,
+        anim: AnimationState,
+    ) -> usize {
+        self.types.push(etype);
+        self.ent_states.push(EntityState::Standing);
+        self.positions.push(pos);
+        self.velocities.push(Vec2i(0, 0));
+        self.restitution.push(0.0);
+        self.sizes.push(size);
+        self.layers.push(etype.default_layer());
+        self.textures.push(tex);
+        self.anim_state.push(anim);
+        debug_assert_eq!(self.entity_count(), self.types.len(), "Entity Vecs out of step");
+        self.types.len() - 1
+    }
+    /// Set up `levels[index]`: its tilemaps become `world`, every entity
+    /// but the players goes away, the level's entities are spawned (with
+    /// textures from `entity_tex`), the players stand at the level's player
+    /// spawn, and `camera` snaps to them.  Build the level's `Sim` from the
+    /// new `world` afterwards.
+    pub fn load_level(
+        &mut self,
+        levels: &[Level],
+        index: usize,
+        camera: &mut Camera,
+        entity_tex: impl Fn(EntityType) -> Rc<CpuTexture>,
+    ) {
+        self.world = levels[index].world();
+        // Back to front, so whatever despawn swaps into slot i is already
+        // known to be a player
+        for i in (0..self.types.len()).rev() {
+            if !self.is_player(i) {
+                self.despawn(i);
+            }
+        }
+        let mut player_spawn = Vec2i(0, 0);
+        for &(etype, x, y) in levels[index].spawns.iter() {
+            if etype == EntityType::Player {
+                player_spawn = Vec2i(x, y);
+                continue;
+            }
+            let anim = self.entity_anims.standing.start();
+            self.spawn(
+                etype,
+                Vec2i(x, y),
+                (FRAME_W as usize, FRAME_H as usize),
+                entity_tex(etype),
+                anim,
+            );
+        }
+        // Players line up side by side from the spawn point
+        for (p, player) in self.players.iter().enumerate() {
+            let e = player.entity;
+            self.positions[e] = Vec2i(player_spawn.0 + (p * FRAME_W as usize) as i32, player_spawn.1);
+            self.velocities[e] = Vec2i(0, 0);
+            self.ent_states[e] = EntityState::Standing;
+            self.anim_state[e] = self.entity_anims.standing.start();
+        }
+        self.level = index;
+        camera.stop();
+        camera.snap_to(self.players_center());
+        self.camera = camera.position();
+    }
+    /// Start over from the first level: no score, full health and lives,
+    /// the clock back at zero and running, and the same random numbers as
+    /// last time.  The players, their bindings and the sound carry over.
+    pub fn restart(
+        &mut self,
+        levels: &[Level],
+        camera: &mut Camera,
+        entity_tex: impl Fn(EntityType) -> Rc<CpuTexture>,
+    ) {
+        self.score = 0;
+        self.combo = ScoreTracker::new(COMBO_WINDOW, MAX_MULTIPLIER);
+        self.collected = 0;
+        self.health = MAX_HEALTH;
+        self.lives = START_LIVES;
+        self.iframes = 0;
+        self.rng = GameRng::new(self.seed);
+        self.load_level(levels, 0, camera, entity_tex);
+        self.timer.reset();
+        self.timer.start();
+    }
+    /// Move on from the current level once it's complete: load the next
+    /// one if there is one.
+    pub fn next_level(
+        &mut self,
+        levels: &[Level],
+        camera: &mut Camera,
+        entity_tex: impl Fn(EntityType) -> Rc<CpuTexture>,
+    ) -> LevelProgress {
+        let next = self.level + 1;
+        if next < levels.len() {
+            self.load_level(levels, next, camera, entity_tex);
+            LevelProgress::Started(next)
+        } else {
+            LevelProgress::Finished
+        }
+    }
+    pub fn entity_rect(&self, i: usize) -> Rect {
+        Rect {
+            x: self.positions[i].0,
+            y: self.positions[i].1,
+            w: self.sizes[i].0 as u16,
+            h: self.sizes[i].1 as u16,
+        }
+    }
+    /// Fill `into` with entity indices in the order to draw them: by layer,
+    /// then by where their feet are, so an entity lower on screen overlaps
+    /// the ones behind it.  Ties go by index so nothing flickers.
+    pub fn draw_order(&self, into: &mut Vec<usize>) {
+        into.clear();
+        into.extend(0..self.entity_count());
+        into.sort_by_key(|&e| (self.layers[e], self.positions[e].1 + self.sizes[e].1 as i32, e));
+    }
+    /// One sprite instance per entity, showing its current animation
+    /// frame, in `draw_order` (which is left in `order`), with players
+    /// tinted by `player_tint`.  Like every other sprite producer these are
+    /// in game pixels; `SpriteRenderer` takes them to clip space with the
+    /// rest.  Each instance sits a little nearer than the one before it, so
+    /// the order holds with the depth test on as well as when drawn back to
+    /// front.
+    pub fn sprite_instances(&self, order: &mut Vec<usize>, into: &mut Vec<SpriteInstance>) {
+        self.draw_order(order);
+        into.clear();
+        let n = order.len() as f32;
+        for (rank, &e) in order.iter().enumerate() {
+            let (tex_w, tex_h) = self.textures[e].size();
+            let Vec2i(x, y) = self.positions[e];
+            let (w, h) = self.sizes[e];
+            let inst = SpriteInstance::new(
+                [x as f32, y as f32, 1.0 - (rank as f32 + 1.0) / (n + 1.0)],
+                [w as f32, h as f32],
+                self.anim_state[e].frame(),
+                (tex_w as f32, tex_h as f32),
+            );
+            // Players blink while they can't be hurt
+            into.push(if self.is_player(e) {
+                inst.with_tint(self.player_tint())
+            } else {
+                inst
+            });
+        }
+    }
+    /// Remove entity `i`.  The last entity takes its slot, so indices past
+    /// `i` aren't stable across a despawn (`players` is kept up to date).
+    /// Players can't be despawned.
+    pub fn despawn(&mut self, i: usize) {
+        assert!(!self.is_player(i), "Can't despawn a player");
+        let last = self.types.len() - 1;
+        for p in self.players.iter_mut() {
+            if p.entity == last {
+                p.entity = i;
+            }
+        }
+        self.types.swap_remove(i);
+        self.ent_states.swap_remove(i);
+        self.positions.swap_remove(i);
+        self.velocities.swap_remove(i);
+        self.restitution.swap_remove(i);
+        self.sizes.swap_remove(i);
+        self.layers.swap_remove(i);
+        self.textures.swap_remove(i);
+        self.anim_state.swap_remove(i);
+        debug_assert_eq!(self.entity_count(), self.types.len(), "Entity Vecs out of step");
+    }
+    /// Hurt the player, unless they're still invulnerable from the last hit.
+    /// Running out of health costs a life and refills health.  Returns true
+    /// once the last life is gone, i.e. it's game over.
+    pub fn take_damage(&mut self, amount: u8) -> bool {
+        if self.iframes > 0 {
+            return false;
+        }
+        self.health = self.health.saturating_sub(amount);
+        self.iframes = IFRAMES;
+        if self.health == 0 {
+            self.lives = self.lives.saturating_sub(1);
+            if self.lives == 0 {
+                return true;
+            }
+            self.health = MAX_HEALTH;
+        }
+        false
+    }
+    /// Start the players' death animation; lives are shared, so everyone
+    /// goes down together.  Switch to `StateType::GameOver` once
+    /// `death_finished` says it's over, rather than right away.
+    pub fn kill_player(&mut self) {
+        for i in 0..self.players.len() {
+            let e = self.players[i].entity;
+            if self.ent_states[e] != EntityState::Dying {
+                self.ent_states[e] = EntityState::Dying;
+                self.velocities[e] = Vec2i(0, 0);
+                self.anim_state[e].play(&self.entity_anims.dying, true);
+            }
+        }
+    }
+    pub fn dying(&self) -> bool {
+        self.player()
+            .map_or(false, |p| *p.state == EntityState::Dying)
+    }
+    pub fn death_finished(&self) -> bool {
+        self.dying()
+            && self
+                .players
+                .iter()
+                .all(|p| self.anim_state[p.entity].done())
+    }
+    pub fn invulnerable(&self) -> bool {
+        self.iframes > 0
+    }
+    /// Call once per frame to run down the invulnerability window.
+    pub fn tick_iframes(&mut self) {
+        self.iframes = self.iframes.saturating_sub(1);
+    }
+    /// Tint to draw the player with: blinks while invulnerable.
+    pub fn player_tint(&self) -> Rgba {
+        if self.invulnerable() && (self.iframes / BLINK_LEN) % 2 == 0 {
+            Rgba(255, 64, 64, 128)
+        } else {
+            Rgba(255, 255, 255, 255)
+        }
+    }
+    /// Draw every entity's current animation frame, the players tinted by
+    /// `player_tint` so they blink while they can't be hurt.
+    pub fn draw(&self, screen: &mut Screen) {
+        for i in 0..self.types.len() {
+            let tint = if self.is_player(i) {
+                self.player_tint()
+            } else {
+                Rgba(255, 255, 255, 255)
+            };
+            screen.bitblt_tinted(
+                &self.textures[i],
+                self.anim_state[i].frame(),
+                self.positions[i],
+                tint,
+            );
+        }
+    }
+    /// Draw a whole frame on the CPU: `bg`, then the world's tiles, then
+    /// every entity over them.
+    pub fn draw_frame(&self, screen: &mut Screen, bg: Rgba) {
+        screen.clear(bg);
+        for map in self.world.tilemaps.iter() {
+            map.draw(screen);
+        }
+        self.draw(screen);
+    }
+    /// Advance every entity's animation by a frame (pickups spin, etc).
+    pub fn tick_anims(&mut self) {
+        for anim in self.anim_state.iter_mut() {
+            anim.tick();
+        }
+    }
+    /// Like an enemy hit test, but non-lethal: every pickup a player is
+    /// touching despawns and bumps `collected`.  Returns how many were
+    /// grabbed this frame.
+    pub fn collect_pickups(&mut self) -> usize {
+        let players: Vec<Rect> = self.players.iter().map(|p| self.entity_rect(p.entity)).collect();
+        let mut grabbed = 0;
+        // Walk backwards so despawning doesn't skip anyone
+        for i in (1..self.types.len()).rev() {
+            let rect = self.entity_rect(i);
+            if self.types[i] == EntityType::Pickup && players.iter().any(|p| p.touches(&rect)) {
+                self.despawn(i);
+                self.score += self.combo.score(1);
+                grabbed += 1;
+            }
+        }
+        self.collected += grabbed;
+        grabbed
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::graphics::gpu::GameSize;
+    use image::{Rgba as Pixel, RgbaImage};
+
+    pub(crate) fn texture(w: u32, h: u32) -> Rc<CpuTexture> {
+        Rc::new(CpuTexture::new(RgbaImage::from_pixel(
+            w,
+            h,
+            Pixel([255, 255, 255, 255]),
+        )))
+    }
+
+    /// Just the player, standing still at the origin, 4 pixels square.
+    pub(crate) fn game_state() -> GameState {
+        let mut state = GameState::new(0, texture(4, 4));
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            w: 4,
+            h: 4,
+        };
+        state.sizes[0] = (4, 4);
+        state.anim_state[0] = Rc::new(Animation::freeze(frame)).start();
+        state
+    }
+
+    #[test]
+    fn entity_anims_stay_on_the_sheet() {
+        let sheet = texture(
+            (SHEET_COLS * FRAME_W) as u32,
+            (SHEET_ROWS * FRAME_H) as u32,
+        );
+        let anims = EntityAnims::new();
+        for state in [
+            EntityState::Standing,
+            EntityState::StartRun,
+            EntityState::Running,
+            EntityState::Jumping,
+            EntityState::Falling,
+            EntityState::Landing,
+            EntityState::WallSliding,
+            EntityState::Climbing,
+            EntityState::Crouching,
+            EntityState::Dying,
+        ]
+        .iter()
+        {
+            let anim = anims.for_state(state);
+            let mut playing = anim.start();
+            for t in 0..anim.duration() {
+                playing.seek(t);
+                assert!(
+                    sheet.valid_frame(playing.frame()),
+                    "{:?} shows {:?} at {}",
+                    state,
+                    playing.frame(),
+                    t
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn player_blinks_while_invulnerable() {
+        let mut state = game_state();
+        let (w, h) = (8, 8);
+        let mut fb = vec![0; w * h * 4];
+        let pixel = |state: &GameState, fb: &mut Vec<u8>| {
+            fb.iter_mut().for_each(|b| *b = 0);
+            state.draw(&mut Screen::wrap(fb, w, h, 4, Vec2i(0, 0)));
+            [fb[0], fb[1], fb[2], fb[3]]
+        };
+        assert_eq!(pixel(&state, &mut fb), [255, 255, 255, 255]);
+
+        // Mid-blink the player is drawn with the blink tint...
+        state.iframes = BLINK_LEN * 2;
+        assert_eq!(state.player_tint(), Rgba(255, 64, 64, 128));
+        assert_eq!(pixel(&state, &mut fb), [255, 64, 64, 128]);
+        // ...and between blinks, as normal
+        state.iframes = BLINK_LEN;
+        assert_eq!(pixel(&state, &mut fb), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn cpu_frame_draws_the_world_under_the_entities() {
+        use crate::graphics::screen::Framebuffer;
+        use crate::logic::tiles::{Tile, Tileset};
+        let red = Rc::new(CpuTexture::new(RgbaImage::from_pixel(
+            16,
+            16,
+            Pixel([255, 0, 0, 255]),
+        )));
+        let tileset = Rc::new(Tileset::new(vec![Tile::SOLID], red));
+        let mut state = game_state();
+        state.world = World::new(vec![Tilemap::new(Vec2i(0, 16), (2, 1), &tileset, vec![0; 2])]);
+        state.positions[0] = Vec2i(0, 12);
+
+        let mut fb = Framebuffer::new(32, 32);
+        state.draw_frame(&mut fb.screen(Vec2i(0, 0)), Rgba(0, 0, 255, 255));
+        let pixel = |x: usize, y: usize| {
+            let i = (y * 32 + x) * 4;
+            fb.buffer()[i..i + 4].to_vec()
+        };
+        assert_eq!(pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(8, 24), [255, 0, 0, 255]);
+        // The player stands on the tiles, drawn over the background
+        assert_eq!(pixel(0, 12), [255, 255, 255, 255]);
+        assert_eq!(pixel(0, 16), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn every_player_blinks() {
+        let mut state = game_state();
+        let second = state.add_player(Vec2i(4, 0), texture(4, 4), KeyBindings::WASD);
+        let e = state.players[second].entity;
+        state.anim_state[e] = state.anim_state[0].clone();
+        let (w, h) = (8, 4);
+        let mut fb = vec![0; w * h * 4];
+        state.iframes = BLINK_LEN * 2;
+        state.draw(&mut Screen::wrap(&mut fb, w, h, 4, Vec2i(0, 0)));
+        // Player two's top left pixel is tinted the same as player one's
+        assert_eq!(fb[0..4], [255, 64, 64, 128]);
+        assert_eq!(fb[16..20], [255, 64, 64, 128]);
+    }
+
+    #[test]
+    fn players_are_drawn_with_their_tint() {
+        let mut state = GameState::new(0, texture(100, 100));
+        let pickup = state.entity_anims.standing.start();
+        state.spawn(EntityType::Pickup, Vec2i(40, 0), (8, 8), texture(100, 100), pickup);
+        let (mut order, mut instances) = (vec![], vec![]);
+        state.sprite_instances(&mut order, &mut instances);
+        assert!(instances.iter().all(|i| i.tint == [1.0; 4]));
+
+        // Mid-blink: the player is tinted, the pickup isn't
+        state.iframes = BLINK_LEN * 2;
+        assert!(state.invulnerable());
+        state.sprite_instances(&mut order, &mut instances);
+        let frame = Rect {
+            x: 0,
+            y: 0,
+            w: 1,
+            h: 1,
+        };
+        let blink = SpriteInstance::new([0.0; 3], [0.0; 2], frame, (1.0, 1.0))
+            .with_tint(state.player_tint());
+        assert_eq!(instances[1].tint, blink.tint);
+        assert_ne!(instances[1].tint, [1.0; 4]);
+        assert_eq!(instances[0].tint, [1.0; 4]);
+    }
+
+    #[test]
+    fn entity_instances_share_the_sprite_clip_conversion() {
+        let mut state = GameState::new(0, texture(100, 100));
+        let pickup = state.entity_anims.standing.start();
+        state.spawn(EntityType::Pickup, Vec2i(160, 120), (8, 8), texture(100, 100), pickup);
+        let (mut order, mut instances) = (vec![], vec![]);
+        state.sprite_instances(&mut order, &mut instances);
+
+        // The pickup's layer is behind the player's, so it's drawn first
+        assert_eq!(order, vec![1, 0]);
+        assert_eq!(instances[0].pos[..2], [160.0, 120.0]);
+        assert_eq!(instances[1].pos[..2], [0.0, 0.0]);
+        assert!(instances[1].pos[2] < instances[0].pos[2]);
+
+        let game = GameSize::new(320, 240);
+        assert_eq!(game.instance_to_clip(&instances[0]).pos[..2], [0.0, 0.0]);
+        let player = game.instance_to_clip(&instances[1]);
+        assert_eq!(player.pos[..2], [-1.0, 1.0]);
+        assert_eq!(player.size, game.scale(FRAME_W as f32, FRAME_H as f32));
+    }
+
+    #[test]
+    fn load_level_swaps_in_the_world_and_snaps_the_camera_to_the_spawn() {
+        use crate::logic::tiles::{Tile, Tileset};
+        let tileset = Rc::new(Tileset::new(vec![Tile::SOLID], texture(16, 16)));
+        let level = |x| Level {
+            tilemaps: vec![Tilemap::new(Vec2i(x, 40), (4, 1), &tileset, vec![0; 4])],
+            spawns: vec![
+                (EntityType::Enemy, x + 60, 20),
+                (EntityType::Player, x + 40, 20),
+            ],
+            map_pool: vec![],
+            empty_map: None,
+            start_map: None,
+        };
+        let levels = vec![level(0), level(1000)];
+        let mut state = game_state();
+        let mut camera = Camera::new((64, 48));
+        camera.smoothing = 0.0;
+
+        state.load_level(&levels, 0, &mut camera, |_| texture(4, 4));
+        assert_eq!(state.world.tilemaps.len(), 1);
+        assert!(state.world.tile_at_world(Vec2i(0, 40)).is_some());
+        assert_eq!(state.types, vec![EntityType::Player, EntityType::Enemy]);
+        assert_eq!(state.positions[0], Vec2i(40, 20));
+        // Centered on the 4x4 player
+        assert_eq!(state.camera, Vec2i(42 - 32, 22 - 24));
+
+        assert_eq!(
+            state.next_level(&levels, &mut camera, |_| texture(4, 4)),
+            LevelProgress::Started(1)
+        );
+        assert!(state.world.tile_at_world(Vec2i(0, 40)).is_none());
+        assert!(state.world.tile_at_world(Vec2i(1000, 40)).is_some());
+        assert_eq!(state.types.len(), 2);
+        assert_eq!(state.camera, Vec2i(1042 - 32, 22 - 24));
+        assert_eq!(
+            state.next_level(&levels, &mut camera, |_| texture(4, 4)),
+            LevelProgress::Finished
+        );
+    }
 }
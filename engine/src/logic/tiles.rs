@@ -0,0 +1,563 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::{BitOr, Range};
+use std::rc::Rc;
+
+use crate::graphics::{gpu::SpriteInstance, screen::Screen, texture::CpuTexture};
+use crate::logic::types::{Rect, Vec2i};
+
+/// Tile size for tilesets that don't say otherwise.
+pub const TILE_SZ: u16 = 16;
+
+/// What a tile does to entities touching it.  Combine them with `|`, e.g.
+/// `TileFlags::SOLID | TileFlags::ICE` for a slippery floor.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TileFlags(u8);
+
+impl TileFlags {
+    pub const NONE: TileFlags = TileFlags(0);
+    // Can't be moved through
+    pub const SOLID: TileFlags = TileFlags(1 << 0);
+    // Hurts whoever touches it
+    pub const HAZARD: TileFlags = TileFlags(1 << 1);
+    // Low friction underfoot
+    pub const ICE: TileFlags = TileFlags(1 << 2);
+    // Slows falling, and the player can swim up through it
+    pub const WATER: TileFlags = TileFlags(1 << 3);
+    // Can be climbed
+    pub const LADDER: TileFlags = TileFlags(1 << 4);
+    // Finishes the level
+    pub const GOAL: TileFlags = TileFlags(1 << 5);
+
+    /// Is every flag in `other` set?
+    pub fn contains(self, other: TileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for TileFlags {
+    type Output = TileFlags;
+    fn bitor(self, other: TileFlags) -> TileFlags {
+        TileFlags(self.0 | other.0)
+    }
+}
+
+/// One kind of tile in a `Tileset`.  Every property defaults to off, so a
+/// plain `Tile::default()` is empty space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Tile {
+    pub flags: TileFlags,
+    // Units/frame this tile carries anything on or in it (conveyors, wind)
+    pub force: Vec2i,
+}
+
+impl Tile {
+    pub const EMPTY: Tile = Tile::new(TileFlags::NONE);
+    pub const SOLID: Tile = Tile::new(TileFlags::SOLID);
+
+    pub const fn new(flags: TileFlags) -> Tile {
+        Tile {
+            flags,
+            force: Vec2i(0, 0),
+        }
+    }
+    /// This tile, but pushing things by `force`, e.g.
+    /// `Tile::SOLID.with_force(Vec2i(1, 0))` for a conveyor belt.
+    pub const fn with_force(self, force: Vec2i) -> Tile {
+        Tile { force, ..self }
+    }
+    pub fn is_solid(self) -> bool {
+        self.flags.contains(TileFlags::SOLID)
+    }
+    pub fn is_hazard(self) -> bool {
+        self.flags.contains(TileFlags::HAZARD)
+    }
+    pub fn is_ice(self) -> bool {
+        self.flags.contains(TileFlags::ICE)
+    }
+    pub fn is_water(self) -> bool {
+        self.flags.contains(TileFlags::WATER)
+    }
+    pub fn is_ladder(self) -> bool {
+        self.flags.contains(TileFlags::LADDER)
+    }
+    pub fn is_goal(self) -> bool {
+        self.flags.contains(TileFlags::GOAL)
+    }
+    pub fn has_force(self) -> bool {
+        self.force != Vec2i(0, 0)
+    }
+}
+
+/// An index into a `Tileset`'s tiles.
+pub type TileID = usize;
+
+/// The tiles a tilemap can use, and the texture they're cut from: tile
+/// `id` is the `id`th `tile_w` x `tile_h` cell, reading left to right and
+/// top to bottom.
+pub struct Tileset {
+    pub tiles: Vec<Tile>,
+    pub tile_w: u16,
+    pub tile_h: u16,
+    texture: Rc<CpuTexture>,
+}
+
+impl Tileset {
+    pub fn new(tiles: Vec<Tile>, texture: Rc<CpuTexture>) -> Self {
+        Self::with_tile_size(tiles, texture, TILE_SZ, TILE_SZ)
+    }
+    pub fn with_tile_size(tiles: Vec<Tile>, texture: Rc<CpuTexture>, tile_w: u16, tile_h: u16) -> Self {
+        Self {
+            tiles,
+            tile_w,
+            tile_h,
+            texture,
+        }
+    }
+    pub fn texture(&self) -> &Rc<CpuTexture> {
+        &self.texture
+    }
+    /// The same tiles cut from `texture` instead, e.g. after the image was
+    /// edited and reloaded.
+    pub fn with_texture(&self, texture: Rc<CpuTexture>) -> Self {
+        Self::with_tile_size(self.tiles.clone(), texture, self.tile_w, self.tile_h)
+    }
+    pub fn contains(&self, id: TileID) -> bool {
+        id < self.tiles.len()
+    }
+    /// Where tile `id` is on the texture.
+    pub fn get_rect(&self, id: TileID) -> Rect {
+        let cols = (self.texture.size().0 / self.tile_w as usize).max(1);
+        Rect {
+            x: ((id % cols) * self.tile_w as usize) as i32,
+            y: ((id / cols) * self.tile_h as usize) as i32,
+            w: self.tile_w,
+            h: self.tile_h,
+        }
+    }
+}
+
+/// A grid of tiles from one tileset, with its top left corner at
+/// `position` in the world.
+pub struct Tilemap {
+    pub position: Vec2i,
+    // In tiles
+    dims: (usize, usize),
+    tileset: Rc<Tileset>,
+    // Row major
+    map: Vec<TileID>,
+    // Where the map was and which tiles were in view at the last
+    // `update_tilemap_instances`, and whether a tile has changed since; if
+    // none of those, its instances are still good
+    drawn_at: Vec2i,
+    drawn_tiles: (Range<usize>, Range<usize>),
+    dirty: bool,
+}
+
+impl Clone for Tilemap {
+    // A copy will be drawn into a buffer of its own, which starts out empty
+    fn clone(&self) -> Self {
+        Self {
+            position: self.position,
+            dims: self.dims,
+            tileset: Rc::clone(&self.tileset),
+            map: self.map.clone(),
+            drawn_at: self.position,
+            drawn_tiles: (0..0, 0..0),
+            dirty: true,
+        }
+    }
+}
+
+/// Something wrong with a map's data, from `Tilemap::validate`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapError {
+    // `dims` says the map should have `expected` tiles, but there are `actual`
+    SizeMismatch { expected: usize, actual: usize },
+    // The tile at column `x`, row `y` (or just index `x`, if the size is
+    // off too) is `id`, which the tileset doesn't have
+    BadTile { x: usize, y: usize, id: TileID },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::SizeMismatch { expected, actual } => {
+                write!(f, "Map should have {} tiles but has {}", expected, actual)
+            }
+            MapError::BadTile { x, y, id } => {
+                write!(f, "Tile ({}, {}) is {}, which isn't in the tileset", x, y, id)
+            }
+        }
+    }
+}
+
+impl Error for MapError {}
+
+impl Tilemap {
+    /// Check map data against its tileset, finding every problem in one
+    /// go instead of stopping at the first, e.g. to list them all in an
+    /// editor or loader.
+    pub fn validate(dims: (usize, usize), tileset: &Tileset, map: &[TileID]) -> Result<(), Vec<MapError>> {
+        let mut errors = vec![];
+        let expected = dims.0 * dims.1;
+        let size_ok = expected == map.len();
+        if !size_ok {
+            errors.push(MapError::SizeMismatch {
+                expected,
+                actual: map.len(),
+            });
+        }
+        for (i, &id) in map.iter().enumerate() {
+            if !tileset.contains(id) {
+                // Without the right size, rows don't mean anything
+                let (x, y) = if size_ok && dims.0 > 0 {
+                    (i % dims.0, i / dims.0)
+                } else {
+                    (i, 0)
+                };
+                errors.push(MapError::BadTile { x, y, id });
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    /// Like `new`, but gives back every problem with the data instead of
+    /// panicking.
+    pub fn try_new(
+        position: Vec2i,
+        dims: (usize, usize),
+        tileset: &Rc<Tileset>,
+        map: Vec<TileID>,
+    ) -> Result<Self, Vec<MapError>> {
+        Self::validate(dims, tileset, &map)?;
+        Ok(Self::new_unchecked(position, dims, tileset, map))
+    }
+    /// A map from data that's known to be good.  Panics, listing what's
+    /// wrong, if the map isn't `dims` big or uses tiles `tileset` doesn't
+    /// have; use `try_new` for data that might be bad.
+    pub fn new(position: Vec2i, dims: (usize, usize), tileset: &Rc<Tileset>, map: Vec<TileID>) -> Self {
+        match Self::try_new(position, dims, tileset, map) {
+            Ok(map) => map,
+            Err(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                panic!("Bad tilemap: {}", errors.join("; "))
+            }
+        }
+    }
+    fn new_unchecked(position: Vec2i, dims: (usize, usize), tileset: &Rc<Tileset>, map: Vec<TileID>) -> Self {
+        Self {
+            position,
+            dims,
+            tileset: Rc::clone(tileset),
+            map,
+            drawn_at: position,
+            drawn_tiles: (0..0, 0..0),
+            dirty: true,
+        }
+    }
+    pub fn dims(&self) -> (usize, usize) {
+        self.dims
+    }
+    pub fn tileset(&self) -> &Rc<Tileset> {
+        &self.tileset
+    }
+    /// Swap in a different tileset, e.g. one with a reloaded texture.  The
+    /// map's tile ids stay the same, so they have to be in `tileset` too.
+    pub fn set_tileset(&mut self, tileset: &Rc<Tileset>) {
+        assert!(
+            self.map.iter().all(|id| tileset.contains(*id)),
+            "Tileset is missing some of the map's tiles"
+        );
+        self.tileset = Rc::clone(tileset);
+        self.dirty = true;
+    }
+    /// Width and height in pixels.
+    pub fn size(&self) -> (usize, usize) {
+        (
+            self.dims.0 * self.tileset.tile_w as usize,
+            self.dims.1 * self.tileset.tile_h as usize,
+        )
+    }
+    /// Is the world point `(x, y)` over the map?  Same bounds as
+    /// `tile_id_at`: the left and top edges are in, the right and bottom
+    /// edges belong to whatever map comes next, so a point on the seam
+    /// between two adjacent maps is in exactly one of them.
+    pub fn contains(&self, Vec2i(x, y): Vec2i) -> bool {
+        let (w, h) = self.size();
+        x >= self.position.0
+            && x < self.position.0 + w as i32
+            && y >= self.position.1
+            && y < self.position.1 + h as i32
+    }
+    /// The column and row of the tile under the world point `(x, y)`, if
+    /// it's on the map.
+    fn tile_coords(&self, Vec2i(x, y): Vec2i) -> Option<(usize, usize)> {
+        let tx = (x - self.position.0).div_euclid(self.tileset.tile_w as i32);
+        let ty = (y - self.position.1).div_euclid(self.tileset.tile_h as i32);
+        if tx >= 0 && tx < self.dims.0 as i32 && ty >= 0 && ty < self.dims.1 as i32 {
+            Some((tx as usize, ty as usize))
+        } else {
+            None
+        }
+    }
+    /// The id of the tile under the world point `(x, y)`, or `None` if
+    /// it's off the map.
+    pub fn try_tile_id_at(&self, posn: Vec2i) -> Option<TileID> {
+        let (tx, ty) = self.tile_coords(posn)?;
+        Some(self.map[ty * self.dims.0 + tx])
+    }
+    /// Like `try_tile_id_at`, for callers that know `posn` is on the map;
+    /// panics otherwise.
+    pub fn tile_id_at(&self, posn: Vec2i) -> TileID {
+        self.try_tile_id_at(posn).expect("Point is off the tilemap")
+    }
+    pub fn tile_at(&self, posn: Vec2i) -> Tile {
+        self.tileset.tiles[self.tile_id_at(posn)]
+    }
+    /// Change the tile in column `tx`, row `ty` to `id`.  Panics if there's
+    /// no such tile or `id` isn't in the tileset.
+    pub fn set_tile_id(&mut self, Vec2i(tx, ty): Vec2i, id: TileID) {
+        assert!(self.tileset.contains(id), "Tile id not in tileset");
+        assert!(
+            tx >= 0 && tx < self.dims.0 as i32 && ty >= 0 && ty < self.dims.1 as i32,
+            "Tile is off the tilemap"
+        );
+        let i = ty as usize * self.dims.0 + tx as usize;
+        if self.map[i] != id {
+            self.map[i] = id;
+            self.dirty = true;
+        }
+    }
+    /// The world rect covered by the tile in column `tx`, row `ty`, or
+    /// `None` if the map has no such tile.
+    pub fn try_get_tile_rect(&self, Vec2i(tx, ty): Vec2i) -> Option<Rect> {
+        if tx < 0 || tx >= self.dims.0 as i32 || ty < 0 || ty >= self.dims.1 as i32 {
+            return None;
+        }
+        let (tw, th) = (self.tileset.tile_w, self.tileset.tile_h);
+        Some(Rect {
+            x: self.position.0 + tx * tw as i32,
+            y: self.position.1 + ty * th as i32,
+            w: tw,
+            h: th,
+        })
+    }
+    /// Like `try_get_tile_rect`, for callers that know the tile exists;
+    /// panics otherwise.
+    pub fn get_tile_rect(&self, tile: Vec2i) -> Rect {
+        self.try_get_tile_rect(tile).expect("Tile is off the tilemap")
+    }
+    /// The columns and rows of the tiles that overlap `view` (in world
+    /// coordinates), as `(left..right, top..bot)`.  Empty if none do.
+    pub fn visible_tiles(&self, view: Rect) -> (Range<usize>, Range<usize>) {
+        if view.w == 0 || view.h == 0 {
+            return (0..0, 0..0);
+        }
+        let (tw, th) = (self.tileset.tile_w as i32, self.tileset.tile_h as i32);
+        let (x0, y0) = (view.x - self.position.0, view.y - self.position.1);
+        let (x1, y1) = (x0 + view.w as i32, y0 + view.h as i32);
+        let clamp = |t: i32, len: usize| t.max(0).min(len as i32) as usize;
+        let left = clamp(x0.div_euclid(tw), self.dims.0);
+        let right = clamp((x1 + tw - 1).div_euclid(tw), self.dims.0);
+        let top = clamp(y0.div_euclid(th), self.dims.1);
+        let bot = clamp((y1 + th - 1).div_euclid(th), self.dims.1);
+        (left..right.max(left), top..bot.max(top))
+    }
+    /// Replace `instances` with a sprite instance for each tile `view` can
+    /// see, for drawing the map with a `SpriteRenderer` and the tileset's
+    /// texture.  Like the map's own position they're in game pixels.  Keep
+    /// a buffer per map: it's only rewritten when the map has changed or
+    /// moved, or different tiles are in view, since the last call, so a
+    /// static map under a still camera costs nothing frame to frame.
+    /// Returns whether it was rewritten, i.e. whether to upload it again.
+    pub fn update_tilemap_instances(&mut self, view: Rect, instances: &mut Vec<SpriteInstance>) -> bool {
+        let visible = self.visible_tiles(view);
+        if !self.dirty && self.drawn_at == self.position && self.drawn_tiles == visible {
+            return false;
+        }
+        let (tw, th) = (self.tileset.tile_w as f32, self.tileset.tile_h as f32);
+        let (tex_w, tex_h) = self.tileset.texture.size();
+        let tex_size = (tex_w as f32, tex_h as f32);
+        instances.clear();
+        for ty in visible.1.clone() {
+            for tx in visible.0.clone() {
+                let r = self.get_tile_rect(Vec2i(tx as i32, ty as i32));
+                instances.push(SpriteInstance::new(
+                    [r.x as f32, r.y as f32, 0.0],
+                    [tw, th],
+                    self.tileset.get_rect(self.map[ty * self.dims.0 + tx]),
+                    tex_size,
+                ));
+            }
+        }
+        self.drawn_at = self.position;
+        self.drawn_tiles = visible;
+        self.dirty = false;
+        true
+    }
+    /// Blit the map into `screen`, skipping tiles it can't see.
+    pub fn draw(&self, screen: &mut Screen) {
+        let (cols, rows) = self.visible_tiles(screen.bounds());
+        for ty in rows {
+            for tx in cols.clone() {
+                let r = self.get_tile_rect(Vec2i(tx as i32, ty as i32));
+                let id = self.map[ty * self.dims.0 + tx];
+                screen.bitblt(&self.tileset.texture, self.tileset.get_rect(id), Vec2i(r.x, r.y));
+            }
+        }
+    }
+}
+
+/// Every tilemap chunk in a level.  Chunks shouldn't overlap; if they do,
+/// the first one listed wins.
+#[derive(Clone)]
+pub struct World {
+    pub tilemaps: Vec<Tilemap>,
+}
+
+impl World {
+    pub fn new(tilemaps: Vec<Tilemap>) -> Self {
+        Self { tilemaps }
+    }
+    /// The chunk the world point `posn` is on, if any.
+    pub fn tilemap_at(&self, posn: Vec2i) -> Option<&Tilemap> {
+        self.tilemaps.iter().find(|map| map.contains(posn))
+    }
+    /// The tile under the world point `posn`, or `None` if no chunk covers
+    /// it.
+    pub fn tile_at_world(&self, posn: Vec2i) -> Option<Tile> {
+        let map = self.tilemap_at(posn)?;
+        map.try_tile_id_at(posn).map(|id| map.tileset.tiles[id])
+    }
+    /// The world rect of every tile `pred` picks, in every chunk, e.g.
+    /// `world.tile_rects(Tile::is_hazard)`.
+    pub fn tile_rects(&self, pred: impl Fn(Tile) -> bool) -> Vec<Rect> {
+        let mut rects = vec![];
+        for map in self.tilemaps.iter() {
+            for (i, &id) in map.map.iter().enumerate() {
+                if pred(map.tileset.tiles[id]) {
+                    let (tx, ty) = (i % map.dims.0, i / map.dims.0);
+                    rects.push(map.get_tile_rect(Vec2i(tx as i32, ty as i32)));
+                }
+            }
+        }
+        rects
+    }
+
+    /// Every tile in the world that pushes things, with its push.
+    pub fn tile_forces(&self) -> Vec<(Rect, Vec2i)> {
+        let mut forces = vec![];
+        for map in self.tilemaps.iter() {
+            for (i, &id) in map.map.iter().enumerate() {
+                let tile = map.tileset.tiles[id];
+                if tile.has_force() {
+                    let (tx, ty) = (i % map.dims.0, i / map.dims.0);
+                    forces.push((map.get_tile_rect(Vec2i(tx as i32, ty as i32)), tile.force));
+                }
+            }
+        }
+        forces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    // Two 4x3 maps of 16px tiles side by side
+    fn two_maps() -> (Tilemap, Tilemap) {
+        let texture = Rc::new(CpuTexture::new(RgbaImage::new(32, 16)));
+        let tileset = Rc::new(Tileset::new(vec![Tile::EMPTY, Tile::SOLID], texture));
+        (
+            Tilemap::new(Vec2i(0, 0), (4, 3), &tileset, vec![0; 12]),
+            Tilemap::new(Vec2i(64, 0), (4, 3), &tileset, vec![1; 12]),
+        )
+    }
+
+    #[test]
+    fn map_owns_its_left_and_top_edges() {
+        let (left, _) = two_maps();
+        assert!(left.contains(Vec2i(0, 0)));
+        assert!(left.contains(Vec2i(63, 47)));
+        assert!(!left.contains(Vec2i(-1, 0)));
+        assert!(!left.contains(Vec2i(0, -1)));
+        assert!(!left.contains(Vec2i(0, 48)));
+    }
+
+    #[test]
+    fn seam_belongs_to_the_next_map() {
+        let (left, right) = two_maps();
+        // x = position + width * tile_w is one past the left map
+        let seam = Vec2i(left.position.0 + 4 * TILE_SZ as i32, 10);
+        assert!(!left.contains(seam));
+        assert!(right.contains(seam));
+        assert_eq!(right.tile_id_at(seam), 1);
+        assert_eq!(left.tile_id_at(Vec2i(seam.0 - 1, 10)), 0);
+    }
+
+    #[test]
+    fn world_looks_up_the_chunk_under_a_point() {
+        let (left, right) = two_maps();
+        let world = World::new(vec![left, right]);
+        let seam = Vec2i(64, 10);
+        assert_eq!(world.tilemap_at(seam).unwrap().position, Vec2i(64, 0));
+        assert_eq!(world.tile_at_world(seam), Some(Tile::SOLID));
+        assert_eq!(world.tile_at_world(Vec2i(63, 10)), Some(Tile::EMPTY));
+        assert_eq!(world.tile_at_world(Vec2i(128, 10)), None);
+    }
+
+    fn view(x: i32, y: i32, w: u16, h: u16) -> Rect {
+        Rect { x, y, w, h }
+    }
+
+    #[test]
+    fn visible_tiles_clips_to_the_map() {
+        let (left, right) = two_maps();
+        // The whole map
+        assert_eq!(left.visible_tiles(view(0, 0, 64, 48)), (0..4, 0..3));
+        // Hanging off the bottom right: the tiles it touches, and no more
+        assert_eq!(left.visible_tiles(view(40, 20, 100, 100)), (2..4, 1..3));
+        // Partly left of and above the map, from negative coordinates
+        assert_eq!(left.visible_tiles(view(-20, -5, 37, 10)), (0..2, 0..1));
+        // Same for a map that isn't at the origin
+        assert_eq!(right.visible_tiles(view(50, 0, 20, 16)), (0..1, 0..1));
+    }
+
+    #[test]
+    fn visible_tiles_is_empty_off_the_map() {
+        let (left, _) = two_maps();
+        let empty = |(cols, rows): (Range<usize>, Range<usize>)| cols.is_empty() || rows.is_empty();
+        assert!(empty(left.visible_tiles(view(-40, 0, 40, 48))));
+        assert!(empty(left.visible_tiles(view(0, -30, 64, 30))));
+        assert!(empty(left.visible_tiles(view(64, 0, 10, 10))));
+        assert!(empty(left.visible_tiles(view(0, 0, 0, 10))));
+    }
+
+    #[test]
+    fn instances_are_only_rebuilt_when_something_changed() {
+        let (mut map, _) = two_maps();
+        let mut instances = vec![];
+        let all = view(0, 0, 64, 48);
+        assert!(map.update_tilemap_instances(all, &mut instances));
+        assert_eq!(instances.len(), 12);
+        // Nothing changed
+        assert!(!map.update_tilemap_instances(all, &mut instances));
+        // Setting a tile to what it already is doesn't count
+        map.set_tile_id(Vec2i(1, 1), 0);
+        assert!(!map.update_tilemap_instances(all, &mut instances));
+
+        map.set_tile_id(Vec2i(1, 1), 1);
+        assert!(map.update_tilemap_instances(all, &mut instances));
+        assert!(!map.update_tilemap_instances(all, &mut instances));
+
+        map.position = Vec2i(16, 0);
+        assert!(map.update_tilemap_instances(all, &mut instances));
+        assert_eq!(instances[0].pos, [16.0, 0.0, 0.0]);
+        assert_eq!(instances.len(), 9);
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+/// What happens when a countdown runs out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TimeUp {
+    // Survived the clock (e.g. the tag game)
+    Victory,
+    // Ran out of time (e.g. a time attack)
+    GameOver,
+}
+
+/// Which way a `Timer` runs.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TimerMode {
+    // Count up from zero forever, e.g. for a speedrun clock
+    Up,
+    // Count down from this many seconds to zero
+    Down(f64, TimeUp),
+}
+
+/// A game clock in seconds.  It advances by whatever `dt` it's given, so
+/// feed it `Time::dt` and it keeps the same pace at any step rate.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Timer {
+    pub mode: TimerMode,
+    // Seconds counted so far
+    elapsed: f64,
+    running: bool,
+}
+
+impl Timer {
+    /// A stopped timer at zero; call `start` to set it going.
+    pub fn new(mode: TimerMode) -> Self {
+        Self {
+            mode,
+            elapsed: 0.0,
+            running: false,
+        }
+    }
+    pub fn start(&mut self) {
+        self.running = true;
+    }
+    pub fn pause(&mut self) {
+        self.running = false;
+    }
+    /// Back to zero (or the full countdown), stopped.
+    pub fn reset(&mut self) {
+        self.elapsed = 0.0;
+        self.running = false;
+    }
+    pub fn running(&self) -> bool {
+        self.running
+    }
+    /// What the clock shows: seconds so far when counting up, seconds left
+    /// when counting down.
+    pub fn seconds(&self) -> f64 {
+        match self.mode {
+            TimerMode::Up => self.elapsed,
+            TimerMode::Down(from, _) => (from - self.elapsed).max(0.0),
+        }
+    }
+    /// Advance by `dt` seconds if running.  Returns what should happen on
+    /// the step a countdown reaches zero, and `None` otherwise.
+    pub fn tick(&mut self, dt: f64) -> Option<TimeUp> {
+        if !self.running {
+            return None;
+        }
+        self.elapsed += dt;
+        match self.mode {
+            TimerMode::Down(from, up) if self.elapsed >= from => {
+                self.running = false;
+                Some(up)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Something that can't happen again until `length` has passed since it
+/// last did, like a dash or a sound that shouldn't spam.  Units are up to
+/// the caller: tick by `Time::dt` for seconds or by 1.0 for frames.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cooldown {
+    pub length: f64,
+    // Time until ready again
+    left: f64,
+}
+
+impl Cooldown {
+    /// A cooldown that starts out ready.
+    pub fn new(length: f64) -> Self {
+        Self { length, left: 0.0 }
+    }
+    pub fn tick(&mut self, dt: f64) {
+        self.left = (self.left - dt).max(0.0);
+    }
+    pub fn ready(&self) -> bool {
+        self.left <= 0.0
+    }
+    /// Do the thing if it's ready, starting the cooldown over.  Returns
+    /// whether it happened.
+    pub fn trigger(&mut self) -> bool {
+        if self.ready() {
+            self.left = self.length;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Something that happens every `period`, like an enemy spawner; replaces
+/// `frame % period == 0` checks.  Units work like `Cooldown`'s.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Interval {
+    pub period: f64,
+    // Time since it last went off
+    elapsed: f64,
+}
+
+impl Interval {
+    pub fn new(period: f64) -> Self {
+        Self {
+            period,
+            elapsed: 0.0,
+        }
+    }
+    /// Advance by `dt`.  Returns how many times the interval went off, which
+    /// is more than one if `dt` is longer than `period`.
+    pub fn tick(&mut self, dt: f64) -> usize {
+        if self.period <= 0.0 {
+            return 0;
+        }
+        self.elapsed += dt;
+        let times = (self.elapsed / self.period).floor();
+        self.elapsed -= times * self.period;
+        times as usize
+    }
+    /// Start the period over, e.g. after doing the thing early.
+    pub fn reset(&mut self) {
+        self.elapsed = 0.0;
+    }
+}
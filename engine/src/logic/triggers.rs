@@ -0,0 +1,84 @@
+use crate::logic::types::Rect;
+
+/// A non-solid region that fires when the player enters it.  Games decide
+/// what an `id` means (checkpoint, level exit, ...).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Trigger {
+    pub rect: Rect,
+    pub id: usize,
+    // Only fire the first time the player enters
+    pub once: bool,
+}
+
+/// Enter/exit edges found by `Triggers::update`, as trigger ids.
+pub struct TriggerEvents {
+    pub entered: Vec<usize>,
+    pub exited: Vec<usize>,
+}
+
+impl TriggerEvents {
+    pub fn new() -> Self {
+        Self {
+            entered: vec![],
+            exited: vec![],
+        }
+    }
+    fn clear(&mut self) {
+        self.entered.clear();
+        self.exited.clear();
+    }
+}
+
+impl Default for TriggerEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    // Was the player inside each trigger last frame?
+    inside: Vec<bool>,
+    // Has each trigger fired at least once?
+    fired: Vec<bool>,
+}
+
+impl Triggers {
+    pub fn new(triggers: impl IntoIterator<Item = Trigger>) -> Self {
+        let triggers: Vec<Trigger> = triggers.into_iter().collect();
+        Self {
+            inside: vec![false; triggers.len()],
+            fired: vec![false; triggers.len()],
+            triggers,
+        }
+    }
+    pub fn triggers(&self) -> &[Trigger] {
+        &self.triggers
+    }
+    /// Forget which triggers have fired, e.g. when restarting a level.
+    pub fn reset(&mut self) {
+        self.inside.iter_mut().for_each(|i| *i = false);
+        self.fired.iter_mut().for_each(|f| *f = false);
+    }
+    /// Call once per frame with the player's rect.  Fills `into` with the
+    /// ids of triggers the player entered or left since the last call;
+    /// standing inside a trigger doesn't fire it again.
+    pub fn update(&mut self, player: Rect, into: &mut TriggerEvents) {
+        into.clear();
+        for (ti, t) in self.triggers.iter().enumerate() {
+            let now_inside = t.rect.touches(&player);
+            let was_inside = self.inside[ti];
+            self.inside[ti] = now_inside;
+            if now_inside && !was_inside {
+                if t.once && self.fired[ti] {
+                    continue;
+                }
+                self.fired[ti] = true;
+                into.entered.push(t.id);
+            } else if !now_inside && was_inside {
+                into.exited.push(t.id);
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 
 use super::geom::*;
 use crate::graphics::gpu::InstanceRaw;
 
-const DT: f32 = 1.0 / 60.0;
+/// Length of a step of the 3D marble sim, in seconds.
+pub const DT: f32 = 1.0 / 60.0;
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
 pub struct Rect {
@@ -12,6 +14,43 @@ pub struct Rect {
     pub w: u16,
     pub h: u16,
 }
+
+impl Rect {
+    /// Do `self` and `other` touch?  Bounds are inclusive: a rect covers
+    /// `x..=x+w` and `y..=y+h`, so two rects that merely share an edge
+    /// (or a corner) count as touching.
+    pub fn touches(&self, other: &Rect) -> bool {
+        // self left is left of other right
+        self.x <= other.x + other.w as i32
+            // other left is left of self right
+            && other.x <= self.x + self.w as i32
+            // those two conditions handle the x axis overlap;
+            // the next two do the same for the y axis:
+            && self.y <= other.y + other.h as i32
+            && other.y <= self.y + self.h as i32
+    }
+    /// Like `touches`, but with exclusive bounds: rects that only share an
+    /// edge or a corner do not overlap.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w as i32
+            && other.x < self.x + self.w as i32
+            && self.y < other.y + other.h as i32
+            && other.y < self.y + self.h as i32
+    }
+    /// How far do `self` and `other` overlap on each axis?  Returns `None`
+    /// if they don't touch.  Uses the same inclusive bounds as `touches`,
+    /// so edge-touching rects give `Some(Vec2i(0, _))` or `Some(Vec2i(_, 0))`.
+    pub fn displacement(&self, other: &Rect) -> Option<Vec2i> {
+        let x_overlap = (self.x + self.w as i32).min(other.x + other.w as i32) - self.x.max(other.x);
+        let y_overlap = (self.y + self.h as i32).min(other.y + other.h as i32) - self.y.max(other.y);
+        if x_overlap >= 0 && y_overlap >= 0 {
+            Some(Vec2i(x_overlap, y_overlap))
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Marble {
     pub body: Sphere,
@@ -25,7 +64,8 @@ impl Marble {
                 .into(),
         }
     }
-    fn update(&mut self, g: f32) {
+    /// Fall under gravity `g` for a step of `DT`.
+    pub fn update(&mut self, g: f32) {
         self.velocity += Vec3::new(0.0, -g * (1.0 + (self.body.r - 0.1) * 0.5), 0.0) * DT;
         self.body.c += self.velocity * DT;
     }
@@ -39,13 +79,19 @@ impl Marble {
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Wall {
     pub body: Plane,
-    pub distructable: bool,
+    // Can a fast enough hit knock this wall out?
+    pub destructible: bool,
+    // Knocked out: no longer collides or gets drawn
+    pub destroyed: bool,
 }
 
 impl Wall {
+    /// Turn the wall model to face along the plane's normal, then move it
+    /// out to where the plane is (the points where `p . n == d`).
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            model: (Mat4::from(cgmath::Quaternion::between_vectors(
+            model: (Mat4::from_translation(self.body.n * self.body.d)
+                * Mat4::from(cgmath::Quaternion::between_vectors(
                 Vec3::new(0.0, 1.0, 0.0),
                 self.body.n,
             )) * Mat4::from_translation(Vec3::new(0.0, -0.025, 0.0))
@@ -55,7 +101,7 @@ impl Wall {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash, Debug, Default, Serialize, Deserialize)]
 pub struct Vec2i(pub i32, pub i32);
 
 #[derive(PartialEq, Eq, Clone, Copy, Hash, Debug)]
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::logic::state::Inputs;
+
+// Every packet starts with this so stray traffic gets ignored
+const MAGIC: [u8; 2] = *b"LS";
+// magic, seed, first frame, frame count
+const HEADER_LEN: usize = 2 + 8 + 4 + 1;
+// How many of our latest frames go in every packet.  UDP can drop packets,
+// so each frame gets sent this many times before we stop repeating it.
+const REDUNDANCY: u32 = 8;
+// Frames of input delay; hides round trips shorter than this many frames
+pub const DEFAULT_DELAY: u32 = 3;
+
+/// Two-player lockstep over UDP.  Each side sends its inputs every frame,
+/// and a frame only runs once both players' inputs for it are in, so both
+/// machines feed the same inputs to the same deterministic simulation
+/// (`logic::sim::Sim`) from the same seed and stay in sync without ever
+/// sending game state.
+///
+/// Each frame: `send` the local inputs, `poll`, then `step` the game with
+/// whatever `next_frame` returns.  If it returns `None`, the other side
+/// hasn't caught up yet; skip simulating this frame and try again.
+pub struct Lockstep {
+    socket: UdpSocket,
+    remote: SocketAddr,
+    seed: u64,
+    // Is the local player player one?  Both sides have to agree.
+    host: bool,
+    delay: u32,
+    // Next frame to simulate
+    frame: u32,
+    local: BTreeMap<u32, Inputs>,
+    remote_inputs: BTreeMap<u32, Inputs>,
+}
+
+impl Lockstep {
+    /// Bind `local` and talk to `remote`.  The two sides must use the same
+    /// `seed` and opposite values of `host`.
+    pub fn connect(
+        local: impl ToSocketAddrs,
+        remote: impl ToSocketAddrs,
+        seed: u64,
+        host: bool,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        let remote = remote
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No remote address"))?;
+        let mut lockstep = Self {
+            socket,
+            remote,
+            seed,
+            host,
+            delay: DEFAULT_DELAY,
+            frame: 0,
+            local: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+        };
+        // Nobody presses anything during the delay at the start
+        for f in 0..lockstep.delay {
+            lockstep.local.insert(f, Inputs::new());
+            lockstep.remote_inputs.insert(f, Inputs::new());
+        }
+        Ok(lockstep)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Queue this frame's local inputs (they'll apply `delay` frames from
+    /// now) and send the latest few frames to the other side.
+    pub fn send(&mut self, inputs: Inputs) -> io::Result<()> {
+        let target = self.frame + self.delay;
+        self.local.entry(target).or_insert(inputs);
+        let last = *self.local.keys().next_back().unwrap();
+        // Queued frames have no gaps, so start at the oldest one still in
+        // the window; the receiver counts frames up from `first`
+        let (&first, _) = self
+            .local
+            .range(last.saturating_sub(REDUNDANCY - 1)..)
+            .next()
+            .unwrap();
+        let mut packet = Vec::with_capacity(HEADER_LEN + REDUNDANCY as usize);
+        packet.extend_from_slice(&MAGIC);
+        packet.extend_from_slice(&self.seed.to_le_bytes());
+        packet.extend_from_slice(&first.to_le_bytes());
+        let frames: Vec<u8> = self
+            .local
+            .range(first..=last)
+            .map(|(_, inputs)| inputs.to_bits())
+            .collect();
+        packet.push(frames.len() as u8);
+        packet.extend_from_slice(&frames);
+        match self.socket.send_to(&packet, self.remote) {
+            Ok(_) => Ok(()),
+            // Nothing listening yet; keep going and resend next frame
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read everything the other side has sent so far.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let mut buf = [0; 256];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(got) => got,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if from != self.remote {
+                continue;
+            }
+            self.receive(&buf[..len]);
+        }
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        if packet.len() < HEADER_LEN || packet[0..2] != MAGIC {
+            return;
+        }
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&packet[2..10]);
+        if u64::from_le_bytes(seed) != self.seed {
+            log::warn!("Ignoring input from a game with a different seed");
+            return;
+        }
+        let mut first = [0; 4];
+        first.copy_from_slice(&packet[10..14]);
+        let first = u32::from_le_bytes(first);
+        let count = packet[14] as usize;
+        for (i, bits) in packet[HEADER_LEN..].iter().take(count).enumerate() {
+            let f = first + i as u32;
+            // Frames we've already simulated are done with
+            if f >= self.frame {
+                self.remote_inputs
+                    .entry(f)
+                    .or_insert_with(|| Inputs::from_bits(*bits));
+            }
+        }
+    }
+
+    /// Both players' inputs for the next frame, player one first, if both
+    /// are in.  Moves on to the frame after.
+    pub fn next_frame(&mut self) -> Option<[Inputs; 2]> {
+        let local = *self.local.get(&self.frame)?;
+        let remote = *self.remote_inputs.get(&self.frame)?;
+        self.remote_inputs.remove(&self.frame);
+        // Keep a few old local frames around to resend
+        if self.frame >= REDUNDANCY {
+            self.local.remove(&(self.frame - REDUNDANCY));
+        }
+        self.frame += 1;
+        Some(if self.host {
+            [local, remote]
+        } else {
+            [remote, local]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // A port nobody's using right now, for a peer we have to name up front
+    fn free_addr() -> SocketAddr {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    // Something different on every frame, so frames can't get mixed up
+    fn inputs_for(frame: u32, host: bool) -> Inputs {
+        Inputs::from_bits((frame as u8 % 16) << if host { 0 } else { 3 })
+    }
+
+    #[test]
+    fn header_starts_at_the_first_queued_frame() {
+        let peer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut lockstep =
+            Lockstep::connect("127.0.0.1:0", peer.local_addr().unwrap(), 7, true).unwrap();
+        let right = Inputs::from_bits(1 << 3);
+        lockstep.send(right).unwrap();
+        let mut buf = [0; 256];
+        let (len, _) = peer.recv_from(&mut buf).unwrap();
+        let packet = &buf[..len];
+        assert_eq!(packet[0..2], MAGIC);
+        assert_eq!(
+            u32::from_le_bytes([packet[10], packet[11], packet[12], packet[13]]),
+            0
+        );
+        // The empty frames covering the delay, then ours
+        let count = packet[14] as usize;
+        assert_eq!(count, DEFAULT_DELAY as usize + 1);
+        assert_eq!(packet[HEADER_LEN + count - 1], right.to_bits());
+    }
+
+    // One time around the game loop for one side
+    fn tick(side: &mut Lockstep, frames: &mut Vec<[Inputs; 2]>) {
+        let inputs = inputs_for(side.frame() + side.delay, side.host);
+        side.send(inputs).unwrap();
+        side.poll().unwrap();
+        if let Some(both) = side.next_frame() {
+            frames.push(both);
+        }
+    }
+
+    #[test]
+    fn loopback_peers_run_the_same_frames() {
+        let (a, b) = (free_addr(), free_addr());
+        let mut host = Lockstep::connect(a, b, 7, true).unwrap();
+        let mut guest = Lockstep::connect(b, a, 7, false).unwrap();
+        let (mut host_frames, mut guest_frames) = (vec![], vec![]);
+        // Each side sends once per frame it gets to run, like the game loop
+        for _ in 0..10_000 {
+            if host_frames.len() >= 40 && guest_frames.len() >= 40 {
+                break;
+            }
+            tick(&mut host, &mut host_frames);
+            tick(&mut guest, &mut guest_frames);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(host_frames.len() >= 40 && guest_frames.len() >= 40);
+        for f in 0..40 {
+            let expected = if f < DEFAULT_DELAY {
+                [Inputs::new(), Inputs::new()]
+            } else {
+                [inputs_for(f, true), inputs_for(f, false)]
+            };
+            assert_eq!(host_frames[f as usize], expected);
+            assert_eq!(guest_frames[f as usize], expected);
+        }
+    }
+}
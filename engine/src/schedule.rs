@@ -0,0 +1,135 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How `run` decides how many simulation steps to take each time around
+/// the event loop.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StepMode {
+    /// Keep up with the wall clock: take as many fixed-length steps as the
+    /// time since the last loop covers.  Smooth on one machine, but how many
+    /// steps share an input snapshot depends on render timing, so two
+    /// machines given the same inputs can drift apart.
+    CatchUp,
+    /// Take exactly this many steps per loop, each with freshly read input,
+    /// whatever the clock says.  The simulation only ever sees its inputs
+    /// and its step count, so the same inputs give the same state anywhere;
+    /// use this for lockstep networking and replays.
+    Fixed(usize),
+}
+
+/// Turns elapsed real time into simulation steps for a `StepMode`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Scheduler {
+    mode: StepMode,
+    // Length of one simulation step, in seconds
+    dt: f64,
+    // Time not yet simulated, in seconds (catch-up mode only)
+    available: f64,
+    // Most steps catch-up mode will take at once.  After a long stall
+    // (dragging the window, a breakpoint) we drop the backlog instead of
+    // fast-forwarding through it.
+    pub max_steps: usize,
+}
+
+impl Scheduler {
+    pub fn new(mode: StepMode, dt: f64) -> Self {
+        Self {
+            mode,
+            dt,
+            available: 0.0,
+            max_steps: 8,
+        }
+    }
+
+    pub fn mode(&self) -> StepMode {
+        self.mode
+    }
+
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    /// How many steps to simulate now, given `elapsed` seconds since the
+    /// last call.
+    pub fn steps(&mut self, elapsed: f64) -> usize {
+        match self.mode {
+            StepMode::Fixed(n) => n,
+            StepMode::CatchUp => {
+                self.available += elapsed;
+                let steps = (self.available / self.dt) as usize;
+                self.available -= steps as f64 * self.dt;
+                if steps > self.max_steps {
+                    self.available = 0.0;
+                    self.max_steps
+                } else {
+                    steps
+                }
+            }
+        }
+    }
+}
+
+/// Simulation time, handed to `update` every step.  `elapsed` counts
+/// simulated seconds (steps times `dt`), not wall-clock time, so timers
+/// built on it behave the same whatever the step rate or render speed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Time {
+    // Steps simulated before this one
+    pub frame: usize,
+    // Seconds simulated before this step
+    pub elapsed: f64,
+    // Length of this step in seconds
+    pub dt: f64,
+}
+
+impl Time {
+    pub fn new(frame: usize, dt: f64) -> Self {
+        Self {
+            frame,
+            elapsed: frame as f64 * dt,
+            dt,
+        }
+    }
+    /// True on the one step in each `period` seconds where a multiple of
+    /// `period` is crossed, e.g. `time.every(20.0)` to spawn a wave every
+    /// twenty seconds at any step rate.
+    pub fn every(&self, period: f64) -> bool {
+        let before = (self.elapsed / period).floor();
+        let after = ((self.elapsed + self.dt) / period).floor();
+        after > before || self.frame == 0
+    }
+}
+
+/// Caps how often `run` goes around the loop by sleeping off whatever is
+/// left of each frame's time budget.  With vsync (`PresentMode::Fifo`) the
+/// swap chain already does this; without it, this keeps the game from
+/// spinning the CPU and GPU flat out.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameLimiter {
+    // Shortest time a frame may take; `None` for no cap
+    budget: Option<Duration>,
+    last: Instant,
+}
+
+impl FrameLimiter {
+    /// At most `max_fps` frames a second, or uncapped for `None` (or a
+    /// rate that isn't positive).
+    pub fn new(max_fps: Option<f64>) -> Self {
+        Self {
+            budget: max_fps
+                .filter(|fps| *fps > 0.0)
+                .map(|fps| Duration::from_secs_f64(1.0 / fps)),
+            last: Instant::now(),
+        }
+    }
+    /// Sleep until a frame's budget has passed since the last call.
+    pub fn wait(&mut self) {
+        if let Some(budget) = self.budget {
+            let spent = self.last.elapsed();
+            if spent < budget {
+                thread::sleep(budget - spent);
+            }
+        }
+        self.last = Instant::now();
+    }
+}
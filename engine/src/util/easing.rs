@@ -0,0 +1,184 @@
+use std::f32::consts::PI;
+
+use crate::logic::types::Vec2i;
+
+/// An easing curve: maps `t` in [0, 1] to how far along a change is,
+/// starting at 0 and ending at 1.  Elastic and bounce curves overshoot in
+/// between.
+pub type Ease = fn(f32) -> f32;
+
+pub fn linear(t: f32) -> f32 {
+    t
+}
+/// Hermite ease in and out, gentler than `cubic_in_out`.
+pub fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+pub fn quad_in(t: f32) -> f32 {
+    t * t
+}
+pub fn quad_out(t: f32) -> f32 {
+    1.0 - quad_in(1.0 - t)
+}
+pub fn quad_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        2.0 * t * t
+    } else {
+        1.0 - 2.0 * (1.0 - t) * (1.0 - t)
+    }
+}
+pub fn cubic_in(t: f32) -> f32 {
+    t * t * t
+}
+pub fn cubic_out(t: f32) -> f32 {
+    1.0 - cubic_in(1.0 - t)
+}
+pub fn cubic_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - 4.0 * (1.0 - t).powi(3)
+    }
+}
+/// Overshoots and wobbles into place like a spring.
+pub fn elastic_out(t: f32) -> f32 {
+    if t <= 0.0 || t >= 1.0 {
+        return t.max(0.0).min(1.0);
+    }
+    2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+}
+pub fn elastic_in(t: f32) -> f32 {
+    1.0 - elastic_out(1.0 - t)
+}
+/// Lands and bounces a few times, smaller each time.
+pub fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let t = t - 1.5 / D;
+        N * t * t + 0.75
+    } else if t < 2.5 / D {
+        let t = t - 2.25 / D;
+        N * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / D;
+        N * t * t + 0.984375
+    }
+}
+pub fn bounce_in(t: f32) -> f32 {
+    1.0 - bounce_out(1.0 - t)
+}
+
+/// Values a `Tween` can move between.
+pub trait Lerp: Copy {
+    /// `a` at `t` = 0, `b` at `t` = 1.
+    fn lerp(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(a: f32, b: f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+}
+
+impl Lerp for (f32, f32) {
+    fn lerp(a: Self, b: Self, t: f32) -> Self {
+        (f32::lerp(a.0, b.0, t), f32::lerp(a.1, b.1, t))
+    }
+}
+
+impl Lerp for Vec2i {
+    fn lerp(a: Self, b: Self, t: f32) -> Self {
+        let (x, y) = <(f32, f32)>::lerp((a.0 as f32, a.1 as f32), (b.0 as f32, b.1 as f32), t);
+        Vec2i(x.round() as i32, y.round() as i32)
+    }
+}
+
+/// A value moving from `from` to `to` over `duration` along an easing
+/// curve.  Time units are the caller's: seconds or frames both work.
+#[derive(Clone, Copy, Debug)]
+pub struct Tween<T: Lerp> {
+    pub from: T,
+    pub to: T,
+    pub duration: f32,
+    pub ease: Ease,
+    elapsed: f32,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(from: T, to: T, duration: f32, ease: Ease) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            ease,
+            elapsed: 0.0,
+        }
+    }
+    /// Advance by `dt` and return the new value.
+    pub fn tick(&mut self, dt: f32) -> T {
+        self.elapsed = (self.elapsed + dt).min(self.duration);
+        self.value()
+    }
+    pub fn value(&self) -> T {
+        let t = if self.duration > 0.0 {
+            self.elapsed / self.duration
+        } else {
+            1.0
+        };
+        T::lerp(self.from, self.to, (self.ease)(t))
+    }
+    pub fn done(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [(&str, Ease); 12] = [
+        ("linear", linear),
+        ("smoothstep", smoothstep),
+        ("quad_in", quad_in),
+        ("quad_out", quad_out),
+        ("quad_in_out", quad_in_out),
+        ("cubic_in", cubic_in),
+        ("cubic_out", cubic_out),
+        ("cubic_in_out", cubic_in_out),
+        ("elastic_in", elastic_in),
+        ("elastic_out", elastic_out),
+        ("bounce_in", bounce_in),
+        ("bounce_out", bounce_out),
+    ];
+
+    #[test]
+    fn curves_start_at_0_and_end_at_1() {
+        for (name, ease) in CURVES.iter() {
+            assert!(ease(0.0).abs() < 1e-5, "{}(0) = {}", name, ease(0.0));
+            assert!((ease(1.0) - 1.0).abs() < 1e-5, "{}(1) = {}", name, ease(1.0));
+        }
+    }
+
+    #[test]
+    fn tween_stops_at_its_duration() {
+        let mut tween = Tween::new(10.0, 20.0, 4.0, linear);
+        assert_eq!(tween.value(), 10.0);
+        assert_eq!(tween.tick(1.0), 12.5);
+        assert!(!tween.done());
+        // Running over doesn't carry the value past `to`
+        assert_eq!(tween.tick(10.0), 20.0);
+        assert!(tween.done());
+        assert_eq!(tween.tick(1.0), 20.0);
+    }
+
+    #[test]
+    fn zero_length_tween_is_already_there() {
+        let mut tween = Tween::new(Vec2i(0, 0), Vec2i(8, -8), 0.0, quad_in_out);
+        assert!(tween.done());
+        assert_eq!(tween.value(), Vec2i(8, -8));
+        assert_eq!(tween.tick(0.0), Vec2i(8, -8));
+    }
+}
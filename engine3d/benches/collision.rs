@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use engine3d::collision::{self, Contact};
+use engine3d::geom::{Plane, Pos3, Sphere, Vec3};
+
+// Cheap, repeatable scatter so every run benchmarks the same layout
+fn scatter(i: usize, range: usize) -> f32 {
+    ((i * 7919 + 104_729) % range) as f32 / 10.0
+}
+
+// `n` marbles piled into a 10x5x10 box, so plenty of them touch
+fn marbles(n: usize) -> Vec<Sphere> {
+    (0..n)
+        .map(|i| Sphere {
+            c: Pos3::new(scatter(i, 100) - 5.0, scatter(i * 3, 50), scatter(i * 7, 100) - 5.0),
+            r: 0.3,
+        })
+        .collect()
+}
+
+const COUNTS: [usize; 3] = [10, 100, 1000];
+
+fn gather_contacts(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gather_contacts_aa");
+    for &n in COUNTS.iter() {
+        let bodies = marbles(n);
+        let mut contacts: Vec<Contact<usize>> = vec![];
+        group.bench_with_input(BenchmarkId::from_parameter(n), &bodies, |b, bodies| {
+            b.iter(|| {
+                contacts.clear();
+                collision::gather_contacts_aa(bodies, &mut contacts)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn restitute(c: &mut Criterion) {
+    let floor = [Plane {
+        n: Vec3::new(0.0, 1.0, 0.0),
+        d: 0.0,
+    }];
+    let mut group = c.benchmark_group("restitute");
+    for &n in COUNTS.iter() {
+        let bodies = marbles(n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &bodies, |b, bodies| {
+            b.iter(|| {
+                let mut bodies = bodies.clone();
+                let mut vels = vec![Vec3::new(0.0, -1.0, 0.0); bodies.len()];
+                let (mut mm, mut mw) = (vec![], vec![]);
+                collision::gather_contacts_aa(&bodies, &mut mm);
+                collision::gather_contacts_ab(&bodies, &floor, &mut mw);
+                collision::restitute_dyn_stat(&mut bodies, &mut vels, &floor, &mut mw, 0.5);
+                collision::restitute_dyns(&mut bodies, &mut vels, &mut mm, 0.5);
+                bodies
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, gather_contacts, restitute);
+criterion_main!(benches);
@@ -10,5 +10,6 @@ fn build_shader(path_to_create: &str) -> Result<(), Box<dyn Error>> {
 fn main() -> Result<(), Box<dyn Error>> {
     build_shader("../shaders/model")?;
     build_shader("../shaders/bones")?;
+    build_shader("../shaders/post")?;
     Ok(())
 }
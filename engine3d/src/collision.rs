@@ -7,11 +7,44 @@ pub struct Contact<T: Copy> {
     pub mtv: Vec3,
 }
 
+/// The change in angular velocity from an impulse `impulse` applied at
+/// offset `arm` from a body's center of mass.  `inertia` is the body's
+/// (scalar) moment of inertia; bigger means harder to spin.  Off-center
+/// hits spin things, and a hit straight at the center doesn't.
+pub fn angular_impulse(arm: Vec3, impulse: Vec3, inertia: f32) -> Vec3 {
+    arm.cross(impulse) / inertia
+}
+
+/// The normal impulse (as a velocity change) a body resting on or pushed
+/// out of a surface gets this frame: the restitution push `mtv` plus the
+/// part of gravity `g` the surface holds up.
+pub fn normal_impulse(mtv: Vec3, n: Vec3, g: f32, dt: f32) -> f32 {
+    mtv.dot(n).max(0.0) + g * dt * n.y.max(0.0)
+}
+
+/// Slow `vel` along a surface with normal `n` by `mu` times the normal
+/// impulse `normal`, like Coulomb friction.  It can stop a sliding or
+/// rolling body but never pushes it backwards.
+pub fn apply_friction(vel: &mut Vec3, n: Vec3, normal: f32, mu: f32) {
+    let along = n * vel.dot(n);
+    let tangent = *vel - along;
+    let speed = tangent.magnitude();
+    if speed == 0.0 {
+        return;
+    }
+    let slowed = (speed - mu * normal).max(0.0);
+    *vel = along + tangent * (slowed / speed);
+}
+
+/// Push dynamic bodies out of static ones.  `restitution` is how bouncy
+/// the contacts are: 0 stops a body dead against the surface, 1 bounces
+/// it back out as fast as it went in.
 pub fn restitute_dyn_stat<S1: Shape, S2: Shape>(
     ashapes: &mut [S1],
     avels: &mut [Vec3],
     bshapes: &[S2],
     contacts: &mut [Contact<usize>],
+    restitution: f32,
 ) where
     S1: Collide<S2>,
 {
@@ -22,6 +55,14 @@ pub fn restitute_dyn_stat<S1: Shape, S2: Shape>(
         // Are they still touching?  This way we don't need to track disps or anything
         // at the expense of some extra collision checks
         if let Some(disp) = ashapes[a].disp(&bshapes[b]) {
+            // Bounce whatever velocity was heading into the surface back
+            // out, scaled by the restitution coefficient
+            if let Some(n) = unit(disp) {
+                let vn = avels[a].dot(n);
+                if vn < 0.0 {
+                    avels[a] -= n * vn * (1.0 + restitution);
+                }
+            }
             // We can imagine we're instantaneously applying a
             // velocity change to pop the object just above the floor.
             ashapes[a].translate(disp);
@@ -34,12 +75,15 @@ pub fn restitute_dyn_stat<S1: Shape, S2: Shape>(
     }
 }
 
+/// Push pairs of dynamic bodies apart, bouncing them off each other with
+/// coefficient `restitution` (0 to 1, like `restitute_dyn_stat`).
 pub fn restitute_dyn_dyn<S1: Shape, S2: Shape>(
     ashapes: &mut [S1],
     avels: &mut [Vec3],
     bshapes: &mut [S2],
     bvels: &mut [Vec3],
     contacts: &mut [Contact<usize>],
+    restitution: f32,
 ) where
     S1: Collide<S2>,
 {
@@ -52,6 +96,9 @@ pub fn restitute_dyn_dyn<S1: Shape, S2: Shape>(
         // cause issues, but those will always be hard to solve with
         // this kind of technique.
         if let Some(disp) = ashapes[a].disp(&bshapes[b]) {
+            let (va, vb) = bounce(avels[a], bvels[b], disp, restitution);
+            avels[a] = va;
+            bvels[b] = vb;
             ashapes[a].translate(-disp / 2.0);
             avels[a] -= disp / 2.0;
             bshapes[b].translate(disp / 2.0);
@@ -60,10 +107,12 @@ pub fn restitute_dyn_dyn<S1: Shape, S2: Shape>(
     }
 }
 
+/// `restitute_dyn_dyn` for bodies in the same list.
 pub fn restitute_dyns<S1: Shape>(
     ashapes: &mut [S1],
     avels: &mut [Vec3],
     contacts: &mut [Contact<usize>],
+    restitution: f32,
 ) where
     S1: Collide<S1>,
 {
@@ -76,6 +125,9 @@ pub fn restitute_dyns<S1: Shape>(
         // cause issues, but those will always be hard to solve with
         // this kind of technique.
         if let Some(disp) = ashapes[a].disp(&ashapes[b]) {
+            let (va, vb) = bounce(avels[a], avels[b], disp, restitution);
+            avels[a] = va;
+            avels[b] = vb;
             ashapes[a].translate(-disp / 2.0);
             avels[a] -= disp / 2.0;
             ashapes[b].translate(disp / 2.0);
@@ -84,6 +136,32 @@ pub fn restitute_dyns<S1: Shape>(
     }
 }
 
+fn unit(v: Vec3) -> Option<Vec3> {
+    let len = v.magnitude();
+    if len == 0.0 {
+        None
+    } else {
+        Some(v / len)
+    }
+}
+
+// Equal-mass bounce between bodies moving at `va` and `vb`, where `disp`
+// points from a towards b.  Only bodies approaching each other bounce.
+fn bounce(va: Vec3, vb: Vec3, disp: Vec3, restitution: f32) -> (Vec3, Vec3) {
+    match unit(disp) {
+        Some(n) => {
+            let closing = (vb - va).dot(n);
+            if closing < 0.0 {
+                let j = -(1.0 + restitution) * closing / 2.0;
+                (va - n * j, vb + n * j)
+            } else {
+                (va, vb)
+            }
+        }
+        None => (va, vb),
+    }
+}
+
 pub fn gather_contacts_ab<S1: Shape, S2: Shape>(a: &[S1], b: &[S2], into: &mut Vec<Contact<usize>>)
 where
     S1: Collide<S2>,
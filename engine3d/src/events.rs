@@ -17,7 +17,9 @@ impl Events {
     pub(crate) fn device_event(&mut self, ev: &winit::event::DeviceEvent) {
         match ev {
             winit::event::DeviceEvent::MouseMotion { delta: (x, y) } => {
-                self.mouse_delta = (*x as f32, *y as f32)
+                // There can be several motion events a frame; add them all up
+                self.mouse_delta.0 += *x as f32;
+                self.mouse_delta.1 += *y as f32;
             }
             _ => {}
         }
@@ -5,8 +5,37 @@ pub type Vec4 = cgmath::Vector4<f32>;
 pub type Mat3 = cgmath::Matrix3<f32>;
 pub type Mat4 = cgmath::Matrix4<f32>;
 pub type Quat = cgmath::Quaternion<f32>;
+pub type Euler = cgmath::Euler<cgmath::Rad<f32>>;
 pub const PI: f32 = std::f32::consts::PI;
 
+/// The rotation that does nothing.
+pub fn quat_identity() -> Quat {
+    Quat::new(1.0, 0.0, 0.0, 0.0)
+}
+
+/// A rotation from Euler angles in radians: `pitch` around x, `yaw` around
+/// y, and `roll` around z.
+pub fn quat_euler(pitch: f32, yaw: f32, roll: f32) -> Quat {
+    Quat::from(Euler::new(
+        cgmath::Rad(pitch),
+        cgmath::Rad(yaw),
+        cgmath::Rad(roll),
+    ))
+}
+
+/// Turn `rot` by angular velocity `omega` (axis times radians per second)
+/// for `dt` seconds.  This rotates by the exact angle rather than adding
+/// the quaternion derivative, and renormalizes, so many small steps don't
+/// drift away from a unit quaternion and skew whatever it's applied to.
+pub fn integrate_rotation(rot: Quat, omega: Vec3, dt: f32) -> Quat {
+    let speed = omega.magnitude();
+    if speed == 0.0 {
+        return rot;
+    }
+    let step = Quat::from_axis_angle(omega / speed, cgmath::Rad(speed * dt));
+    (step * rot).normalize()
+}
+
 pub trait Shape {
     fn translate(&mut self, v: Vec3);
 }
@@ -23,6 +52,46 @@ impl Shape for Sphere {
     }
 }
 
+/// A sphere swept along the segment from `a` to `b`: a pill shape.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Capsule {
+    pub a: Pos3,
+    pub b: Pos3,
+    pub r: f32,
+}
+
+impl Capsule {
+    /// An upright capsule centered at `c`, `height` tall including its caps.
+    pub fn upright(c: Pos3, height: f32, r: f32) -> Self {
+        let half = Vec3::new(0.0, (height / 2.0 - r).max(0.0), 0.0);
+        Self {
+            a: c - half,
+            b: c + half,
+            r,
+        }
+    }
+    pub fn center(&self) -> Pos3 {
+        self.a.midpoint(self.b)
+    }
+    /// The point on the capsule's segment closest to `p`.
+    pub fn closest_point(&self, p: Pos3) -> Pos3 {
+        let ab = self.b - self.a;
+        let len2 = ab.magnitude2();
+        if len2 == 0.0 {
+            return self.a;
+        }
+        let t = ((p - self.a).dot(ab) / len2).max(0.0).min(1.0);
+        self.a + ab * t
+    }
+}
+
+impl Shape for Capsule {
+    fn translate(&mut self, v: Vec3) {
+        self.a += v;
+        self.b += v;
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Plane {
     pub n: Vec3,
@@ -121,6 +190,44 @@ impl Collide<Plane> for Sphere {
     }
 }
 
+/// What's the offset I'd need to push `s` out of capsule `c`?  Same
+/// direction as sphere-sphere: from the capsule towards the sphere.
+pub fn disp_sphere_capsule(s: &Sphere, c: &Capsule) -> Option<Vec3> {
+    // The capsule acts like a sphere sitting at whichever point on its
+    // segment is nearest the sphere
+    let nearest = Sphere {
+        c: c.closest_point(s.c),
+        r: c.r,
+    };
+    nearest.disp(s)
+}
+
+/// What's the offset I'd need to push capsule `c` out of plane `p`?  Same
+/// direction as sphere-plane: along the plane's normal.
+pub fn disp_capsule_plane(c: &Capsule, p: &Plane) -> Option<Vec3> {
+    // Only the end nearer the plane (or further through it) matters
+    let da = c.a.dot(p.n) - p.d;
+    let db = c.b.dot(p.n) - p.d;
+    let (near, far) = if da < db { (da, db) } else { (db, da) };
+    if near <= c.r && far >= -c.r {
+        Some(p.n * (c.r - near))
+    } else {
+        None
+    }
+}
+
+impl Collide<Sphere> for Capsule {
+    fn disp(&self, s: &Sphere) -> Option<Vec3> {
+        disp_sphere_capsule(s, self)
+    }
+}
+
+impl Collide<Plane> for Capsule {
+    fn disp(&self, p: &Plane) -> Option<Vec3> {
+        disp_capsule_plane(self, p)
+    }
+}
+
 type CastHit = Option<(Pos3, f32)>;
 
 trait Cast<S: Shape> {
@@ -211,3 +318,19 @@ impl Cast<AABB> for Ray {
         Some((self.p + self.dir * tmin, tmin))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrated_rotation_stays_unit_length() {
+        let mut rot = quat_euler(0.3, 1.2, -0.7);
+        // Spinning around a skewed axis, about three minutes at 60Hz
+        let omega = Vec3::new(1.5, -4.0, 2.5);
+        for _ in 0..10_000 {
+            rot = integrate_rotation(rot, omega, 1.0 / 60.0);
+        }
+        assert!((rot.magnitude() - 1.0).abs() < 1e-4, "{}", rot.magnitude());
+    }
+}
@@ -17,6 +17,7 @@ use render::{InstanceGroups, Render};
 pub mod assets;
 use assets::Assets;
 pub mod lights;
+mod post;
 
 pub const DT: f32 = 1.0 / 60.0;
 
@@ -32,6 +33,9 @@ pub struct Engine {
     pub assets: Assets,
     render: Render,
     pub events: Events,
+    // Does the game want the cursor grabbed and hidden for mouse-look?
+    mouse_capture: bool,
+    focused: bool,
 }
 
 impl Engine {
@@ -58,6 +62,15 @@ impl Engine {
             gltf,
         )
     }
+    /// Grab and hide the cursor so mouse movement keeps turning the camera
+    /// however far it goes, or let it go again.  The cursor is always let
+    /// go while the window is out of focus and grabbed again on return.
+    pub fn set_mouse_capture(&mut self, capture: bool) {
+        self.mouse_capture = capture;
+    }
+    pub fn mouse_captured(&self) -> bool {
+        self.mouse_capture && self.focused
+    }
     pub fn camera_mut(&mut self) -> &mut camera::Camera {
         &mut self.render.camera
     }
@@ -67,6 +80,22 @@ impl Engine {
     pub fn set_lights(&mut self, lights: impl IntoIterator<Item = lights::Light>) {
         self.render.set_lights(lights.into_iter().collect());
     }
+    /// Fade distant things into `fog`, or pass `None` to turn fog off
+    /// (the default).
+    pub fn set_fog(&mut self, fog: Option<lights::Fog>) {
+        self.render.set_fog(fog);
+    }
+    /// Turn bloom on or off; it's off to start with since it costs a few
+    /// extra fullscreen passes every frame.
+    pub fn set_bloom(&mut self, enabled: bool) {
+        self.render.bloom.enabled = enabled;
+    }
+    /// Colors brighter than `threshold` (luminance, 1.0 is full white) glow,
+    /// with the glow scaled by `intensity`.
+    pub fn set_bloom_params(&mut self, threshold: f32, intensity: f32) {
+        self.render.bloom.threshold = threshold;
+        self.render.bloom.intensity = intensity;
+    }
 }
 
 pub fn run<R, G: Game<StaticData = R>>(
@@ -85,17 +114,35 @@ pub fn run<R, G: Game<StaticData = R>>(
         render,
         events,
         frame: 0,
+        mouse_capture: false,
+        focused: true,
     };
     let (mut game, rules) = G::start(&mut engine);
     // How many unsimulated frames have we saved up?
     let mut available_time: f32 = 0.0;
     let mut since = Instant::now();
+    // Whether the cursor is actually grabbed right now
+    let mut grabbed = false;
 
     event_loop.run_return(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::MainEventsCleared => window.request_redraw(),
-            Event::DeviceEvent { ref event, .. } => engine.events.device_event(event),
+            Event::MainEventsCleared => {
+                if grabbed {
+                    // Keep the cursor in the middle so it never hits the
+                    // window edge
+                    let size = window.inner_size();
+                    let center =
+                        winit::dpi::PhysicalPosition::new(size.width / 2, size.height / 2);
+                    let _ = window.set_cursor_position(center);
+                }
+                window.request_redraw()
+            }
+            // Raw mouse motion keeps coming when we're in the background, so
+            // only listen while focused
+            Event::DeviceEvent { ref event, .. } if engine.focused => {
+                engine.events.device_event(event)
+            }
             Event::WindowEvent {
                 ref event,
                 window_id,
@@ -113,6 +160,9 @@ pub fn run<R, G: Game<StaticData = R>>(
                         }
                         _ => {}
                     },
+                    WindowEvent::Focused(focused) => {
+                        engine.focused = *focused;
+                    }
                     WindowEvent::Resized(physical_size) => {
                         engine.render.resize(*physical_size);
                     }
@@ -148,5 +198,14 @@ pub fn run<R, G: Game<StaticData = R>>(
             engine.events.next_frame();
             engine.frame += 1;
         }
+        if engine.mouse_captured() != grabbed {
+            grabbed = engine.mouse_captured();
+            // Not every platform can grab the cursor; mouse-look still
+            // works there as long as it stays over the window
+            if let Err(e) = window.set_cursor_grab(grabbed) {
+                log::warn!("Couldn't grab the cursor: {}", e);
+            }
+            window.set_cursor_visible(!grabbed);
+        }
     });
 }
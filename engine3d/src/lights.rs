@@ -37,3 +37,12 @@ impl Light {
         Vec3::new(self.color[0], self.color[1], self.color[2])
     }
 }
+
+/// Distance fog: past `start` units from the camera, colors blend toward
+/// `color`, reaching it completely at `end`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fog {
+    pub color: Vec3,
+    pub start: f32,
+    pub end: f32,
+}
@@ -64,6 +64,9 @@ pub struct Material {
     pub diffuse_texture: texture::Texture,
     // pub normal_texture: texture::Texture,
     pub bind_group: wgpu::BindGroup,
+    // Alpha-blended rather than opaque or cutout; drawn after everything
+    // else, back to front
+    pub transparent: bool,
 }
 
 pub struct Mesh {
@@ -108,6 +111,10 @@ pub struct Model {
 }
 
 impl Model {
+    pub fn has_transparent(&self) -> bool {
+        self.materials.iter().any(|m| m.transparent)
+    }
+
     pub fn load_obj(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -144,6 +151,7 @@ impl Model {
                 name: mat.name,
                 diffuse_texture,
                 bind_group,
+                transparent: mat.dissolve < 1.0,
             });
         }
 
@@ -308,6 +316,7 @@ impl Model {
                     name: mat.name().unwrap_or("").to_string(),
                     diffuse_texture,
                     bind_group,
+                    transparent: mat.alpha_mode() == gltf::material::AlphaMode::Blend,
                 }
             })
             .collect();
@@ -345,6 +354,7 @@ impl Model {
                     name: "Default Material".to_string(),
                     diffuse_texture,
                     bind_group,
+                    transparent: false,
                 }
             })
         }
@@ -484,6 +494,15 @@ where
         uniforms: &'b wgpu::BindGroup,
         light: &'b wgpu::BindGroup,
     );
+    // Only the meshes whose materials are (or aren't) transparent
+    fn draw_model_instanced_filtered(
+        &mut self,
+        model: &'b Model,
+        transparent: bool,
+        instances: Range<u32>,
+        uniforms: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'a, 'b> for wgpu::RenderPass<'a>
@@ -537,4 +556,20 @@ where
             self.draw_mesh_instanced(mesh, material, instances.clone(), uniforms, light);
         }
     }
+
+    fn draw_model_instanced_filtered(
+        &mut self,
+        model: &'b Model,
+        transparent: bool,
+        instances: Range<u32>,
+        uniforms: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            let material = &model.materials[mesh.material];
+            if material.transparent == transparent {
+                self.draw_mesh_instanced(mesh, material, instances.clone(), uniforms, light);
+            }
+        }
+    }
 }
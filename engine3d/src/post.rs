@@ -0,0 +1,341 @@
+use wgpu::util::DeviceExt;
+
+/// The scene renders into this instead of the swap chain, so colors
+/// brighter than 1.0 survive until bloom picks them out.
+pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+struct Target {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    // Bind group for sampling this target in a later pass
+    bind_group: wgpu::BindGroup,
+}
+
+impl Target {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsage::RENDER_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some(label),
+        });
+        Self {
+            texture,
+            view,
+            bind_group,
+        }
+    }
+}
+
+/// A uniform buffer of bloom parameters for one pass (see shaders/post).
+struct Params {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Params {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, label: &str) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(&[0.0_f32; 4]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some(label),
+        });
+        Self { buffer, bind_group }
+    }
+}
+
+/// Post-processing that makes bright highlights glow: pick out the pixels
+/// over `threshold`, blur them at half resolution, and add them back on top
+/// of the scene.  With `enabled` off the scene is just copied to the screen.
+pub(crate) struct Bloom {
+    pub(crate) enabled: bool,
+    pub(crate) threshold: f32,
+    pub(crate) intensity: f32,
+    texture_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    scene: Target,
+    // Half-resolution ping-pong targets for the blur
+    ping: Target,
+    pong: Target,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    // Each pass gets its own parameters so writing them can't race
+    bright_params: Params,
+    blur_h_params: Params,
+    blur_v_params: Params,
+    composite_params: Params,
+}
+
+impl Bloom {
+    pub(crate) fn new(device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) -> Self {
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_texture_layout"),
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("post_params_layout"),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let module = device.create_shader_module(&wgpu::include_spirv!(env!("post_shader.spv")));
+        let pass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Composite Pipeline Layout"),
+            bind_group_layouts: &[&texture_layout, &params_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label: &str,
+                        layout: &wgpu::PipelineLayout,
+                        entry_point: &str,
+                        format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "fullscreen_vs",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[wgpu::ColorTargetState {
+                        format,
+                        alpha_blend: wgpu::BlendState::REPLACE,
+                        color_blend: wgpu::BlendState::REPLACE,
+                        write_mask: wgpu::ColorWrite::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: wgpu::CullMode::None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            })
+        };
+        let bright_pipeline = pipeline("Bloom Bright Pipeline", &pass_layout, "bright_fs", HDR_FORMAT);
+        let blur_pipeline = pipeline("Bloom Blur Pipeline", &pass_layout, "blur_fs", HDR_FORMAT);
+        let composite_pipeline = pipeline(
+            "Bloom Composite Pipeline",
+            &composite_layout,
+            "composite_fs",
+            sc_desc.format,
+        );
+        let (scene, ping, pong) = Self::targets(device, &texture_layout, &sampler, sc_desc);
+        Self {
+            enabled: false,
+            threshold: 1.0,
+            intensity: 1.0,
+            bright_params: Params::new(device, &params_layout, "bloom_bright_params"),
+            blur_h_params: Params::new(device, &params_layout, "bloom_blur_h_params"),
+            blur_v_params: Params::new(device, &params_layout, "bloom_blur_v_params"),
+            composite_params: Params::new(device, &params_layout, "bloom_composite_params"),
+            texture_layout,
+            sampler,
+            scene,
+            ping,
+            pong,
+            bright_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> (Target, Target, Target) {
+        let (w, h) = (sc_desc.width, sc_desc.height);
+        (
+            Target::new(device, layout, sampler, w, h, "hdr_scene"),
+            Target::new(device, layout, sampler, w / 2, h / 2, "bloom_ping"),
+            Target::new(device, layout, sampler, w / 2, h / 2, "bloom_pong"),
+        )
+    }
+
+    pub(crate) fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        let (scene, ping, pong) = Self::targets(device, &self.texture_layout, &self.sampler, sc_desc);
+        self.scene = scene;
+        self.ping = ping;
+        self.pong = pong;
+    }
+
+    /// Where the scene should be drawn.
+    pub(crate) fn scene_view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+
+    /// Add the bloom passes to `encoder`, ending with the final image in
+    /// `output`.
+    pub(crate) fn apply(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        output: &wgpu::TextureView,
+    ) {
+        let texel = (
+            2.0 / sc_desc.width.max(1) as f32,
+            2.0 / sc_desc.height.max(1) as f32,
+        );
+        let intensity = if self.enabled { self.intensity } else { 0.0 };
+        let write = |params: &Params, p: [f32; 4]| {
+            queue.write_buffer(&params.buffer, 0, bytemuck::cast_slice(&p));
+        };
+        write(&self.bright_params, [0.0, 0.0, self.threshold, intensity]);
+        write(&self.blur_h_params, [texel.0, 0.0, self.threshold, intensity]);
+        write(&self.blur_v_params, [0.0, texel.1, self.threshold, intensity]);
+        write(&self.composite_params, [0.0, 0.0, self.threshold, intensity]);
+
+        if self.enabled {
+            // Bright parts of the scene -> ping -> blur across -> pong -> blur down -> ping
+            Self::pass(encoder, &self.bright_pipeline, &self.ping.view, &[
+                &self.scene.bind_group,
+                &self.bright_params.bind_group,
+            ]);
+            Self::pass(encoder, &self.blur_pipeline, &self.pong.view, &[
+                &self.ping.bind_group,
+                &self.blur_h_params.bind_group,
+            ]);
+            Self::pass(encoder, &self.blur_pipeline, &self.ping.view, &[
+                &self.pong.bind_group,
+                &self.blur_v_params.bind_group,
+            ]);
+        } else {
+            // The composite still samples ping, so make sure it's not garbage
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom Clear Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &self.ping.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+        }
+        // With bloom off, intensity is 0 and this is a plain copy
+        Self::pass(encoder, &self.composite_pipeline, output, &[
+            &self.scene.bind_group,
+            &self.composite_params.bind_group,
+            &self.ping.bind_group,
+        ]);
+    }
+
+    fn pass(
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        target: &wgpu::TextureView,
+        bind_groups: &[&wgpu::BindGroup],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        for (i, bg) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(i as u32, bg, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+}
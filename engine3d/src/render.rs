@@ -2,6 +2,7 @@ use crate::anim::{self, DrawAnimated};
 use crate::assets::{Assets, ModelRef};
 use crate::camera::Camera;
 use crate::model::*;
+use crate::post::{Bloom, HDR_FORMAT};
 use crate::texture;
 use crate::Game;
 use cgmath::SquareMatrix;
@@ -21,6 +22,7 @@ pub(crate) struct Render {
     pub(crate) size: winit::dpi::PhysicalSize<u32>,
     static_render_pipeline: wgpu::RenderPipeline,
     animated_render_pipeline: wgpu::RenderPipeline,
+    transparent_render_pipeline: wgpu::RenderPipeline,
     pub(crate) texture_layout: wgpu::BindGroupLayout,
     pub(crate) camera: Camera,
     uniforms: Uniforms,
@@ -35,6 +37,7 @@ pub(crate) struct Render {
     light_bind_group: wgpu::BindGroup,
     depth_texture: texture::Texture,
     instance_groups: InstanceGroups,
+    pub(crate) bloom: Bloom,
 }
 
 impl Render {
@@ -283,7 +286,8 @@ impl Render {
                     module: &static_module,
                     entry_point: "main_fs",
                     targets: &[wgpu::ColorTargetState {
-                        format: sc_desc.format,
+                        // The scene goes to an HDR target for post-processing
+                        format: HDR_FORMAT,
                         alpha_blend: wgpu::BlendState::REPLACE,
                         color_blend: wgpu::BlendState::REPLACE,
                         write_mask: wgpu::ColorWrite::ALL,
@@ -338,7 +342,7 @@ impl Render {
                     module: &static_module,
                     entry_point: "main_fs",
                     targets: &[wgpu::ColorTargetState {
-                        format: sc_desc.format,
+                        format: HDR_FORMAT,
                         alpha_blend: wgpu::BlendState::REPLACE,
                         color_blend: wgpu::BlendState {
                             operation: wgpu::BlendOperation::Add,
@@ -373,6 +377,73 @@ impl Render {
             })
         };
 
+        let transparent_render_pipeline = {
+            let transparent_render_pipeline_layout =
+                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Transparent Render Pipeline Layout"),
+                    bind_group_layouts: &[
+                        &texture_bind_group_layout,
+                        &uniform_bind_group_layout,
+                        &light_bind_group_layout,
+                    ],
+                    push_constant_ranges: &[],
+                });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Transparent Render Pipeline"),
+                layout: Some(&transparent_render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &static_module,
+                    entry_point: "main_vs",
+                    buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &static_module,
+                    entry_point: "main_fs",
+                    targets: &[wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        // The shader writes straight (not premultiplied) alpha
+                        alpha_blend: wgpu::BlendState {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        },
+                        color_blend: wgpu::BlendState {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        },
+                        write_mask: wgpu::ColorWrite::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // See-through things should show their back faces too
+                    cull_mode: wgpu::CullMode::None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    // Test against opaque geometry, but don't hide whatever
+                    // transparent surfaces are drawn after this one
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                    clamp_depth: false,
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+            })
+        };
+
+        let bloom = Bloom::new(&device, &sc_desc);
+
         Self {
             surface,
             device,
@@ -382,6 +453,7 @@ impl Render {
             size,
             static_render_pipeline,
             animated_render_pipeline,
+            transparent_render_pipeline,
             camera,
             uniform_buffer,
             uniform_bind_group,
@@ -396,6 +468,7 @@ impl Render {
             texture_layout: texture_bind_group_layout,
             depth_texture,
             instance_groups: InstanceGroups::new(),
+            bloom,
         }
     }
 
@@ -405,6 +478,11 @@ impl Render {
             .write_buffer(&self.light_ambient_buffer, 0, bytemuck::cast_slice(&[amb]));
     }
 
+    /// Fog is written along with the camera in `update_buffers`.
+    pub(crate) fn set_fog(&mut self, fog: Option<crate::lights::Fog>) {
+        self.uniforms.set_fog(fog);
+    }
+
     pub(crate) fn set_lights(&mut self, ls: Vec<crate::lights::Light>) {
         assert!(ls.len() < LIGHT_MAX);
         self.lights = ls;
@@ -438,6 +516,7 @@ impl Render {
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
         self.depth_texture =
             texture::Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
+        self.bloom.resize(&self.device, &self.sc_desc);
     }
 
     pub(crate) fn render<R, G: Game<StaticData = R>>(
@@ -460,7 +539,7 @@ impl Render {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
+                    attachment: self.bloom.scene_view(),
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
@@ -485,8 +564,9 @@ impl Render {
             render_pass.set_pipeline(&self.static_render_pipeline);
             for (mr, (irs, buf, _cap)) in self.instance_groups.static_groups.iter() {
                 render_pass.set_vertex_buffer(1, buf.as_ref().unwrap().slice(..));
-                render_pass.draw_model_instanced(
+                render_pass.draw_model_instanced_filtered(
                     assets.get_model(*mr).unwrap(),
+                    false,
                     0..irs.len() as u32,
                     &self.uniform_bind_group,
                     &self.light_bind_group,
@@ -509,7 +589,37 @@ impl Render {
                     );
                 }
             }
+
+            // Transparent meshes go last, farthest first, so each one blends
+            // over everything behind it
+            let eye = self.camera.eye;
+            let mut transparent: Vec<(f32, ModelRef, u32)> = vec![];
+            for (mr, (irs, _buf, _cap)) in self.instance_groups.static_groups.iter() {
+                if !assets.get_model(*mr).unwrap().has_transparent() {
+                    continue;
+                }
+                for (i, ir) in irs.iter().enumerate() {
+                    let [x, y, z, _w] = ir.model[3];
+                    let dist = (eye.x - x).powi(2) + (eye.y - y).powi(2) + (eye.z - z).powi(2);
+                    transparent.push((dist, *mr, i as u32));
+                }
+            }
+            transparent.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            render_pass.set_pipeline(&self.transparent_render_pipeline);
+            for (_dist, mr, i) in transparent {
+                let (_irs, buf, _cap) = &self.instance_groups.static_groups[&mr];
+                render_pass.set_vertex_buffer(1, buf.as_ref().unwrap().slice(..));
+                render_pass.draw_model_instanced_filtered(
+                    assets.get_model(mr).unwrap(),
+                    true,
+                    i..(i + 1),
+                    &self.uniform_bind_group,
+                    &self.light_bind_group,
+                );
+            }
         }
+        self.bloom
+            .apply(&self.queue, &mut encoder, &self.sc_desc, &frame.view);
 
         self.queue.submit(std::iter::once(encoder.finish()));
 
@@ -672,6 +782,10 @@ struct Uniforms {
     view_position: [f32; 4],
     view: [[f32; 4]; 4],
     proj: [[f32; 4]; 4],
+    // rgb is the fog color; w is 1 with fog on, 0 with it off
+    fog_color: [f32; 4],
+    // x and y are the fog's start and end distances
+    fog_range: [f32; 4],
 }
 
 impl Uniforms {
@@ -680,6 +794,18 @@ impl Uniforms {
             view_position: [0.0; 4],
             view: cgmath::Matrix4::identity().into(),
             proj: cgmath::Matrix4::identity().into(),
+            fog_color: [0.0; 4],
+            fog_range: [0.0; 4],
+        }
+    }
+
+    fn set_fog(&mut self, fog: Option<crate::lights::Fog>) {
+        match fog {
+            Some(fog) => {
+                self.fog_color = [fog.color.x, fog.color.y, fog.color.z, 1.0];
+                self.fog_range = [fog.start, fog.end, 0.0, 0.0];
+            }
+            None => self.fog_color[3] = 0.0,
         }
     }
 
@@ -7,21 +7,31 @@ const G: f32 = 1.0;
 
 #[derive(Clone, Debug)]
 pub struct Player {
-    pub body: Sphere,
+    pub body: Capsule,
     pub velocity: Vec3,
     pub acc: Vec3,
     pub rot: Quat,
     pub omega: Vec3,
+    // Scales the friction of whatever surface the player is on
+    pub rolling_resistance: f32,
+    // How bouncy the player is, from 0 (not at all) to 1
+    pub restitution: f32,
 }
 
 // TODO: implement player info
 impl Player {
     const MAX_SPEED: f32 = 3.0;
+    // Most radians per second a hit can spin the player
+    const MAX_OMEGA: f32 = 6.0;
+    // How hard the player is to spin; there's no mass, so this is tuned by feel
+    const INERTIA: f32 = 0.5;
+    // How much spin is left after a frame without turning
+    const SPIN_DAMPING: f32 = 0.95;
     fn render(&self, rules: &GameData, igs: &mut InstanceGroups) {
         igs.render(
             rules.player_model,
             engine3d::render::InstanceRaw {
-                model: (Mat4::from_translation(self.body.c.to_vec() - Vec3::new(0.0, 0.2, 0.0))
+                model: (Mat4::from_translation(self.body.center().to_vec() - Vec3::new(0.0, 0.2, 0.0))
                     * Mat4::from_scale(self.body.r)
                     * Mat4::from(self.rot))
                 .into(),
@@ -33,8 +43,16 @@ impl Player {
         if self.velocity.magnitude() > Self::MAX_SPEED {
             self.velocity = self.velocity.normalize_to(Self::MAX_SPEED);
         }
-        self.body.c += self.velocity * DT;
-        self.rot += 0.5 * DT * Quat::new(0.0, self.omega.x, self.omega.y, self.omega.z) * self.rot;
+        self.body.translate(self.velocity * DT);
+        self.rot = integrate_rotation(self.rot, self.omega, DT);
+    }
+    /// Spin the player from an impulse hitting it at `point`.
+    fn hit(&mut self, point: Pos3, impulse: Vec3) {
+        let arm = point - self.body.center();
+        self.omega += collision::angular_impulse(arm, impulse, Self::INERTIA);
+        if self.omega.magnitude() > Self::MAX_OMEGA {
+            self.omega = self.omega.normalize_to(Self::MAX_OMEGA);
+        }
     }
 }
 
@@ -47,9 +65,45 @@ trait Camera {
     fn integrate(&mut self) {}
 }
 
+/// Turns mouse movement into camera rotation, smoothed out so a fast flick
+/// or a noisy mouse doesn't jitter the view.
+#[derive(Clone, Debug)]
+pub struct MouseLook {
+    // Radians per unit of mouse movement
+    pub sensitivity: f32,
+    // How much of last frame's motion carries over: 0 is raw input, and
+    // closer to 1 is smoother but laggier
+    pub smoothing: f32,
+    smoothed: (f32, f32),
+}
+
+impl MouseLook {
+    fn new() -> Self {
+        Self {
+            sensitivity: 0.01,
+            smoothing: 0.5,
+            smoothed: (0.0, 0.0),
+        }
+    }
+    /// This frame's (yaw, pitch) change in radians.
+    fn delta(&mut self, events: &engine3d::events::Events) -> (f32, f32) {
+        let (dx, dy) = events.mouse_delta();
+        let k = self.smoothing;
+        self.smoothed.0 = self.smoothed.0 * k + dx * (1.0 - k);
+        self.smoothed.1 = self.smoothed.1 * k + dy * (1.0 - k);
+        (
+            self.smoothed.0 * self.sensitivity,
+            self.smoothed.1 * self.sensitivity,
+        )
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FPCamera {
     pub pitch: f32,
+    // Pitch stays within plus or minus this many radians
+    pub pitch_limit: f32,
+    pub look: MouseLook,
     player_pos: Pos3,
     player_rot: Quat,
 }
@@ -58,26 +112,25 @@ impl Camera for FPCamera {
     fn new() -> Self {
         Self {
             pitch: 0.0,
+            // Just shy of straight up or down, where the view would flip
+            pitch_limit: PI / 2.0 - 0.05,
+            look: MouseLook::new(),
             player_pos: Pos3::new(0.0, 0.0, 0.0),
-            player_rot: Quat::new(1.0, 0.0, 0.0, 0.0),
+            player_rot: quat_identity(),
         }
     }
     fn update(&mut self, events: &engine3d::events::Events, player: &Player) {
-        let (_dx, dy) = events.mouse_delta();
-        self.pitch += dy / 100.0;
-        self.pitch = self.pitch.clamp(-PI / 4.0, PI / 4.0);
-        self.player_pos = player.body.c;
+        let (_dyaw, dpitch) = self.look.delta(events);
+        self.pitch += dpitch;
+        self.pitch = self.pitch.clamp(-self.pitch_limit, self.pitch_limit);
+        self.player_pos = player.body.center();
         self.player_rot = player.rot;
     }
     fn update_camera(&self, c: &mut engine3d::camera::Camera) {
         c.eye = self.player_pos + Vec3::new(0.0, 0.5, 0.0);
         // The camera is pointing at a point just in front of the composition of the player's rot and the camera's rot (player * cam * forward-offset)
         let rotation = self.player_rot
-            * (Quat::from(cgmath::Euler::new(
-                cgmath::Rad(self.pitch),
-                cgmath::Rad(0.0),
-                cgmath::Rad(0.0),
-            )));
+            * quat_euler(self.pitch, 0.0, 0.0);
         let offset = rotation * Vec3::unit_z();
         c.target = c.eye + offset;
     }
@@ -88,6 +141,10 @@ pub struct OrbitCamera {
     pub pitch: f32,
     pub yaw: f32,
     pub distance: f32,
+    // Pitch stays within plus or minus this many radians; yaw can go all
+    // the way around
+    pub pitch_limit: f32,
+    pub look: MouseLook,
     player_pos: Pos3,
     player_rot: Quat,
 }
@@ -98,24 +155,25 @@ impl Camera for OrbitCamera {
             pitch: 0.0,
             yaw: 0.0,
             distance: 5.0,
+            pitch_limit: PI / 3.0,
+            look: MouseLook::new(),
             player_pos: Pos3::new(0.0, 0.0, 0.0),
-            player_rot: Quat::new(1.0, 0.0, 0.0, 0.0),
+            player_rot: quat_identity(),
         }
     }
     fn update(&mut self, events: &engine3d::events::Events, player: &Player) {
-        let (dx, dy) = events.mouse_delta();
-        self.pitch += dy / 100.0;
-        self.pitch = self.pitch.clamp(-PI / 4.0, PI / 4.0);
+        let (dyaw, dpitch) = self.look.delta(events);
+        self.pitch += dpitch;
+        self.pitch = self.pitch.clamp(-self.pitch_limit, self.pitch_limit);
 
-        self.yaw += dx / 100.0;
-        self.yaw = self.yaw.clamp(-PI / 4.0, PI / 4.0);
+        self.yaw = (self.yaw + dyaw).rem_euclid(2.0 * PI);
         if events.key_pressed(KeyCode::Up) {
             self.distance -= 0.5;
         }
         if events.key_pressed(KeyCode::Down) {
             self.distance += 0.5;
         }
-        self.player_pos = player.body.c;
+        self.player_pos = player.body.center();
         self.player_rot = player.rot;
         // TODO: when player moves, slightly move yaw towards zero
     }
@@ -124,21 +182,136 @@ impl Camera for OrbitCamera {
         c.target = self.player_pos;
         // And rotated around the player's position and offset backwards
         let camera_rot = self.player_rot
-            * Quat::from(cgmath::Euler::new(
-                cgmath::Rad(self.pitch),
-                cgmath::Rad(self.yaw),
-                cgmath::Rad(0.0),
-            ));
+            * quat_euler(self.pitch, self.yaw, 0.0);
         let offset = camera_rot * Vec3::new(0.0, 0.0, -self.distance);
         c.eye = self.player_pos + offset;
         // To be fancy, we'd want to make the camera's eye to be an object in the world and whose rotation is locked to point towards the player, and whose distance from the player is locked, and so on---so we'd have player OR camera movements apply accelerations to the camera which could be "beaten" by collision.
     }
 }
 
+/// Whichever camera is in use; press C to switch between first person and
+/// orbiting.
+#[derive(Clone, Debug)]
+pub enum PlayerCamera {
+    FirstPerson(FPCamera),
+    Orbit(OrbitCamera),
+}
+
+impl PlayerCamera {
+    /// Swap to the other kind of camera, starting from the player's
+    /// current position and facing.
+    fn toggle(&mut self, events: &engine3d::events::Events, player: &Player) {
+        *self = match self {
+            PlayerCamera::FirstPerson(_) => PlayerCamera::Orbit(OrbitCamera::new()),
+            PlayerCamera::Orbit(_) => PlayerCamera::FirstPerson(FPCamera::new()),
+        };
+        self.update(events, player);
+    }
+}
+
+impl Camera for PlayerCamera {
+    fn new() -> Self {
+        PlayerCamera::Orbit(OrbitCamera::new())
+    }
+    fn update(&mut self, events: &engine3d::events::Events, player: &Player) {
+        match self {
+            PlayerCamera::FirstPerson(c) => c.update(events, player),
+            PlayerCamera::Orbit(c) => c.update(events, player),
+        }
+    }
+    fn render(&self, rules: &GameData, igs: &mut InstanceGroups) {
+        match self {
+            PlayerCamera::FirstPerson(c) => c.render(rules, igs),
+            PlayerCamera::Orbit(c) => c.render(rules, igs),
+        }
+    }
+    fn update_camera(&self, cam: &mut engine3d::camera::Camera) {
+        match self {
+            PlayerCamera::FirstPerson(c) => c.update_camera(cam),
+            PlayerCamera::Orbit(c) => c.update_camera(cam),
+        }
+    }
+    fn integrate(&mut self) {
+        match self {
+            PlayerCamera::FirstPerson(c) => c.integrate(),
+            PlayerCamera::Orbit(c) => c.integrate(),
+        }
+    }
+}
+
+/// A camera detached from the player for looking around the scene: F1
+/// toggles it, WASD flies, Space and LShift go up and down, and the mouse
+/// looks.  The player ignores input while it's on.
+#[derive(Clone, Debug)]
+pub struct DebugCamera {
+    pub pos: Pos3,
+    pub yaw: f32,
+    pub pitch: f32,
+    // Units per second
+    pub speed: f32,
+    pub look: MouseLook,
+}
+
+impl DebugCamera {
+    /// Start from wherever `c` is and whichever way it's looking, so
+    /// turning the debug camera on doesn't jump the view.
+    fn from_camera(c: &engine3d::camera::Camera) -> Self {
+        let dir = (c.target - c.eye).normalize();
+        Self {
+            pos: c.eye,
+            yaw: dir.x.atan2(dir.z),
+            pitch: -dir.y.asin(),
+            speed: 5.0,
+            look: MouseLook::new(),
+        }
+    }
+    fn rotation(&self) -> Quat {
+        quat_euler(0.0, self.yaw, 0.0) * quat_euler(self.pitch, 0.0, 0.0)
+    }
+    fn update(&mut self, events: &engine3d::events::Events) {
+        let (dyaw, dpitch) = self.look.delta(events);
+        self.yaw = (self.yaw - dyaw).rem_euclid(2.0 * PI);
+        self.pitch = (self.pitch + dpitch).clamp(-PI / 2.0 + 0.05, PI / 2.0 - 0.05);
+
+        let forward = self.rotation() * Vec3::unit_z();
+        let right = forward.cross(Vec3::unit_y()).normalize();
+        let mut dir = Vec3::zero();
+        if events.key_held(KeyCode::W) {
+            dir += forward;
+        }
+        if events.key_held(KeyCode::S) {
+            dir -= forward;
+        }
+        if events.key_held(KeyCode::D) {
+            dir += right;
+        }
+        if events.key_held(KeyCode::A) {
+            dir -= right;
+        }
+        if events.key_held(KeyCode::Space) {
+            dir += Vec3::unit_y();
+        }
+        if events.key_held(KeyCode::LShift) {
+            dir -= Vec3::unit_y();
+        }
+        if dir.magnitude2() > 0.0 {
+            self.pos += dir.normalize() * self.speed * DT;
+        }
+    }
+    fn update_camera(&self, c: &mut engine3d::camera::Camera) {
+        c.eye = self.pos;
+        c.target = self.pos + self.rotation() * Vec3::unit_z();
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Marbles {
     pub body: Vec<Sphere>,
     pub velocity: Vec<Vec3>,
+    // Scales the friction of whatever surface a marble is on
+    pub rolling_resistance: f32,
+    // How bouncy the marbles are, from 0 (not at all) to 1
+    pub restitution: f32,
 }
 
 // Ziang: I think we can base our game with marbles & boxes...
@@ -167,6 +340,10 @@ impl Marbles {
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Wall {
     pub body: Plane,
+    // Friction coefficient of this surface
+    pub friction: f32,
+    // Scales the bounciness of anything that hits this surface
+    pub restitution: f32,
     control: (i8, i8),
 }
 
@@ -175,7 +352,7 @@ impl Wall {
         igs.render(
             rules.wall_model,
             engine3d::render::InstanceRaw {
-                model: (Mat4::from(cgmath::Quaternion::between_vectors(
+                model: (Mat4::from(Quat::between_vectors(
                     Vec3::new(0.0, 1.0, 0.0),
                     self.body.n,
                 )) * Mat4::from_translation(Vec3::new(0.0, -0.025, 0.0))
@@ -213,11 +390,13 @@ impl Wall {
 
 
 // Ziang: should we allow for 
-struct Game<Cam: Camera> {
+struct Game {
     marbles: Marbles,
     wall: Wall,
     player: Player,
-    camera: Cam,
+    camera: PlayerCamera,
+    // Flying around on the debug camera instead?
+    debug_camera: Option<DebugCamera>,
     pm: Vec<collision::Contact<usize>>,
     pw: Vec<collision::Contact<usize>>,
     mm: Vec<collision::Contact<usize>>,
@@ -229,28 +408,33 @@ struct GameData {
     player_model: engine3d::assets::ModelRef,
 }
 
-impl<C: Camera> engine3d::Game for Game<C> {
+impl engine3d::Game for Game {
     type StaticData = GameData;
     fn start(engine: &mut Engine) -> (Self, Self::StaticData) {
         use rand::Rng;
+        // Both cameras are mouse-look cameras
+        engine.set_mouse_capture(true);
         let wall = Wall {
             body: Plane {
                 n: Vec3::new(0.0, 1.0, 0.0),
                 d: 0.0,
             },
+            friction: 1.0,
+            restitution: 1.0,
             control: (0, 0),
         };
         let player = Player {
-            body: Sphere {
-                c: Pos3::new(0.0, 3.0, 0.0),
-                r: 0.3,
-            },
+            // Matches the proportions of capsule.obj
+            body: Capsule::upright(Pos3::new(0.0, 3.0, 0.0), 1.2, 0.3),
             velocity: Vec3::zero(),
             acc: Vec3::zero(),
             omega: Vec3::zero(),
-            rot: Quat::new(1.0, 0.0, 0.0, 0.0),
+            rot: quat_identity(),
+            // Has to stay under 1.0 or friction beats walking
+            rolling_resistance: 0.8,
+            restitution: 0.1,
         };
-        let camera = C::new();
+        let camera = PlayerCamera::new();
         let mut rng = rand::thread_rng();
         let marbles = Marbles {
             body: (0..NUM_MARBLES)
@@ -266,6 +450,9 @@ impl<C: Camera> engine3d::Game for Game<C> {
                 })
                 .collect::<Vec<_>>(),
             velocity: vec![Vec3::zero(); NUM_MARBLES],
+            rolling_resistance: 0.3,
+            // Bouncy balls
+            restitution: 0.6,
         };
         let wall_model = engine.load_model("floor.obj");
         let marble_model = engine.load_model("sphere.obj");
@@ -277,6 +464,7 @@ impl<C: Camera> engine3d::Game for Game<C> {
                 wall,
                 player,
                 camera,
+                debug_camera: None,
                 // TODO nice this up somehow
                 mm: vec![],
                 mw: vec![],
@@ -303,32 +491,54 @@ impl<C: Camera> engine3d::Game for Game<C> {
         // TODO TODO show how spherecasting could work?  camera pseudo-entity collision check?  camera entity for real?
         // self.camera_controller.update(engine);
 
-        self.player.acc = Vec3::zero();
-        if engine.events.key_held(KeyCode::W) {
-            self.player.acc.z = 1.0;
-        } else if engine.events.key_held(KeyCode::S) {
-            self.player.acc.z = -1.0;
+        if engine.events.key_pressed(KeyCode::F1) {
+            self.debug_camera = match self.debug_camera {
+                Some(_) => None,
+                None => Some(DebugCamera::from_camera(engine.camera_mut())),
+            };
         }
 
-        if engine.events.key_held(KeyCode::A) {
-            self.player.acc.x = 1.0;
-        } else if engine.events.key_held(KeyCode::D) {
-            self.player.acc.x = -1.0;
-        }
-        if self.player.acc.magnitude2() > 1.0 {
-            self.player.acc = self.player.acc.normalize();
-        }
-
-        if engine.events.key_held(KeyCode::Q) {
-            self.player.omega = Vec3::unit_y();
-        } else if engine.events.key_held(KeyCode::E) {
-            self.player.omega = -Vec3::unit_y();
+        self.player.acc = Vec3::zero();
+        if let Some(debug) = self.debug_camera.as_mut() {
+            debug.update(&engine.events);
+            self.player.omega *= Player::SPIN_DAMPING;
         } else {
-            self.player.omega = Vec3::zero();
+            if engine.events.key_held(KeyCode::W) {
+                self.player.acc.z = 1.0;
+            } else if engine.events.key_held(KeyCode::S) {
+                self.player.acc.z = -1.0;
+            }
+
+            if engine.events.key_held(KeyCode::A) {
+                self.player.acc.x = 1.0;
+            } else if engine.events.key_held(KeyCode::D) {
+                self.player.acc.x = -1.0;
+            }
+            if self.player.acc.magnitude2() > 1.0 {
+                self.player.acc = self.player.acc.normalize();
+            }
+
+            if engine.events.key_held(KeyCode::Q) {
+                self.player.omega = Vec3::unit_y();
+            } else if engine.events.key_held(KeyCode::E) {
+                self.player.omega = -Vec3::unit_y();
+            } else {
+                // Let spin from hits die down instead of stopping dead
+                self.player.omega *= Player::SPIN_DAMPING;
+            }
         }
 
-        // orbit camera
-        self.camera.update(&engine.events, &self.player);
+        if engine.events.key_pressed(KeyCode::Tab) {
+            // Let go of the mouse (or grab it again), e.g. to use another window
+            let captured = engine.mouse_captured();
+            engine.set_mouse_capture(!captured);
+        }
+        if self.debug_camera.is_none() {
+            if engine.events.key_pressed(KeyCode::C) {
+                self.camera.toggle(&engine.events, &self.player);
+            }
+            self.camera.update(&engine.events, &self.player);
+        }
 
         self.wall.integrate();
         self.player.integrate();
@@ -359,39 +569,67 @@ impl<C: Camera> engine3d::Game for Game<C> {
         collision::gather_contacts_ab(&pb, &[self.wall.body], &mut self.pw);
         collision::gather_contacts_ab(&self.marbles.body, &[self.wall.body], &mut self.mw);
         collision::gather_contacts_aa(&self.marbles.body, &mut self.mm);
-        collision::restitute_dyn_stat(&mut pb, &mut pv, &[self.wall.body], &mut self.pw);
+        collision::restitute_dyn_stat(
+            &mut pb,
+            &mut pv,
+            &[self.wall.body],
+            &mut self.pw,
+            self.player.restitution * self.wall.restitution,
+        );
         collision::restitute_dyn_stat(
             &mut self.marbles.body,
             &mut self.marbles.velocity,
             &[self.wall.body],
             &mut self.mw,
+            self.marbles.restitution * self.wall.restitution,
         );
         collision::restitute_dyns(
             &mut self.marbles.body,
             &mut self.marbles.velocity,
             &mut self.mm,
+            self.marbles.restitution,
         );
+        for c in self.pm.iter() {
+            // Marbles hit the player's surface where it faces them, and
+            // restitution pushes the player back with half the overlap
+            let m = self.marbles.body[c.b];
+            let axis = pb[c.a].closest_point(m.c);
+            if axis == m.c {
+                continue;
+            }
+            let point = axis + (m.c - axis).normalize_to(pb[c.a].r);
+            self.player.hit(point, -c.mtv / 2.0);
+        }
         collision::restitute_dyn_dyn(
             &mut pb,
             &mut pv,
             &mut self.marbles.body,
             &mut self.marbles.velocity,
             &mut self.pm,
+            self.player.restitution * self.marbles.restitution,
         );
         self.player.body = pb[0];
         self.player.velocity = pv[0];
 
-        for collision::Contact { a: ma, .. } in self.mw.iter() {
-            // apply "friction" to marbles on the ground
-            self.marbles.velocity[*ma] *= 0.995;
+        let n = self.wall.body.n;
+        for c in self.mw.iter() {
+            // rolling friction for marbles on the ground
+            let normal = collision::normal_impulse(c.mtv, n, G, DT);
+            let mu = self.wall.friction * self.marbles.rolling_resistance;
+            collision::apply_friction(&mut self.marbles.velocity[c.a], n, normal, mu);
         }
-        for collision::Contact { a: pa, .. } in self.pw.iter() {
-            // apply "friction" to players on the ground
-            assert_eq!(*pa, 0);
-            self.player.velocity *= 0.98;
+        for c in self.pw.iter() {
+            // and for players on the ground
+            assert_eq!(c.a, 0);
+            let normal = collision::normal_impulse(c.mtv, n, G, DT);
+            let mu = self.wall.friction * self.player.rolling_resistance;
+            collision::apply_friction(&mut self.player.velocity, n, normal, mu);
         }
 
-        self.camera.update_camera(engine.camera_mut());
+        match &self.debug_camera {
+            Some(debug) => debug.update_camera(engine.camera_mut()),
+            None => self.camera.update_camera(engine.camera_mut()),
+        }
     }
 }
 
@@ -399,5 +637,5 @@ fn main() {
     env_logger::init();
     let title = env!("CARGO_PKG_NAME");
     let window = winit::window::WindowBuilder::new().with_title(title);
-    run::<GameData, Game<OrbitCamera>>(window, std::path::Path::new("content"));
+    run::<GameData, Game>(window, std::path::Path::new("content"));
 }
\ No newline at end of file
@@ -54,6 +54,10 @@ pub struct Uniforms {
     u_view_position: Vec4, // unused
     u_view: Mat4,
     u_proj: Mat4,
+    // rgb is the fog color, w is 1 if fog is on
+    fog_color: Vec4,
+    // x..y is the distance range the fog fades in over
+    fog_range: Vec4,
 }
 
 
@@ -100,6 +104,11 @@ pub fn main_fs(
     if object_color.w < 0.1 {
         discard();
     }
+    // Blend toward the fog color by view-space depth
+    let depth = -(uniforms.u_view * v_position.extend(1.0)).z;
+    let fog_span = (uniforms.fog_range.y - uniforms.fog_range.x).max(0.0001);
+    let fog = ((depth - uniforms.fog_range.x) / fog_span).max(0.0).min(1.0) * uniforms.fog_color.w;
+    let result = result * (1.0 - fog) + uniforms.fog_color.xyz() * fog;
     *output = result.extend(object_color.w);
 }
 
@@ -0,0 +1,96 @@
+#![cfg_attr(
+    target_arch = "spirv",
+    no_std,
+    feature(register_attr, lang_items),
+    register_attr(spirv)
+)]
+// HACK(eddyb) can't easily see warnings otherwise from `spirv-builder` builds.
+#![deny(warnings)]
+
+#[cfg(not(target_arch = "spirv"))]
+#[macro_use]
+pub extern crate spirv_std_macros;
+#[allow(unused_imports)]
+use glam::{Vec2, Vec3, Vec4, Vec4Swizzles};
+use spirv_std::{Image2d, Sampler};
+
+// Fullscreen passes for bloom.  `params` is the same uniform for every pass:
+// xy is one texel step along the blur direction, z is the brightness
+// threshold, and w is the bloom intensity.
+
+// Relative luminance of a linear color
+fn luminance(c: Vec3) -> f32 {
+    c.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+// One big triangle covering the screen; no vertex buffer needed, just
+// draw 0..3.
+#[spirv(vertex)]
+pub fn fullscreen_vs(
+    #[spirv(vertex_index)] vert_idx: i32,
+    #[spirv(position)] out_pos: &mut Vec4,
+    v_uv: &mut Vec2,
+) {
+    let uv = Vec2::new(((vert_idx << 1) & 2) as f32, (vert_idx & 2) as f32);
+    *v_uv = uv;
+    *out_pos = Vec4::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+// Keep only the part of each pixel that's brighter than the threshold
+#[spirv(fragment)]
+pub fn bright_fs(
+    v_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] t_src: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] s_src: &Sampler,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] params: &Vec4,
+    output: &mut Vec4,
+) {
+    let c: Vec4 = t_src.sample(*s_src, v_uv);
+    let lum = luminance(c.xyz());
+    let keep = if lum > params.z {
+        (lum - params.z) / lum
+    } else {
+        0.0
+    };
+    *output = (c.xyz() * keep).extend(1.0);
+}
+
+// One direction of a separable 9-tap gaussian blur
+#[spirv(fragment)]
+pub fn blur_fs(
+    v_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] t_src: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] s_src: &Sampler,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] params: &Vec4,
+    output: &mut Vec4,
+) {
+    let weights = [0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+    let step = params.xy();
+    let center: Vec4 = t_src.sample(*s_src, v_uv);
+    let mut sum = center.xyz() * weights[0];
+    let mut i = 1;
+    while i < 5 {
+        let off = step * i as f32;
+        let a: Vec4 = t_src.sample(*s_src, v_uv + off);
+        let b: Vec4 = t_src.sample(*s_src, v_uv - off);
+        sum += (a.xyz() + b.xyz()) * weights[i];
+        i += 1;
+    }
+    *output = sum.extend(1.0);
+}
+
+// The scene plus its blurred highlights
+#[spirv(fragment)]
+pub fn composite_fs(
+    v_uv: Vec2,
+    #[spirv(descriptor_set = 0, binding = 0)] t_scene: &Image2d,
+    #[spirv(descriptor_set = 0, binding = 1)] s_scene: &Sampler,
+    #[spirv(uniform, descriptor_set = 1, binding = 0)] params: &Vec4,
+    #[spirv(descriptor_set = 2, binding = 0)] t_bloom: &Image2d,
+    #[spirv(descriptor_set = 2, binding = 1)] s_bloom: &Sampler,
+    output: &mut Vec4,
+) {
+    let scene: Vec4 = t_scene.sample(*s_scene, v_uv);
+    let bloom: Vec4 = t_bloom.sample(*s_bloom, v_uv);
+    *output = (scene.xyz() + bloom.xyz() * params.w).extend(1.0);
+}
@@ -16,6 +16,7 @@ use spirv_std::{discard, Image2d, Sampler};
 #[spirv(fragment)]
 pub fn main_fs(
     v_tex_coords: Vec2,
+    v_tint: Vec4,
     #[spirv(descriptor_set = 0, binding = 0)] t_diffuse: &Image2d,
     #[spirv(descriptor_set = 0, binding = 1)] s_diffuse: &Sampler,
     output: &mut Vec4,
@@ -24,7 +25,8 @@ pub fn main_fs(
     if texel.w < 0.5 {
         discard();
     }
-    *output = texel;
+    // Textures are premultiplied, so tint every channel
+    *output = texel * v_tint;
 }
 
 #[spirv(vertex)]
@@ -36,11 +38,14 @@ pub fn main_vs(
     a_pos_scale: Vec2,
     a_tex_offset: Vec2,
     a_tex_scale: Vec2,
+    a_tint: Vec4,
     #[spirv(uniform, descriptor_set = 1, binding = 0)] camera_pos: &Vec2,
     #[spirv(position)] out_pos: &mut Vec4,
     v_tex_coords: &mut Vec2,
+    v_tint: &mut Vec4,
 ) {
     *v_tex_coords = (a_tex_coords * a_tex_scale) + a_tex_offset;
+    *v_tint = a_tint;
     *out_pos =
         (a_position * a_pos_scale.extend(1.0) + pos_offset - camera_pos.extend(0.0)).extend(1.0);
 }